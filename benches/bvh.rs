@@ -0,0 +1,118 @@
+//! Measures how long finding the closest hit of rays takes in a scene with
+//! a few thousand spheres, through the acceleration structures of a
+//! [`PreparedScene`] and through a linear scan over every object, like
+//! scenes used to.
+//!
+//! Run with `cargo bench --bench bvh`.
+
+use float_ord::FloatOrd;
+use pathtracer::{
+    accel::{Accel, BvhBuild},
+    common::{Color, Ray},
+    material::Simple,
+    render::{Camera, PreparedScene, Scene},
+    shape::Intersect,
+    texture::SolidColor,
+    Vec3,
+};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use std::{hint::black_box, sync::Arc, time::Instant};
+
+const SPHERES: usize = 4000;
+const RAYS: usize = 20_000;
+
+fn scene(rng: &mut SmallRng) -> Scene {
+    let material = Arc::new(Simple {
+        albedo: Box::new(SolidColor(Color::new(0.5, 0.5, 0.5))),
+        diffuse: 1.0,
+        fuzzyness: 0.0,
+    });
+
+    (0..SPHERES)
+        .fold(
+            Scene::builder().camera(Camera::new(Vec3::ZERO, Vec3::Z, 1.0, 1.0)),
+            |builder, _| {
+                let center = Vec3::new(
+                    rng.gen_range(-50.0..50.0),
+                    rng.gen_range(-50.0..50.0),
+                    rng.gen_range(-50.0..50.0),
+                );
+                builder.sphere(center, rng.gen_range(0.1..1.0), material.clone())
+            },
+        )
+        .build()
+}
+
+fn rays(rng: &mut SmallRng) -> Vec<Ray> {
+    (0..RAYS)
+        .map(|_| {
+            let origin = Vec3::new(
+                rng.gen_range(-50.0..50.0),
+                rng.gen_range(-50.0..50.0),
+                rng.gen_range(-50.0..50.0),
+            );
+            let direction = Vec3::new(
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+            );
+            Ray::new(origin, direction.normalize())
+        })
+        .collect()
+}
+
+/// Finds the closest hit of every ray with `closest` and prints how long it
+/// took. Returns the amount of nanoseconds per ray.
+fn measure(name: &str, rays: &[Ray], closest: impl Fn(Ray) -> Option<f32>) -> f64 {
+    let start = Instant::now();
+    for &ray in rays {
+        black_box(closest(black_box(ray)));
+    }
+    let elapsed = start.elapsed();
+
+    let per_ray = elapsed.as_nanos() as f64 / rays.len() as f64;
+    println!("{name:>10}: {elapsed:>10.2?} ({per_ray:.0} ns per ray)");
+    per_ray
+}
+
+/// Prepares a scene with `accel`, printing how long building it took.
+fn prepare(name: &str, rng: &mut SmallRng, accel: Accel) -> PreparedScene {
+    let scene = scene(rng);
+    let start = Instant::now();
+    let prepared = scene.build_with(accel);
+    println!("{name:>10}: built in {:.2?}", start.elapsed());
+    prepared
+}
+
+fn main() {
+    let rays = rays(&mut SmallRng::seed_from_u64(1));
+    let scenes = [
+        ("median", Accel::Bvh(BvhBuild::Median)),
+        ("sah", Accel::Bvh(BvhBuild::Sah { buckets: 12 })),
+        ("grid", Accel::Grid { resolution: 32 }),
+    ]
+    .map(|(name, accel)| {
+        let scene = prepare(name, &mut SmallRng::seed_from_u64(0), accel);
+        (name, scene)
+    });
+
+    let scene = scene(&mut SmallRng::seed_from_u64(0));
+    let linear = measure("linear", &rays, |ray| {
+        scene
+            .objects
+            .iter()
+            .filter_map(|obj| obj.shape.intersection(ray))
+            .map(|intersection| FloatOrd(intersection.t))
+            .min()
+            .map(|t| t.0)
+    });
+
+    for (name, scene) in &scenes {
+        let accelerated = measure(name, &rays, |ray| {
+            scene
+                .closest_hit(ray)
+                .map(|(_, intersection)| intersection.t)
+        });
+        println!("speedup: {:.1}x", linear / accelerated);
+    }
+}
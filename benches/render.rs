@@ -0,0 +1,136 @@
+//! Measures how long rendering a simple scene takes when its materials draw
+//! from the renderer's sampler, against seeding a new sampler from the
+//! thread-local rng on every scatter, like materials used to.
+//!
+//! Run with `cargo bench --bench render`.
+
+use pathtracer::{
+    background::Background,
+    common::{Color, Ray},
+    filter::Filter,
+    light::{Light, LightRay},
+    material::{Material, Scatter, Simple},
+    render::{Camera, PreparedScene, Renderer, Scene},
+    sampler::{Sampler, SamplerKind},
+    shape::{Intersection, Sphere},
+    texture::SolidColor,
+    tonemap::{Encoding, Exposure, ToneMap},
+    Vec3,
+};
+use picture::prelude::{ImgBuf, RGB};
+use rand::Rng;
+use std::{sync::Arc, time::Instant};
+
+/// A [`Simple`] material which ignores the sampler of the renderer, seeding
+/// a new one from the thread-local rng on every scatter instead.
+struct Reseeded(Simple);
+
+impl Material for Reseeded {
+    fn scatter(&self, ray: Ray, intersection: &Intersection, _: &mut Sampler) -> Option<Scatter> {
+        let mut sampler = Sampler::new(SamplerKind::Independent, rand::thread_rng().gen(), 0);
+        self.0.scatter(ray, intersection, &mut sampler)
+    }
+
+    fn pdf(&self, incoming: Vec3, outgoing: Vec3, normal: Vec3) -> f32 {
+        self.0.pdf(incoming, outgoing, normal)
+    }
+
+    fn color(&self, intersection: &Intersection) -> Color {
+        self.0.color(intersection)
+    }
+}
+
+fn simple(color: Color, diffuse: f32) -> Simple {
+    Simple {
+        albedo: Box::new(SolidColor(color)),
+        diffuse,
+        fuzzyness: 0.1,
+    }
+}
+
+/// A floor with two spheres on it, lit by a spherical light, with materials
+/// made by `material`.
+fn scene(material: impl Fn(Simple) -> Arc<dyn Material>) -> PreparedScene {
+    Scene::builder()
+        .camera(Camera::new(
+            Vec3::new(0.0, 1.0, -6.0),
+            Vec3::new(0.0, -0.1, 1.0).normalize(),
+            60f32.to_radians(),
+            1.0,
+        ))
+        .plane(
+            Vec3::new(0.0, -1.0, 0.0),
+            Vec3::Y,
+            material(simple(Color::new(0.8, 0.8, 0.8), 1.0)),
+        )
+        .sphere(
+            Vec3::new(-1.2, 0.0, 0.0),
+            1.0,
+            material(simple(Color::new(0.9, 0.5, 0.3), 1.0)),
+        )
+        .sphere(
+            Vec3::new(1.2, 0.0, 0.0),
+            1.0,
+            material(simple(Color::new(1.0, 1.0, 1.0), 0.0)),
+        )
+        .add_light(Light::new(
+            Sphere {
+                center: Vec3::new(0.0, 4.0, 0.0),
+                radius: 1.0,
+            }
+            .into(),
+            Color::new(1.0, 1.0, 1.0),
+            4.0,
+        ))
+        .build()
+        .build()
+}
+
+/// Renders `scene` and prints how long it took. Returns the amount of
+/// seconds it took.
+fn measure(name: &str, renderer: &Renderer, scene: &PreparedScene) -> f64 {
+    let mut buffer = ImgBuf::<RGB<f32>, Vec<_>>::new(256, 256);
+    let start = Instant::now();
+    renderer.render(scene, &mut buffer);
+    let elapsed = start.elapsed();
+
+    println!("{name:>10}: {elapsed:>10.2?}");
+    elapsed.as_secs_f64()
+}
+
+fn main() {
+    let renderer = Renderer {
+        sample_count: 32,
+        indirect_count: 4,
+        exposure: Exposure::Manual { max_value: 1.0 },
+        background: Background::solid(LightRay {
+            color: Color::new(0.2, 0.3, 0.5),
+            intensity: 1.0,
+        }),
+        rr_min_depth: 2,
+        tone_map: ToneMap::Clamp,
+        exposure_compensation: 0.0,
+        sample_clamp: None,
+        indirect_clamp: None,
+        seed: 7,
+        tile_size: 16,
+        parallel: true,
+        sampler: SamplerKind::Independent,
+        stratified: false,
+        transparent_background: false,
+        region: None,
+        motion_blur: false,
+        filter: Filter::Box,
+        adaptive: None,
+        encoding: Encoding::Linear,
+    };
+
+    let shared = measure("shared", &renderer, &scene(|simple| Arc::new(simple)));
+    let reseeded = measure(
+        "reseeded",
+        &renderer,
+        &scene(|simple| Arc::new(Reseeded(simple))),
+    );
+
+    println!("speedup: {:.2}x", reseeded / shared);
+}
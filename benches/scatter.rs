@@ -0,0 +1,56 @@
+//! Measures how long a [`Simple`] material takes to scatter rays when it's
+//! given the renderer's sampler, against seeding a new sampler from the
+//! thread-local rng on every scatter, like materials used to.
+//!
+//! Run with `cargo bench --bench scatter`.
+
+use pathtracer::{
+    common::{Color, Ray},
+    material::{Material, Simple},
+    sampler::{Sampler, SamplerKind},
+    shape::Intersection,
+    texture::SolidColor,
+    Vec3,
+};
+use rand::Rng;
+use std::{hint::black_box, time::Instant};
+
+const SCATTERS: u32 = 4_000_000;
+
+/// Runs `scatter` [`SCATTERS`] times and prints how long it took. Returns
+/// the amount of nanoseconds per scatter.
+fn measure(name: &str, mut scatter: impl FnMut(u32)) -> f64 {
+    // warm up
+    (0..SCATTERS / 10).for_each(&mut scatter);
+
+    let start = Instant::now();
+    (0..SCATTERS).for_each(&mut scatter);
+    let elapsed = start.elapsed();
+
+    let per_scatter = elapsed.as_nanos() as f64 / SCATTERS as f64;
+    println!("{name:>10}: {elapsed:>10.2?} ({per_scatter:.1} ns per scatter)");
+    per_scatter
+}
+
+fn main() {
+    let material = Simple {
+        albedo: Box::new(SolidColor(Color::new(0.8, 0.8, 0.8))),
+        diffuse: 0.5,
+        fuzzyness: 0.1,
+    };
+
+    let ray = Ray::new(Vec3::new(0.0, 0.0, -2.0), Vec3::Z);
+    let intersection = Intersection::new(ray, 1.0, -Vec3::Z);
+
+    let mut sampler = Sampler::new(SamplerKind::Independent, 7, 0);
+    let shared = measure("shared", |_| {
+        black_box(material.scatter(black_box(ray), &intersection, &mut sampler));
+    });
+
+    let reseeded = measure("reseeded", |_| {
+        let mut sampler = Sampler::new(SamplerKind::Independent, rand::thread_rng().gen(), 0);
+        black_box(material.scatter(black_box(ray), &intersection, &mut sampler));
+    });
+
+    println!("speedup: {:.2}x", reseeded / shared);
+}
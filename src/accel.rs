@@ -0,0 +1,582 @@
+use crate::{common::Ray, shape::Intersection, stats, Vec3};
+use enum_dispatch::enum_dispatch;
+use glam::Affine3A;
+
+mod grid;
+
+pub use grid::Grid;
+
+/// The maximum amount of primitives in a [`Bvh`] leaf.
+const MAX_LEAF_SIZE: usize = 4;
+
+/// An axis aligned bounding box.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    /// An empty bounding box. It contains nothing, and the union of it with
+    /// any other box is the other box.
+    pub const EMPTY: Self = Self {
+        min: Vec3::splat(f32::INFINITY),
+        max: Vec3::splat(f32::NEG_INFINITY),
+    };
+
+    #[inline(always)]
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Self { min, max }
+    }
+
+    /// Returns the smallest box containing all the given points.
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Self {
+        points
+            .into_iter()
+            .fold(Self::EMPTY, |aabb, point| aabb.grow(point))
+    }
+
+    /// Returns the smallest box containing both this box and `other`.
+    #[inline(always)]
+    pub fn union(&self, other: Self) -> Self {
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    /// Returns the smallest box containing both this box and `point`.
+    #[inline(always)]
+    pub fn grow(&self, point: Vec3) -> Self {
+        Self {
+            min: self.min.min(point),
+            max: self.max.max(point),
+        }
+    }
+
+    /// Returns the box containing what's inside of both this box and
+    /// `other`. It's empty if they don't overlap.
+    #[inline(always)]
+    pub fn intersection_box(&self, other: Self) -> Self {
+        Self {
+            min: self.min.max(other.min),
+            max: self.max.min(other.max),
+        }
+    }
+
+    /// Returns the smallest box containing this box after it's transformed
+    /// by `transform`.
+    pub fn transformed(&self, transform: Affine3A) -> Self {
+        Self::from_points((0..8).map(|corner| {
+            let corner = Vec3::select(
+                glam::BVec3A::new(corner & 1 != 0, corner & 2 != 0, corner & 4 != 0),
+                self.max,
+                self.min,
+            );
+            transform.transform_point3a(corner)
+        }))
+    }
+
+    #[inline(always)]
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    #[inline(always)]
+    pub fn size(&self) -> Vec3 {
+        self.max - self.min
+    }
+
+    /// The surface area of this box. Zero if it's empty.
+    #[inline(always)]
+    pub fn surface_area(&self) -> f32 {
+        let size = self.size().max(Vec3::ZERO);
+        2.0 * (size.x * size.y + size.y * size.z + size.z * size.x)
+    }
+
+    /// Tests the box for intersection with a ray whose direction has the
+    /// given component-wise inverse. Returns the t at which the ray enters
+    /// the box (or zero if it starts inside it), as long as it's at most
+    /// `t_max`.
+    #[inline(always)]
+    pub fn intersection(&self, ray: Ray, inv_direction: Vec3, t_max: f32) -> Option<f32> {
+        let t1 = (self.min - ray.point()) * inv_direction;
+        let t2 = (self.max - ray.point()) * inv_direction;
+
+        let t_enter = t1.min(t2).max_element().max(0.0);
+        let t_exit = t1.max(t2).min_element().min(t_max);
+
+        (t_enter <= t_exit).then_some(t_enter)
+    }
+}
+
+/// Trait for shapes that can be bounded by an [`Aabb`].
+#[enum_dispatch]
+pub trait Bounded {
+    /// The bounding box of this shape, or [`None`] if it's unbounded.
+    fn aabb(&self) -> Option<Aabb>;
+}
+
+/// How a [`Bvh`] decides where to split its nodes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BvhBuild {
+    /// Splits at the median of the primitive centers along the largest
+    /// axis. Fast to build, but primitives that are unevenly distributed
+    /// lead to nodes that overlap a lot.
+    #[default]
+    Median,
+    /// Splits where the surface area heuristic estimates traversal to be the
+    /// cheapest, trying `buckets` evenly spaced positions along each axis.
+    /// Slower to build, but usually faster to traverse.
+    Sah { buckets: usize },
+}
+
+/// Which acceleration structure to build over the objects of a scene.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Accel {
+    /// A [`Bvh`], built with the given strategy.
+    Bvh(BvhBuild),
+    /// A uniform [`Grid`] with `resolution` cells along its longest axis.
+    Grid { resolution: u32 },
+}
+
+impl Default for Accel {
+    #[inline(always)]
+    fn default() -> Self {
+        Accel::Bvh(BvhBuild::default())
+    }
+}
+
+impl Accel {
+    /// Builds the acceleration structure over primitives with the given
+    /// bounding boxes.
+    pub(crate) fn build(self, bounds: &[Aabb]) -> Accelerator {
+        match self {
+            Accel::Bvh(build) => Accelerator::Bvh(Bvh::with_build(bounds, build)),
+            Accel::Grid { resolution } => Accelerator::Grid(Grid::new(bounds, resolution)),
+        }
+    }
+}
+
+/// An acceleration structure built from an [`Accel`].
+#[derive(Clone, Debug)]
+pub(crate) enum Accelerator {
+    Bvh(Bvh),
+    Grid(Grid),
+}
+
+impl Accelerator {
+    #[inline(always)]
+    pub(crate) fn bounds(&self) -> Aabb {
+        match self {
+            Accelerator::Bvh(bvh) => bvh.bounds(),
+            Accelerator::Grid(grid) => grid.bounds(),
+        }
+    }
+
+    /// See [`Bvh::closest_hit`].
+    #[inline(always)]
+    pub(crate) fn closest_hit(
+        &self,
+        ray: Ray,
+        intersect: impl FnMut(usize, Ray) -> Option<Intersection>,
+    ) -> Option<(usize, Intersection)> {
+        match self {
+            Accelerator::Bvh(bvh) => bvh.closest_hit(ray, intersect),
+            Accelerator::Grid(grid) => grid.closest_hit(ray, intersect),
+        }
+    }
+
+    /// Updates the structure after the bounding boxes of the primitives
+    /// changed. BVHs are [refit](Bvh::refit), while grids are rebuilt.
+    pub(crate) fn refit(&mut self, bounds: &[Aabb]) {
+        match self {
+            Accelerator::Bvh(bvh) => bvh.refit(bounds),
+            Accelerator::Grid(grid) => *grid = Grid::new(bounds, grid.resolution()),
+        }
+    }
+
+    /// See [`Bvh::any_hit`].
+    #[inline(always)]
+    pub(crate) fn any_hit(
+        &self,
+        ray: Ray,
+        t_max: f32,
+        hit: impl FnMut(usize, Ray) -> bool,
+    ) -> bool {
+        match self {
+            Accelerator::Bvh(bvh) => bvh.any_hit(ray, t_max, hit),
+            Accelerator::Grid(grid) => grid.any_hit(ray, t_max, hit),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Node {
+    bounds: Aabb,
+    /// For leaves, the index of the first primitive in [`Bvh::indices`].
+    /// For interior nodes, the index of the left child. The right child
+    /// always comes right after it.
+    start: u32,
+    /// The amount of primitives in this node. Zero for interior nodes.
+    count: u32,
+}
+
+/// A bounding volume hierarchy over a list of primitives, each identified by
+/// its index.
+#[derive(Clone, Debug)]
+pub struct Bvh {
+    nodes: Vec<Node>,
+    indices: Vec<usize>,
+}
+
+impl Bvh {
+    /// Builds a [`Bvh`] over primitives with the given bounding boxes,
+    /// using [`BvhBuild::Median`].
+    #[inline(always)]
+    pub fn new(bounds: &[Aabb]) -> Self {
+        Self::with_build(bounds, BvhBuild::Median)
+    }
+
+    /// Builds a [`Bvh`] over primitives with the given bounding boxes,
+    /// using the given strategy.
+    ///
+    /// # Panics
+    /// Panics if the strategy is [`BvhBuild::Sah`] with less than 2
+    /// buckets.
+    pub fn with_build(bounds: &[Aabb], build: BvhBuild) -> Self {
+        if let BvhBuild::Sah { buckets } = build {
+            assert!(buckets >= 2);
+        }
+
+        let mut bvh = Self {
+            nodes: Vec::with_capacity(2 * bounds.len()),
+            indices: (0..bounds.len()).collect(),
+        };
+
+        let centers: Vec<Vec3> = bounds.iter().map(Aabb::center).collect();
+        bvh.nodes.push(Node {
+            bounds: Aabb::EMPTY,
+            start: 0,
+            count: 0,
+        });
+        bvh.build(0, 0, bounds.len(), bounds, &centers, build);
+
+        bvh
+    }
+
+    /// Builds the node at `node` over the primitives in
+    /// `indices[start..end]`.
+    fn build(
+        &mut self,
+        node: usize,
+        start: usize,
+        end: usize,
+        bounds: &[Aabb],
+        centers: &[Vec3],
+        build: BvhBuild,
+    ) {
+        let node_bounds = self.indices[start..end]
+            .iter()
+            .fold(Aabb::EMPTY, |aabb, &index| aabb.union(bounds[index]));
+        self.nodes[node].bounds = node_bounds;
+
+        let count = end - start;
+        if count <= MAX_LEAF_SIZE {
+            self.nodes[node].start = start as u32;
+            self.nodes[node].count = count as u32;
+            return;
+        }
+
+        let center_bounds = Aabb::from_points(self.indices[start..end].iter().map(|&i| centers[i]));
+        let middle = match build {
+            BvhBuild::Sah { buckets } => {
+                self.sah_split(start, end, bounds, centers, center_bounds, buckets)
+            }
+            BvhBuild::Median => None,
+        }
+        .unwrap_or_else(|| self.median_split(start, end, centers, center_bounds));
+
+        let left = self.nodes.len();
+        self.nodes[node].start = left as u32;
+        for _ in 0..2 {
+            self.nodes.push(Node {
+                bounds: Aabb::EMPTY,
+                start: 0,
+                count: 0,
+            });
+        }
+
+        self.build(left, start, middle, bounds, centers, build);
+        self.build(left + 1, middle, end, bounds, centers, build);
+    }
+
+    /// Splits `indices[start..end]` at the median of the centers along the
+    /// largest axis, returning where the second half starts.
+    fn median_split(
+        &mut self,
+        start: usize,
+        end: usize,
+        centers: &[Vec3],
+        center_bounds: Aabb,
+    ) -> usize {
+        let size = center_bounds.size();
+        let axis = if size.x >= size.y && size.x >= size.z {
+            0
+        } else if size.y >= size.z {
+            1
+        } else {
+            2
+        };
+
+        let half = (end - start) / 2;
+        self.indices[start..end]
+            .select_nth_unstable_by(half, |&a, &b| centers[a][axis].total_cmp(&centers[b][axis]));
+
+        start + half
+    }
+
+    /// Splits `indices[start..end]` where the surface area heuristic is the
+    /// lowest, returning where the second half starts. Returns [`None`] if
+    /// the primitives can't be split into buckets, i.e. all centers are in
+    /// the same place.
+    fn sah_split(
+        &mut self,
+        start: usize,
+        end: usize,
+        bounds: &[Aabb],
+        centers: &[Vec3],
+        center_bounds: Aabb,
+        buckets: usize,
+    ) -> Option<usize> {
+        let size = center_bounds.size();
+        let bucket_of = |index: usize, axis: usize| {
+            let offset = (centers[index][axis] - center_bounds.min[axis]) / size[axis];
+            ((offset * buckets as f32) as usize).min(buckets - 1)
+        };
+
+        // (cost, axis, first bucket of the second half)
+        let mut best: Option<(f32, usize, usize)> = None;
+        let mut bucket_bounds = vec![Aabb::EMPTY; buckets];
+        let mut bucket_counts = vec![0usize; buckets];
+        let mut right_areas = vec![0.0; buckets];
+        for axis in 0..3 {
+            if size[axis] <= 0.0 {
+                continue;
+            }
+
+            bucket_bounds.fill(Aabb::EMPTY);
+            bucket_counts.fill(0);
+            for &index in &self.indices[start..end] {
+                let bucket = bucket_of(index, axis);
+                bucket_bounds[bucket] = bucket_bounds[bucket].union(bounds[index]);
+                bucket_counts[bucket] += 1;
+            }
+
+            // sweep from the right to know the area of every suffix, then
+            // from the left to evaluate every split
+            let mut right = Aabb::EMPTY;
+            for bucket in (1..buckets).rev() {
+                right = right.union(bucket_bounds[bucket]);
+                right_areas[bucket] = right.surface_area();
+            }
+
+            let mut left = Aabb::EMPTY;
+            let mut left_count = 0;
+            for split in 1..buckets {
+                left = left.union(bucket_bounds[split - 1]);
+                left_count += bucket_counts[split - 1];
+
+                let right_count = (end - start) - left_count;
+                if left_count == 0 || right_count == 0 {
+                    continue;
+                }
+
+                let cost = left.surface_area() * left_count as f32
+                    + right_areas[split] * right_count as f32;
+                if best.is_none_or(|(best_cost, ..)| cost < best_cost) {
+                    best = Some((cost, axis, split));
+                }
+            }
+        }
+
+        let (_, axis, split) = best?;
+        let mut middle = start;
+        for i in start..end {
+            if bucket_of(self.indices[i], axis) < split {
+                self.indices.swap(i, middle);
+                middle += 1;
+            }
+        }
+
+        Some(middle)
+    }
+
+    /// Updates the bounding boxes of the nodes after the ones of the
+    /// primitives changed, without changing the structure of the tree.
+    ///
+    /// This is much cheaper than building a new BVH, but the tree gets worse
+    /// the farther primitives move from where they were when it was built.
+    ///
+    /// # Panics
+    /// Panics if the amount of primitives changed.
+    pub fn refit(&mut self, bounds: &[Aabb]) {
+        assert_eq!(bounds.len(), self.indices.len());
+
+        // children always come after their parents, so going backwards
+        // updates them first
+        for node in (0..self.nodes.len()).rev() {
+            let Node { start, count, .. } = self.nodes[node];
+            let start = start as usize;
+            self.nodes[node].bounds = if count > 0 {
+                self.indices[start..start + count as usize]
+                    .iter()
+                    .fold(Aabb::EMPTY, |aabb, &index| aabb.union(bounds[index]))
+            } else if self.indices.is_empty() {
+                Aabb::EMPTY
+            } else {
+                self.nodes[start].bounds.union(self.nodes[start + 1].bounds)
+            };
+        }
+    }
+
+    /// The amount of nodes in this BVH, both interior ones and leaves.
+    #[inline(always)]
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// The bounding box of everything in this BVH.
+    #[inline(always)]
+    pub fn bounds(&self) -> Aabb {
+        self.nodes[0].bounds
+    }
+
+    /// Finds the closest intersection of `ray` with the primitives in this
+    /// BVH. `intersect` is called with the index of every primitive whose
+    /// bounding box is hit by the ray and should return its intersection
+    /// with it, if any.
+    pub fn closest_hit(
+        &self,
+        ray: Ray,
+        mut intersect: impl FnMut(usize, Ray) -> Option<Intersection>,
+    ) -> Option<(usize, Intersection)> {
+        if self.indices.is_empty() {
+            return None;
+        }
+
+        let inv_direction = ray.direction().recip();
+        let mut closest: Option<(usize, Intersection)> = None;
+        let mut t_max = f32::INFINITY;
+
+        let mut stack = Vec::with_capacity(64);
+        if self.nodes[0]
+            .bounds
+            .intersection(ray, inv_direction, t_max)
+            .is_some()
+        {
+            stack.push(0);
+        }
+
+        let mut visited = 0;
+        while let Some(node) = stack.pop() {
+            visited += 1;
+            let node = self.nodes[node];
+            if node
+                .bounds
+                .intersection(ray, inv_direction, t_max)
+                .is_none()
+            {
+                continue;
+            }
+
+            if node.count > 0 {
+                let start = node.start as usize;
+                let end = start + node.count as usize;
+                for &index in &self.indices[start..end] {
+                    if let Some(intersection) = intersect(index, ray) {
+                        if intersection.t < t_max {
+                            t_max = intersection.t;
+                            closest = Some((index, intersection));
+                        }
+                    }
+                }
+
+                continue;
+            }
+
+            // visit the closest child first
+            let left = node.start as usize;
+            let right = left + 1;
+            let t_left = self.nodes[left]
+                .bounds
+                .intersection(ray, inv_direction, t_max);
+            let t_right = self.nodes[right]
+                .bounds
+                .intersection(ray, inv_direction, t_max);
+
+            match (t_left, t_right) {
+                (Some(t_left), Some(t_right)) => {
+                    if t_left < t_right {
+                        stack.push(right);
+                        stack.push(left);
+                    } else {
+                        stack.push(left);
+                        stack.push(right);
+                    }
+                }
+                (Some(_), None) => stack.push(left),
+                (None, Some(_)) => stack.push(right),
+                (None, None) => (),
+            }
+        }
+
+        stats::count(|stats| stats.nodes_visited += visited);
+        closest
+    }
+
+    /// Whether `hit` returns true for any primitive whose bounding box is
+    /// hit by the ray before `t_max`. Stops as soon as one does.
+    pub fn any_hit(&self, ray: Ray, t_max: f32, mut hit: impl FnMut(usize, Ray) -> bool) -> bool {
+        if self.indices.is_empty() {
+            return false;
+        }
+
+        let inv_direction = ray.direction().recip();
+        let mut stack = Vec::with_capacity(64);
+        stack.push(0);
+
+        let mut visited = 0;
+        while let Some(node) = stack.pop() {
+            visited += 1;
+            let node = self.nodes[node];
+            if node
+                .bounds
+                .intersection(ray, inv_direction, t_max)
+                .is_none()
+            {
+                continue;
+            }
+
+            if node.count > 0 {
+                let start = node.start as usize;
+                let end = start + node.count as usize;
+                if self.indices[start..end]
+                    .iter()
+                    .any(|&index| hit(index, ray))
+                {
+                    stats::count(|stats| stats.nodes_visited += visited);
+                    return true;
+                }
+
+                continue;
+            }
+
+            stack.push(node.start as usize);
+            stack.push(node.start as usize + 1);
+        }
+
+        stats::count(|stats| stats.nodes_visited += visited);
+        false
+    }
+}
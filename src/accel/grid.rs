@@ -0,0 +1,248 @@
+use super::Aabb;
+use crate::{common::Ray, shape::Intersection, stats, Vec3, EPSILON};
+
+/// A uniform grid over a list of primitives, each identified by its index.
+///
+/// Every cell of the grid lists the primitives whose bounding boxes overlap
+/// it, and rays walk through the cells they cross in order. For many
+/// primitives of similar sizes, this is both faster to build and to
+/// traverse than a [`Bvh`](super::Bvh).
+#[derive(Clone, Debug)]
+pub struct Grid {
+    /// The bounding box of every primitive.
+    bounds: Aabb,
+    /// The box covered by the cells, which is `bounds` slightly enlarged
+    /// so that no axis is flat.
+    cells_bounds: Aabb,
+    cell_size: Vec3,
+    /// The amount of cells along the longest axis.
+    resolution: u32,
+    /// The amount of cells along each axis.
+    dims: [i32; 3],
+    /// The primitives of the cell at `i` are in
+    /// `indices[cell_starts[i]..cell_starts[i + 1]]`.
+    cell_starts: Vec<u32>,
+    indices: Vec<u32>,
+}
+
+impl Grid {
+    /// Builds a [`Grid`] over primitives with the given bounding boxes,
+    /// with `resolution` cells along its longest axis. Cells are cubes, so
+    /// the other axes get proportionally less.
+    ///
+    /// # Panics
+    /// Panics if `resolution` is zero.
+    pub fn new(bounds: &[Aabb], resolution: u32) -> Self {
+        assert!(resolution > 0);
+
+        let grid_bounds = bounds
+            .iter()
+            .fold(Aabb::EMPTY, |aabb, &bounds| aabb.union(bounds));
+        if bounds.is_empty() {
+            return Self {
+                bounds: grid_bounds,
+                cells_bounds: Aabb::new(Vec3::ZERO, Vec3::ONE),
+                cell_size: Vec3::ONE,
+                resolution,
+                dims: [1; 3],
+                cell_starts: vec![0, 0],
+                indices: Vec::new(),
+            };
+        }
+
+        let padding = (grid_bounds.size().max_element() * 1e-4).max(EPSILON);
+        let cells_bounds = Aabb::new(grid_bounds.min - padding, grid_bounds.max + padding);
+        let size = cells_bounds.size();
+
+        let cell_length = size.max_element() / resolution as f32;
+        let dims = (size / cell_length)
+            .ceil()
+            .clamp(Vec3::ONE, Vec3::splat(resolution as f32))
+            .to_array()
+            .map(|dim| dim as i32);
+        let cell_size = size / Vec3::new(dims[0] as f32, dims[1] as f32, dims[2] as f32);
+
+        let mut grid = Self {
+            bounds: grid_bounds,
+            cells_bounds,
+            cell_size,
+            resolution,
+            dims,
+            cell_starts: Vec::new(),
+            indices: Vec::new(),
+        };
+
+        // count the primitives of every cell, then fill them in
+        let cell_count = (dims[0] * dims[1] * dims[2]) as usize;
+        let mut counts = vec![0u32; cell_count + 1];
+        for aabb in bounds {
+            grid.for_each_cell(*aabb, |cell| counts[cell] += 1);
+        }
+
+        let mut start = 0;
+        for count in &mut counts {
+            let cell_start = start;
+            start += *count;
+            *count = cell_start;
+        }
+
+        let mut next = counts.clone();
+        let mut indices = vec![0; start as usize];
+        for (index, aabb) in bounds.iter().enumerate() {
+            grid.for_each_cell(*aabb, |cell| {
+                indices[next[cell] as usize] = index as u32;
+                next[cell] += 1;
+            });
+        }
+
+        grid.cell_starts = counts;
+        grid.indices = indices;
+        grid
+    }
+
+    /// The cell containing `point`, clamped to the grid.
+    #[inline(always)]
+    fn cell_of(&self, point: Vec3) -> [i32; 3] {
+        let cell = ((point - self.cells_bounds.min) / self.cell_size)
+            .floor()
+            .to_array();
+        [0, 1, 2].map(|axis| (cell[axis] as i32).clamp(0, self.dims[axis] - 1))
+    }
+
+    #[inline(always)]
+    fn cell_index(&self, [x, y, z]: [i32; 3]) -> usize {
+        ((z * self.dims[1] + y) * self.dims[0] + x) as usize
+    }
+
+    /// Calls `f` with the index of every cell overlapping `aabb`.
+    fn for_each_cell(&self, aabb: Aabb, mut f: impl FnMut(usize)) {
+        let [min_x, min_y, min_z] = self.cell_of(aabb.min);
+        let [max_x, max_y, max_z] = self.cell_of(aabb.max);
+        for z in min_z..=max_z {
+            for y in min_y..=max_y {
+                for x in min_x..=max_x {
+                    f(self.cell_index([x, y, z]));
+                }
+            }
+        }
+    }
+
+    /// The indices of the primitives overlapping the cell at `index`.
+    #[inline(always)]
+    fn cell(&self, index: usize) -> &[u32] {
+        let start = self.cell_starts[index] as usize;
+        let end = self.cell_starts[index + 1] as usize;
+        &self.indices[start..end]
+    }
+
+    /// The amount of cells along the longest axis of this grid.
+    #[inline(always)]
+    pub fn resolution(&self) -> u32 {
+        self.resolution
+    }
+
+    /// The bounding box of everything in this grid.
+    #[inline(always)]
+    pub fn bounds(&self) -> Aabb {
+        self.bounds
+    }
+
+    /// Walks through the cells crossed by `ray` in order, with a 3D-DDA,
+    /// calling `visit` with the primitives of every cell and the t at which
+    /// the ray leaves it. Stops when `visit` returns true.
+    fn traverse(&self, ray: Ray, t_max: f32, mut visit: impl FnMut(&[u32], f32) -> bool) {
+        if self.indices.is_empty() {
+            return;
+        }
+
+        let inv_direction = ray.direction().recip();
+        let Some(t_enter) = self.cells_bounds.intersection(ray, inv_direction, t_max) else {
+            return;
+        };
+
+        let mut cell = self.cell_of(ray.point_at_t(t_enter));
+        let mut step = [0; 3];
+        let mut t_next = [f32::INFINITY; 3];
+        let mut t_delta = [f32::INFINITY; 3];
+        for axis in 0..3 {
+            let direction = ray.direction()[axis];
+            if direction == 0.0 {
+                continue;
+            }
+
+            step[axis] = if direction > 0.0 { 1 } else { -1 };
+            let boundary_cell = cell[axis] + (direction > 0.0) as i32;
+            let boundary =
+                self.cells_bounds.min[axis] + boundary_cell as f32 * self.cell_size[axis];
+            t_next[axis] = (boundary - ray.point()[axis]) * inv_direction[axis];
+            t_delta[axis] = self.cell_size[axis] * inv_direction[axis].abs();
+        }
+
+        loop {
+            let axis = if t_next[0] < t_next[1] && t_next[0] < t_next[2] {
+                0
+            } else if t_next[1] < t_next[2] {
+                1
+            } else {
+                2
+            };
+
+            let t_exit = t_next[axis];
+            if visit(self.cell(self.cell_index(cell)), t_exit) || t_exit > t_max {
+                return;
+            }
+
+            cell[axis] += step[axis];
+            if !(0..self.dims[axis]).contains(&cell[axis]) {
+                return;
+            }
+
+            t_next[axis] += t_delta[axis];
+        }
+    }
+
+    /// Finds the closest intersection of `ray` with the primitives in this
+    /// grid. `intersect` is called with the index of every primitive in a
+    /// cell crossed by the ray (possibly more than once, if it spans
+    /// multiple cells) and should return its intersection with it, if any.
+    pub fn closest_hit(
+        &self,
+        ray: Ray,
+        mut intersect: impl FnMut(usize, Ray) -> Option<Intersection>,
+    ) -> Option<(usize, Intersection)> {
+        let mut closest: Option<(usize, Intersection)> = None;
+        let mut visited = 0;
+        self.traverse(ray, f32::INFINITY, |primitives, t_exit| {
+            visited += 1;
+            for &index in primitives {
+                if let Some(intersection) = intersect(index as usize, ray) {
+                    if closest.is_none_or(|(_, closest)| intersection.t < closest.t) {
+                        closest = Some((index as usize, intersection));
+                    }
+                }
+            }
+
+            // hits past this cell might still be beaten by hits in the next
+            // ones, so only stop once the closest is inside of it
+            closest.is_some_and(|(_, closest)| closest.t <= t_exit)
+        });
+
+        stats::count(|stats| stats.nodes_visited += visited);
+        closest
+    }
+
+    /// Whether `hit` returns true for any primitive in a cell crossed by the
+    /// ray before `t_max`. Stops as soon as one does.
+    pub fn any_hit(&self, ray: Ray, t_max: f32, mut hit: impl FnMut(usize, Ray) -> bool) -> bool {
+        let mut found = false;
+        let mut visited = 0;
+        self.traverse(ray, t_max, |primitives, _| {
+            visited += 1;
+            found = primitives.iter().any(|&index| hit(index as usize, ray));
+            found
+        });
+
+        stats::count(|stats| stats.nodes_visited += visited);
+        found
+    }
+}
@@ -0,0 +1,441 @@
+//! What rays that escape the scene without hitting anything see.
+
+use crate::{
+    distribution::Distribution, light::LightRay, render::luminance, shape::spherical_uv, Vec3,
+};
+use rand::Rng;
+
+mod hdr;
+
+pub use hdr::HdrError;
+
+/// An image of the light arriving from every direction, in the
+/// equirectangular projection: the horizontal axis goes around the Y axis,
+/// and the vertical one goes from straight up at the top to straight down at
+/// the bottom. The directions are the same as the texture coordinates of a
+/// [`Sphere`](crate::shape::Sphere), so the center of the image is in the
+/// +X direction.
+///
+/// Every direction at a pole sees the average of the closest row, so there's
+/// no seam there, nor at the vertical edges of the image, which wrap around.
+///
+/// Directions can be [sampled](EnvironmentMap::sample) by importance, so
+/// that small and bright parts of the map, like the sun, are found easily.
+pub struct EnvironmentMap {
+    width: u32,
+    height: u32,
+    /// The radiance of every pixel, row by row, starting from the top.
+    pixels: Vec<Vec3>,
+    /// The average radiance of the top and the bottom rows.
+    poles: [Vec3; 2],
+    /// The distribution of the rows of pixels directions are sampled from,
+    /// or [`None`] if the map is black.
+    rows: Option<Distribution>,
+    /// The distribution of the pixels of every row, or [`None`] for rows
+    /// that are black.
+    columns: Vec<Option<Distribution>>,
+}
+
+impl EnvironmentMap {
+    /// Creates a new [`EnvironmentMap`] from the radiance of every pixel,
+    /// row by row, starting from the top.
+    ///
+    /// # Panics
+    /// Panics if the image is empty, if there isn't exactly one pixel for
+    /// every position or if any channel is negative.
+    pub fn new(width: u32, height: u32, pixels: Vec<Vec3>) -> Self {
+        assert!(width > 0 && height > 0);
+        assert_eq!(pixels.len(), width as usize * height as usize);
+        assert!(pixels.iter().all(|pixel| pixel.min_element() >= 0.0));
+
+        let average = |row: &[Vec3]| row.iter().sum::<Vec3>() / width as f32;
+        let row_len = width as usize;
+        let poles = [
+            average(&pixels[..row_len]),
+            average(&pixels[pixels.len() - row_len..]),
+        ];
+
+        // pixels are sampled by their luminance, times the area they cover
+        // on the sphere of directions, which shrinks towards the poles
+        let columns: Vec<_> = pixels
+            .chunks_exact(row_len)
+            .enumerate()
+            .map(|(row, pixels)| {
+                let sin_theta = (std::f32::consts::PI * (row as f32 + 0.5) / height as f32).sin();
+                Distribution::new(pixels.iter().map(|&pixel| luminance(pixel) * sin_theta))
+            })
+            .collect();
+        let rows = Distribution::new(
+            columns
+                .iter()
+                .map(|columns| columns.as_ref().map_or(0.0, Distribution::total)),
+        );
+
+        Self {
+            width,
+            height,
+            pixels,
+            poles,
+            rows,
+            columns,
+        }
+    }
+
+    #[inline(always)]
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    #[inline(always)]
+    fn pixel(&self, x: i64, y: i64) -> Vec3 {
+        // wrap around horizontally, clamp vertically
+        let x = x.rem_euclid(self.width as i64) as usize;
+        let y = y.clamp(0, self.height as i64 - 1) as usize;
+        self.pixels[y * self.width as usize + x]
+    }
+
+    /// The radiance arriving from the given (normalized) direction,
+    /// bilinearly interpolated between the closest pixels.
+    pub fn radiance(&self, direction: Vec3) -> Vec3 {
+        let uv = spherical_uv(direction);
+
+        // position in pixels, relative to the pixel centers
+        let x = uv.x * self.width as f32 - 0.5;
+        let y = (1.0 - uv.y) * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let top = self.pixel(x0, y0).lerp(self.pixel(x0 + 1, y0), tx);
+        let bottom = self.pixel(x0, y0 + 1).lerp(self.pixel(x0 + 1, y0 + 1), tx);
+        let radiance = top.lerp(bottom, ty);
+
+        // past the centers of the first and last rows, fade into the
+        // radiance at the poles, which is the same from every side
+        let last_row = self.height as f32 - 1.0;
+        if y < 0.0 {
+            self.poles[0].lerp(radiance, ((y + 0.5) * 2.0).max(0.0))
+        } else if y > last_row {
+            radiance.lerp(self.poles[1], ((y - last_row) * 2.0).min(1.0))
+        } else {
+            radiance
+        }
+    }
+
+    /// Samples a direction light arrives from, picking pixels with a
+    /// probability proportional to their luminance. Returns the direction,
+    /// the radiance arriving from it and the probability density of having
+    /// sampled it, per unit solid angle, or [`None`] if the map is black.
+    pub fn sample(&self, rng: &mut impl Rng) -> Option<(Vec3, Vec3, f32)> {
+        let rows = self.rows.as_ref()?;
+        let (row, v) = rows.sample(rng.gen_range(0.0..1.0));
+        let columns = self.columns[row].as_ref()?;
+        let (column, u) = columns.sample(rng.gen_range(0.0..1.0));
+
+        // the inverse of spherical_uv, with the image upside down
+        let u = (column as f32 + u) / self.width as f32;
+        let v = 1.0 - (row as f32 + v) / self.height as f32;
+        let theta = std::f32::consts::PI * v;
+        let phi = 2.0 * std::f32::consts::PI * u - std::f32::consts::PI;
+        let direction = Vec3::new(
+            theta.sin() * phi.cos(),
+            -theta.cos(),
+            -theta.sin() * phi.sin(),
+        );
+
+        let pdf = self.pdf_of(row, column, theta.sin());
+        (pdf > 0.0).then(|| (direction, self.radiance(direction), pdf))
+    }
+
+    /// The probability density, per unit solid angle, of
+    /// [`EnvironmentMap::sample`] sampling the given (normalized) direction.
+    pub fn pdf(&self, direction: Vec3) -> f32 {
+        let uv = spherical_uv(direction);
+        let column = ((uv.x * self.width as f32) as u32).min(self.width - 1);
+        let row = (((1.0 - uv.y) * self.height as f32) as u32).min(self.height - 1);
+        let sin_theta = (1.0 - direction.y * direction.y).max(0.0).sqrt();
+
+        self.pdf_of(row as usize, column as usize, sin_theta)
+    }
+
+    /// The probability density, per unit solid angle, of sampling a
+    /// direction in the given pixel, where the sine of the angle to the
+    /// vertical axis is `sin_theta`.
+    #[inline(always)]
+    fn pdf_of(&self, row: usize, column: usize, sin_theta: f32) -> f32 {
+        let (Some(rows), Some(columns)) = (&self.rows, &self.columns[row]) else {
+            return 0.0;
+        };
+
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+
+        // the density over the image, which covers a 2pi by pi rectangle of
+        // angles, and each of those covers sin(theta) of solid angle
+        let pixel_count = (self.width * self.height) as f32;
+        let density = rows.probability(row) * columns.probability(column) * pixel_count;
+        density / (2.0 * std::f32::consts::PI * std::f32::consts::PI * sin_theta)
+    }
+}
+
+/// The light arriving from the background of a scene, depending on the
+/// direction it's looked at from.
+pub enum Background {
+    /// The same light in every direction.
+    Solid(LightRay),
+    /// A vertical gradient, going from `horizon` when looking horizontally
+    /// (or down) to `zenith` when looking straight up.
+    Gradient { horizon: LightRay, zenith: LightRay },
+    /// Light coming from an [`EnvironmentMap`].
+    Environment(EnvironmentMap),
+    /// An arbitrary function of the (normalized) direction.
+    Custom(Box<dyn Fn(Vec3) -> LightRay + Send + Sync>),
+}
+
+impl Background {
+    /// A background with the same light in every direction.
+    #[inline(always)]
+    pub fn solid(light: LightRay) -> Self {
+        Self::Solid(light)
+    }
+
+    /// A background going from `horizon` to `zenith` as the direction
+    /// goes up.
+    #[inline(always)]
+    pub fn gradient(horizon: LightRay, zenith: LightRay) -> Self {
+        Self::Gradient { horizon, zenith }
+    }
+
+    /// A background lit by an [`EnvironmentMap`], e.g. a HDRI.
+    #[inline(always)]
+    pub fn environment(map: EnvironmentMap) -> Self {
+        Self::Environment(map)
+    }
+
+    /// A background given by a function of the direction.
+    #[inline(always)]
+    pub fn from_fn(f: impl Fn(Vec3) -> LightRay + Send + Sync + 'static) -> Self {
+        Self::Custom(Box::new(f))
+    }
+
+    /// Samples a direction light arrives from, for backgrounds that can be
+    /// sampled like lights, which are those with an [`EnvironmentMap`].
+    /// Returns the direction, the light arriving from it and the
+    /// probability density of having sampled it, per unit solid angle.
+    #[inline(always)]
+    pub fn sample(&self, rng: &mut impl Rng) -> Option<(Vec3, LightRay, f32)> {
+        match self {
+            Background::Environment(map) => map.sample(rng).map(|(direction, radiance, pdf)| {
+                (direction, LightRay::from_sample(radiance), pdf)
+            }),
+            _ => None,
+        }
+    }
+
+    /// The probability density, per unit solid angle, of
+    /// [`Background::sample`] sampling the given direction. Zero for
+    /// backgrounds that can't be sampled.
+    #[inline(always)]
+    pub fn pdf(&self, direction: Vec3) -> f32 {
+        match self {
+            Background::Environment(map) => map.pdf(direction),
+            _ => 0.0,
+        }
+    }
+
+    /// Whether this background can be [sampled](Background::sample).
+    #[inline(always)]
+    pub fn is_sampleable(&self) -> bool {
+        matches!(self, Background::Environment(map) if map.rows.is_some())
+    }
+
+    /// The light arriving from the given direction.
+    #[inline(always)]
+    pub fn light(&self, direction: Vec3) -> LightRay {
+        match self {
+            Background::Solid(light) => *light,
+            Background::Gradient { horizon, zenith } => {
+                let t = direction.y.clamp(0.0, 1.0);
+                LightRay::from_sample(horizon.to_sample().lerp(zenith.to_sample(), t))
+            }
+            Background::Environment(map) => LightRay::from_sample(map.radiance(direction)),
+            Background::Custom(f) => f(direction),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::SmallRng, SeedableRng};
+    use std::f32::consts::PI;
+
+    /// The direction seen at `(x, y)` of `map`, in pixels from its top left
+    /// corner, so `(x + 0.5, y + 0.5)` is the center of a pixel.
+    fn direction(map: &EnvironmentMap, (x, y): (f32, f32)) -> Vec3 {
+        let (width, height) = map.dimensions();
+        let theta = PI * (1.0 - y / height as f32);
+        let phi = 2.0 * PI * x / width as f32 - PI;
+        Vec3::new(
+            theta.sin() * phi.cos(),
+            -theta.cos(),
+            -theta.sin() * phi.sin(),
+        )
+    }
+
+    /// A 4 by 2 map where every pixel is different.
+    fn texels() -> EnvironmentMap {
+        let pixels = (0..8)
+            .map(|index| Vec3::new(index as f32, 1.0, 10.0 - index as f32))
+            .collect();
+        EnvironmentMap::new(4, 2, pixels)
+    }
+
+    #[test]
+    fn pixel_centers_see_their_pixel() {
+        let map = texels();
+        for y in 0..2 {
+            for x in 0..4 {
+                let center = direction(&map, (x as f32 + 0.5, y as f32 + 0.5));
+                let expected = map.pixels[y * 4 + x];
+                let radiance = map.radiance(center);
+                assert!(
+                    radiance.abs_diff_eq(expected, 1e-4),
+                    "({x}, {y}): {radiance}"
+                );
+            }
+        }
+
+        // and halfway between two pixels, their average
+        let between = direction(&map, (2.0, 0.5));
+        let expected = (map.pixels[1] + map.pixels[2]) / 2.0;
+        assert!(map.radiance(between).abs_diff_eq(expected, 1e-4));
+    }
+
+    #[test]
+    fn vertical_edges_wrap_around() {
+        let map = texels();
+        for y in [0.5, 1.5] {
+            let row = y as usize * 4;
+
+            // on the edge, the average of the first and last pixels
+            let edge = map.radiance(direction(&map, (0.0, y)));
+            let expected = (map.pixels[row] + map.pixels[row + 3]) / 2.0;
+            assert!(edge.abs_diff_eq(expected, 1e-4), "{y}: {edge}");
+
+            // and no jump from one side to the other
+            let left = map.radiance(direction(&map, (1e-3, y)));
+            let right = map.radiance(direction(&map, (4.0 - 1e-3, y)));
+            assert!(left.abs_diff_eq(right, 1e-2), "{y}: {left} vs {right}");
+        }
+    }
+
+    #[test]
+    fn poles_see_the_average_of_their_row() {
+        let map = texels();
+        let top = map.pixels[..4].iter().sum::<Vec3>() / 4.0;
+        let bottom = map.pixels[4..].iter().sum::<Vec3>() / 4.0;
+        assert!(map.radiance(Vec3::Y).abs_diff_eq(top, 1e-4));
+        assert!(map.radiance(-Vec3::Y).abs_diff_eq(bottom, 1e-4));
+
+        // from every side, without a seam
+        for x in 0..16 {
+            let x = x as f32 / 4.0;
+            let near_top = map.radiance(direction(&map, (x, 1e-4)));
+            let near_bottom = map.radiance(direction(&map, (x, 2.0 - 1e-4)));
+            assert!(near_top.abs_diff_eq(top, 1e-2), "{x}: {near_top}");
+            assert!(near_bottom.abs_diff_eq(bottom, 1e-2), "{x}: {near_bottom}");
+        }
+    }
+
+    /// An 8 by 4 map of varied pixels, with a small and bright sun and a
+    /// black pixel.
+    fn sunny() -> EnvironmentMap {
+        let mut pixels: Vec<_> = (0..32)
+            .map(|index| {
+                let value = ((index * 7) % 11) as f32 / 10.0 + 0.1;
+                Vec3::new(value, value * 0.5, 1.0 - value * 0.5)
+            })
+            .collect();
+        pixels[10] = Vec3::splat(50.0);
+        pixels[21] = Vec3::ZERO;
+        EnvironmentMap::new(8, 4, pixels)
+    }
+
+    /// Integrates `f` over the sphere of directions, with the midpoint rule.
+    fn integrate(f: impl Fn(Vec3) -> f32) -> f64 {
+        let (thetas, phis) = (400, 800);
+        let (d_theta, d_phi) = (PI / thetas as f32, 2.0 * PI / phis as f32);
+        let mut total = 0.0;
+        for i in 0..thetas {
+            let theta = (i as f32 + 0.5) * d_theta;
+            for j in 0..phis {
+                let phi = (j as f32 + 0.5) * d_phi;
+                let direction = Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                total += (f(direction) * theta.sin() * d_theta * d_phi) as f64;
+            }
+        }
+
+        total
+    }
+
+    #[test]
+    fn samples_agree_with_pdf() {
+        let map = sunny();
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mut estimate = 0.0;
+        let mut suns = 0;
+        let samples = 100_000;
+        for _ in 0..samples {
+            let (direction, radiance, pdf) = map.sample(&mut rng).unwrap();
+            assert!((direction.length() - 1.0).abs() < 1e-4);
+            assert_eq!(radiance, map.radiance(direction));
+
+            // right at the poles, sin(theta) is too small for the two to
+            // round the same
+            let expected = map.pdf(direction);
+            if direction.y.abs() < 0.999 {
+                let error = (pdf - expected).abs() / expected;
+                assert!(error < 1e-3, "{pdf} vs {expected} at {direction}");
+            }
+
+            estimate += (luminance(radiance) / pdf) as f64;
+            let uv = spherical_uv(direction);
+            let pixel = ((1.0 - uv.y) * 4.0) as usize * 8 + (uv.x * 8.0) as usize;
+            suns += usize::from(pixel == 10);
+        }
+
+        // pixels are picked by their luminance times the solid angle they
+        // cover, so the sun is picked most of the time
+        let weight = |index: usize| {
+            let sin_theta = (PI * ((index / 8) as f32 + 0.5) / 4.0).sin();
+            (luminance(map.pixels[index]) * sin_theta) as f64
+        };
+        let expected = weight(10) / (0..32).map(weight).sum::<f64>();
+        let fraction = suns as f64 / samples as f64;
+        assert!(expected > 0.5);
+        assert!(
+            (fraction - expected).abs() < 0.01,
+            "{fraction} vs {expected}"
+        );
+
+        // the pdf is a density over the sphere of directions...
+        let total = integrate(|direction| map.pdf(direction));
+        assert!((total - 1.0).abs() < 1e-2, "{total}");
+
+        // ...and dividing by it gives an unbiased estimate of the light
+        // arriving from every direction
+        let estimate = estimate / samples as f64;
+        let expected = integrate(|direction| luminance(map.radiance(direction)));
+        let error = (estimate - expected).abs() / expected;
+        assert!(error < 0.02, "{estimate} vs {expected}");
+
+        // the black pixel is never sampled
+        assert_eq!(map.pdf(direction(&map, (5.5, 2.5))), 0.0);
+    }
+}
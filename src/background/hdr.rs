@@ -0,0 +1,314 @@
+use super::EnvironmentMap;
+use crate::Vec3;
+use std::fmt::Display;
+use std::io::BufRead;
+
+/// An error that happened while loading a Radiance HDR file.
+#[derive(Debug)]
+pub enum HdrError {
+    /// Reading from the source failed.
+    Io(std::io::Error),
+    /// The header of the file is malformed or uses an unsupported format.
+    Header(String),
+    /// The file ended before every scanline was read.
+    Truncated,
+    /// The run-length encoded data of a scanline is malformed.
+    Corrupt { scanline: usize },
+}
+
+impl Display for HdrError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HdrError::Io(err) => write!(f, "failed to read hdr: {err}"),
+            HdrError::Header(message) => write!(f, "invalid hdr header: {message}"),
+            HdrError::Truncated => write!(f, "hdr is truncated"),
+            HdrError::Corrupt { scanline } => write!(f, "scanline {scanline} is corrupt"),
+        }
+    }
+}
+
+impl std::error::Error for HdrError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            HdrError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for HdrError {
+    fn from(value: std::io::Error) -> Self {
+        match value.kind() {
+            std::io::ErrorKind::UnexpectedEof => HdrError::Truncated,
+            _ => HdrError::Io(value),
+        }
+    }
+}
+
+fn header_error(message: impl Into<String>) -> HdrError {
+    HdrError::Header(message.into())
+}
+
+/// Reads a line of the header, without the newline.
+fn read_line(reader: &mut impl BufRead) -> Result<String, HdrError> {
+    let mut line = Vec::new();
+    if reader.read_until(b'\n', &mut line)? == 0 {
+        return Err(HdrError::Truncated);
+    }
+
+    if line.last() == Some(&b'\n') {
+        line.pop();
+    }
+
+    String::from_utf8(line).map_err(|_| header_error("header is not valid text"))
+}
+
+/// Converts a pixel in the shared exponent format to linear radiance.
+#[inline(always)]
+fn rgbe_to_vec3([r, g, b, e]: [u8; 4]) -> Vec3 {
+    if e == 0 {
+        return Vec3::ZERO;
+    }
+
+    // the mantissas are fractions of 256
+    let scale = 2f32.powi(e as i32 - (128 + 8));
+    Vec3::new(r as f32, g as f32, b as f32) * scale
+}
+
+fn read_byte(reader: &mut impl BufRead) -> Result<u8, HdrError> {
+    let mut byte = [0];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+/// Reads a scanline of `width` pixels into `scanline`, replacing its
+/// contents. It only grows as pixels are read, so huge widths in corrupt
+/// headers can't allocate much memory.
+fn read_scanline(
+    reader: &mut impl BufRead,
+    scanline: &mut Vec<[u8; 4]>,
+    width: usize,
+    index: usize,
+) -> Result<(), HdrError> {
+    scanline.clear();
+    let mut first = [0; 4];
+    reader.read_exact(&mut first)?;
+
+    // scanlines in the newer run-length encoding start with two 2s and their
+    // width, then have every channel encoded separately.
+    let is_rle =
+        (8..0x8000).contains(&width) && first[0] == 2 && first[1] == 2 && first[2] & 0x80 == 0;
+    if is_rle {
+        if (first[2] as usize) << 8 | first[3] as usize != width {
+            return Err(HdrError::Corrupt { scanline: index });
+        }
+
+        // the width is small here
+        scanline.resize(width, [0; 4]);
+
+        for channel in 0..4 {
+            let mut x = 0;
+            while x < width {
+                let count = read_byte(reader)? as usize;
+                if count > 128 {
+                    // a run of the same value
+                    let count = count - 128;
+                    if x + count > width {
+                        return Err(HdrError::Corrupt { scanline: index });
+                    }
+
+                    let value = read_byte(reader)?;
+                    for pixel in &mut scanline[x..x + count] {
+                        pixel[channel] = value;
+                    }
+                    x += count;
+                } else {
+                    // literal values
+                    if count == 0 || x + count > width {
+                        return Err(HdrError::Corrupt { scanline: index });
+                    }
+
+                    for pixel in &mut scanline[x..x + count] {
+                        pixel[channel] = read_byte(reader)?;
+                    }
+                    x += count;
+                }
+            }
+        }
+
+        return Ok(());
+    }
+
+    // otherwise, pixels are stored one after the other, where a pixel of
+    // three 1s repeats the previous one (the older run-length encoding).
+    let mut shift = 0;
+    let mut pixel = first;
+    loop {
+        if pixel[..3] == [1, 1, 1] {
+            let Some(&previous) = scanline.last() else {
+                return Err(HdrError::Corrupt { scanline: index });
+            };
+
+            let count = (pixel[3] as usize) << shift;
+            if scanline.len() + count > width {
+                return Err(HdrError::Corrupt { scanline: index });
+            }
+
+            scanline.resize(scanline.len() + count, previous);
+            shift += 8;
+        } else {
+            scanline.push(pixel);
+            shift = 0;
+        }
+
+        if scanline.len() == width {
+            return Ok(());
+        }
+
+        reader.read_exact(&mut pixel)?;
+    }
+}
+
+impl EnvironmentMap {
+    /// Loads an environment map from a Radiance HDR (`.hdr`) file in the
+    /// equirectangular projection.
+    ///
+    /// Only the RGBE format is supported, with the usual `-Y height +X width`
+    /// orientation or its vertically flipped version, `+Y height +X width`.
+    pub fn from_hdr(mut reader: impl BufRead) -> Result<Self, HdrError> {
+        let magic = read_line(&mut reader)?;
+        if !magic.starts_with("#?") {
+            return Err(header_error("missing magic number"));
+        }
+
+        loop {
+            let line = read_line(&mut reader)?;
+            if line.is_empty() {
+                break;
+            }
+
+            if let Some(format) = line.strip_prefix("FORMAT=") {
+                if format.trim() != "32-bit_rle_rgbe" {
+                    return Err(header_error(format!("unsupported format `{format}`")));
+                }
+            }
+        }
+
+        let resolution = read_line(&mut reader)?;
+        let tokens: Vec<_> = resolution.split_whitespace().collect();
+        let (flipped, height, width) = match tokens[..] {
+            ["-Y", height, "+X", width] => (false, height, width),
+            ["+Y", height, "+X", width] => (true, height, width),
+            _ => {
+                return Err(header_error(format!(
+                    "unsupported resolution `{resolution}`"
+                )))
+            }
+        };
+
+        let parse_size = |token: &str| {
+            token
+                .parse::<u32>()
+                .ok()
+                .filter(|&size| size > 0)
+                .ok_or_else(|| header_error(format!("invalid size `{token}`")))
+        };
+        let (width, height) = (parse_size(width)?, parse_size(height)?);
+        let too_big = (width as usize)
+            .checked_mul(height as usize)
+            .and_then(|count| count.checked_mul(std::mem::size_of::<Vec3>()))
+            .is_none_or(|size| size > isize::MAX as usize);
+        if too_big {
+            return Err(header_error(format!(
+                "resolution `{resolution}` is too big"
+            )));
+        }
+
+        // the sizes can't be trusted until the pixels are actually read, so
+        // nothing is allocated for them up front
+        let mut pixels = Vec::new();
+        let mut scanline = Vec::new();
+        for index in 0..height as usize {
+            read_scanline(&mut reader, &mut scanline, width as usize, index)?;
+            pixels.extend(scanline.iter().map(|&pixel| rgbe_to_vec3(pixel)));
+        }
+
+        if flipped {
+            let rows: Vec<_> = pixels.chunks_exact(width as usize).rev().collect();
+            pixels = rows.concat();
+        }
+
+        Ok(Self::new(width, height, pixels))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::write_hdr;
+    use picture::prelude::{ImgBuf, ImgViewMut, RGB};
+
+    #[test]
+    fn written_files_load_back() {
+        let (width, height) = (5, 3);
+        let value = |x: u32, y: u32| {
+            Vec3::new(
+                x as f32 * 10f32.powi(y as i32 * 2 - 2),
+                0.5,
+                (y * width + x) as f32 / 7.0,
+            )
+        };
+
+        let mut image = ImgBuf::<RGB<f32>, Vec<_>>::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let [r, g, b] = value(x, y).to_array();
+                *image.pixel_mut((x, y)).unwrap() = RGB::new(r, g, b);
+            }
+        }
+
+        // broken pixels are written anyway
+        *image.pixel_mut((1, 1)).unwrap() = RGB::new(f32::INFINITY, 1.0, 0.0);
+        *image.pixel_mut((2, 1)).unwrap() = RGB::new(f32::NAN, 1.0, -1.0);
+
+        let mut bytes = Vec::new();
+        write_hdr(&mut bytes, &image).unwrap();
+        let map = EnvironmentMap::from_hdr(&bytes[..]).unwrap();
+        assert_eq!(map.dimensions(), (width, height));
+
+        for y in 0..height {
+            for x in 0..width {
+                let loaded = map.pixels[(y * width + x) as usize];
+                match (x, y) {
+                    (1, 1) => assert!(loaded.x > 1e37 && loaded.is_finite(), "{loaded}"),
+                    (2, 1) => assert_eq!(loaded, Vec3::new(0.0, 1.0, 0.0)),
+                    _ => {
+                        // every channel has 8 bits of the brightest one
+                        let expected = value(x, y);
+                        let tolerance = expected.max_element() / 128.0;
+                        assert!(
+                            (loaded - expected).abs().max_element() <= tolerance,
+                            "{loaded} != {expected} at ({x}, {y})"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn huge_resolutions_are_errors() {
+        let header = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 4000000000 +X 4000000000\n";
+        assert!(matches!(
+            EnvironmentMap::from_hdr(&header[..]),
+            Err(HdrError::Header(_))
+        ));
+
+        // big enough to allocate, but without the pixels to back it
+        let header = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 4000000000 +X 4\n\x01\x02\x03\x80";
+        assert!(matches!(
+            EnvironmentMap::from_hdr(&header[..]),
+            Err(HdrError::Truncated)
+        ));
+    }
+}
@@ -1,26 +1,138 @@
-use std::ops::Mul;
+use std::{
+    fmt::{self, Display},
+    ops::{Add, AddAssign, Mul, Sub},
+};
 
-use crate::Vec3;
+use crate::{tonemap::linear_to_srgb, Vec3};
 
-/// A ray. Represented by a starting point and a direction.
+/// Why a value couldn't be built from the arguments it was given.
+///
+/// Returned by the `try_new` constructors, like [`Ray::try_new`], which
+/// check the same conditions their panicking counterparts assert.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BuildError {
+    /// A direction that must be normalized isn't.
+    NotNormalized { name: &'static str, value: Vec3 },
+    /// A direction that would be normalized is zero or not finite.
+    InvalidDirection { name: &'static str, value: Vec3 },
+    /// A number is not in the range given by `expected`.
+    OutOfRange {
+        name: &'static str,
+        value: f32,
+        expected: &'static str,
+    },
+    /// A mesh refers to a vertex it doesn't have.
+    IndexOutOfBounds { index: u32, vertex_count: usize },
+}
+
+impl Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            BuildError::NotNormalized { name, value } => {
+                write!(f, "`{name}` must be normalized, but is {value}")
+            }
+            BuildError::InvalidDirection { name, value } => {
+                write!(
+                    f,
+                    "`{name}` must be a finite, non-zero direction, but is {value}"
+                )
+            }
+            BuildError::OutOfRange {
+                name,
+                value,
+                expected,
+            } => write!(f, "`{name}` must be {expected}, but is {value}"),
+            BuildError::IndexOutOfBounds {
+                index,
+                vertex_count,
+            } => write!(
+                f,
+                "vertex index {index} is out of bounds for {vertex_count} vertices"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+/// Fails with [`BuildError::NotNormalized`] if `value` isn't normalized.
+#[inline(always)]
+pub(crate) fn check_normalized(name: &'static str, value: Vec3) -> Result<(), BuildError> {
+    if value.is_normalized() {
+        Ok(())
+    } else {
+        Err(BuildError::NotNormalized { name, value })
+    }
+}
+
+/// Fails with [`BuildError::OutOfRange`] if `valid` is false.
+#[inline(always)]
+pub(crate) fn check_range(
+    name: &'static str,
+    value: f32,
+    expected: &'static str,
+    valid: bool,
+) -> Result<(), BuildError> {
+    if valid {
+        Ok(())
+    } else {
+        Err(BuildError::OutOfRange {
+            name,
+            value,
+            expected,
+        })
+    }
+}
+
+/// A ray. Represented by a starting point, a direction, the time it was
+/// cast at and a seed.
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Ray {
     /// The starting point of this ray.
     point: Vec3,
     /// The direction of this ray,
     direction: Vec3,
+    /// The time this ray was cast at, in the \[0..1) range of the shutter.
+    /// Moving shapes are where they are at this time.
+    time: f32,
+    /// Randomness for shapes that need it, like
+    /// [`ConstantMedium`](crate::shape::ConstantMedium).
+    seed: u64,
 }
 
 impl Ray {
     /// Creates a new [`Ray`] with the given starting point
-    /// and direction.
+    /// and direction, cast at time zero with a seed of zero.
     ///
     /// # Panics
     /// Panics if `direction` is not normalized.
     #[inline(always)]
     pub fn new(point: Vec3, direction: Vec3) -> Self {
-        assert!(direction.is_normalized());
-        Self { point, direction }
+        Self::try_new(point, direction).unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Same as [`Ray::new`], but fails instead of panicking.
+    #[inline(always)]
+    pub fn try_new(point: Vec3, direction: Vec3) -> Result<Self, BuildError> {
+        check_normalized("direction", direction)?;
+        Ok(Self {
+            point,
+            direction,
+            time: 0.0,
+            seed: 0,
+        })
+    }
+
+    /// Returns this ray, cast at `time` instead.
+    #[inline(always)]
+    pub fn with_time(self, time: f32) -> Self {
+        Self { time, ..self }
+    }
+
+    /// Returns this ray with the given seed instead.
+    #[inline(always)]
+    pub fn with_seed(self, seed: u64) -> Self {
+        Self { seed, ..self }
     }
 
     /// The starting point of this ray.
@@ -35,6 +147,21 @@ impl Ray {
         self.direction
     }
 
+    /// The time this ray was cast at.
+    #[inline(always)]
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    /// The seed of this ray. The renderer gives every camera and scattered
+    /// ray one derived from [`Renderer::seed`](crate::render::Renderer::seed)
+    /// and the sample, so shapes that scatter at random do it differently
+    /// in every sample. Other rays, like shadow rays, have a seed of zero.
+    #[inline(always)]
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
     /// Returns the point `ray.point + ray.direction * t`.
     #[inline(always)]
     pub fn point_at_t(&self, t: f32) -> Vec3 {
@@ -42,29 +169,73 @@ impl Ray {
     }
 }
 
-/// A color.
+/// A color, with every channel in the \[0..1] range.
+///
+/// Colors are used for things that can't go past one, like the albedo of a
+/// surface or the hue of a light. Amounts of light, which can be
+/// arbitrarily large, are represented by a
+/// [`LightRay`](crate::light::LightRay) instead: a color and an unbounded
+/// intensity it is scaled by.
+///
+/// Arithmetic on colors saturates: results are clamped to the \[0..1]
+/// range, so they are still colors. To add up amounts of light, add their
+/// [samples](crate::light::LightRay::to_sample) instead.
 #[repr(transparent)]
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Color(Vec3);
 
 impl Color {
+    /// The color black.
+    pub const BLACK: Self = Self(Vec3::ZERO);
+
     /// Creates a new [`Color`] with the given RGB channels.
     ///
     /// # Panics
     /// Panics if any of the channels is not in the \[0..1] range.
     #[inline(always)]
     pub fn new(r: f32, g: f32, b: f32) -> Self {
-        assert!(r <= 1.0);
-        assert!(g <= 1.0);
-        assert!(b <= 1.0);
+        Self::try_new(r, g, b).unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Same as [`Color::new`], but fails instead of panicking.
+    #[inline(always)]
+    pub fn try_new(r: f32, g: f32, b: f32) -> Result<Self, BuildError> {
+        for (name, value) in [("r", r), ("g", g), ("b", b)] {
+            check_range(
+                name,
+                value,
+                "in the [0, 1] range",
+                (0.0..=1.0).contains(&value),
+            )?;
+        }
 
-        Self(Vec3::new(r, g, b))
+        Ok(Self(Vec3::new(r, g, b)))
     }
 
     #[inline(always)]
     pub fn to_vec3(&self) -> Vec3 {
         self.0
     }
+
+    /// Linearly interpolates between this color and `other`, giving this
+    /// color when `t` is zero and `other` when it is one.
+    ///
+    /// # Panics
+    /// Panics if `t` is not in the \[0..1] range.
+    #[inline(always)]
+    pub fn lerp(&self, other: Self, t: f32) -> Self {
+        assert!((0.0..=1.0).contains(&t));
+        Self(self.0.lerp(other.0, t).clamp(Vec3::ZERO, Vec3::ONE))
+    }
+
+    /// Encodes this color, which is linear, as 8-bit sRGB channels, ready to
+    /// be written to most image formats.
+    #[inline(always)]
+    pub fn to_srgb8(&self) -> [u8; 3] {
+        self.0
+            .to_array()
+            .map(|c| (linear_to_srgb(c) * 255.0).round() as u8)
+    }
 }
 
 impl Mul<Self> for Color {
@@ -75,3 +246,48 @@ impl Mul<Self> for Color {
         Color(self.0 * rhs.0)
     }
 }
+
+impl Mul<f32> for Color {
+    type Output = Color;
+
+    #[inline(always)]
+    fn mul(self, rhs: f32) -> Self::Output {
+        Color((self.0 * rhs).clamp(Vec3::ZERO, Vec3::ONE))
+    }
+}
+
+impl Add<Self> for Color {
+    type Output = Color;
+
+    #[inline(always)]
+    fn add(self, rhs: Self) -> Self::Output {
+        Color((self.0 + rhs.0).min(Vec3::ONE))
+    }
+}
+
+impl AddAssign<Self> for Color {
+    #[inline(always)]
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub<Self> for Color {
+    type Output = Color;
+
+    #[inline(always)]
+    fn sub(self, rhs: Self) -> Self::Output {
+        Color((self.0 - rhs.0).max(Vec3::ZERO))
+    }
+}
+
+/// Scrambles the bits of `value` (the finalizer of SplitMix64), so that
+/// close inputs give unrelated outputs. Used to derive random seeds from
+/// structured values, like pixel coordinates.
+#[inline(always)]
+pub(crate) fn mix64(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
@@ -0,0 +1,84 @@
+//! Denoising renders guided by their auxiliary outputs, with Intel Open
+//! Image Denoise when the `denoise` feature is enabled.
+
+use crate::Vec3;
+use picture::prelude::{ImgBuf, ImgView, ImgViewMut, Pixel, RGB};
+
+/// Denoises `color`, an image rendered by
+/// [`Renderer::render_with`](crate::render::Renderer::render_with), guided
+/// by the albedo and normal [auxiliary outputs](crate::render::Aovs) of the
+/// same render.
+///
+/// Without the `denoise` feature, this returns a copy of `color`.
+///
+/// # Panics
+/// Panics if `albedo` or `normal` don't have a value for every pixel of
+/// `color`, or if the denoiser fails.
+pub fn denoise_rgb<I, P>(
+    color: &I,
+    albedo: &[Vec3],
+    normal: &[Vec3],
+) -> ImgBuf<RGB<f32>, Vec<RGB<f32>>>
+where
+    I: ImgView<Pixel = P>,
+    P: Pixel<Channels = [f32; 3]>,
+{
+    let (width, height) = color.dimensions();
+    let pixel_count = (width * height) as usize;
+    assert_eq!(albedo.len(), pixel_count);
+    assert_eq!(normal.len(), pixel_count);
+
+    let mut channels = Vec::with_capacity(pixel_count * 3);
+    for y in 0..height {
+        for x in 0..width {
+            channels.extend(color.pixel((x, y)).unwrap().channels());
+        }
+    }
+
+    let denoised = filter(&channels, albedo, normal, (width, height));
+    let mut output = ImgBuf::new(width, height);
+    for (pixel, rgb) in output.pixels_mut().zip(denoised.chunks_exact(3)) {
+        *pixel = RGB::new(rgb[0], rgb[1], rgb[2]);
+    }
+
+    output
+}
+
+/// Runs the denoiser on the interleaved RGB channels of a `width` by
+/// `height` image.
+#[cfg(feature = "denoise")]
+fn filter(
+    color: &[f32],
+    albedo: &[Vec3],
+    normal: &[Vec3],
+    (width, height): (u32, u32),
+) -> Vec<f32> {
+    let flatten = |values: &[Vec3]| -> Vec<f32> {
+        values.iter().flat_map(|value| value.to_array()).collect()
+    };
+    let (albedo, normal) = (flatten(albedo), flatten(normal));
+
+    // renders are already exposed and tone mapped, so their channels are
+    // linear and in the [0, 1] range
+    let device = oidn::Device::new();
+    let mut output = vec![0.0; color.len()];
+    oidn::RayTracing::new(&device)
+        .srgb(false)
+        .hdr(false)
+        .image_dimensions(width as usize, height as usize)
+        .albedo_normal(&albedo, &normal)
+        .filter(color, &mut output)
+        .expect("the buffers should match the dimensions of the image");
+
+    if let Err((_, message)) = device.get_error() {
+        panic!("denoising failed: {message}");
+    }
+
+    output
+}
+
+#[cfg(not(feature = "denoise"))]
+#[inline(always)]
+fn filter(color: &[f32], _: &[Vec3], _: &[Vec3], _: (u32, u32)) -> Vec<f32> {
+    color.to_vec()
+}
@@ -0,0 +1,77 @@
+//! Discrete probability distributions, for sampling things by importance.
+
+/// A distribution over the indices of a list of weights, where every index
+/// is picked with a probability proportional to its weight.
+#[derive(Clone, Debug)]
+pub(crate) struct Distribution {
+    /// The sum of the weights up to and including every index, divided by
+    /// the total weight.
+    cdf: Vec<f32>,
+    total: f32,
+}
+
+impl Distribution {
+    /// Creates a new [`Distribution`] over `weights`, or returns [`None`]
+    /// if their total is zero, in which case nothing can be picked.
+    ///
+    /// # Panics
+    /// Panics if any weight is negative or not finite.
+    pub(crate) fn new(weights: impl IntoIterator<Item = f32>) -> Option<Self> {
+        let mut total = 0.0;
+        let mut last = 0;
+        let mut cdf: Vec<f32> = weights
+            .into_iter()
+            .enumerate()
+            .map(|(index, weight)| {
+                assert!(weight >= 0.0 && weight.is_finite());
+                if weight > 0.0 {
+                    last = index;
+                }
+
+                total += weight;
+                total
+            })
+            .collect();
+
+        if total <= 0.0 {
+            return None;
+        }
+
+        cdf.iter_mut().for_each(|sum| *sum /= total);
+        // so that rounding errors can't leave numbers close to one without
+        // an index to pick
+        cdf[last..].fill(1.0);
+
+        Some(Self { cdf, total })
+    }
+
+    /// The sum of the weights.
+    #[inline(always)]
+    pub(crate) fn total(&self) -> f32 {
+        self.total
+    }
+
+    /// The probability of picking `index`.
+    #[inline(always)]
+    pub(crate) fn probability(&self, index: usize) -> f32 {
+        let previous = index
+            .checked_sub(1)
+            .map_or(0.0, |previous| self.cdf[previous]);
+        self.cdf[index] - previous
+    }
+
+    /// Picks an index given `u`, a uniformly distributed number in the
+    /// \[0..1) range. Also returns where `u` fell inside of the range of
+    /// numbers that pick the index, remapped to \[0..1), which can be used
+    /// as another uniformly distributed number.
+    pub(crate) fn sample(&self, u: f32) -> (usize, f32) {
+        // indices of zero weight have empty ranges, so they're never picked
+        let index = self.cdf.partition_point(|&sum| sum <= u);
+
+        let start = index
+            .checked_sub(1)
+            .map_or(0.0, |previous| self.cdf[previous]);
+        let remapped = (u - start) / self.probability(index);
+        (index, remapped.clamp(0.0, 1.0 - f32::EPSILON))
+    }
+}
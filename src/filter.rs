@@ -0,0 +1,144 @@
+//! Pixel reconstruction filters, which weight the samples contributing to
+//! a pixel by their distance to its center.
+
+use crate::common::{check_range, BuildError};
+
+/// A pixel reconstruction filter.
+///
+/// Samples contribute to every pixel whose center is closer than the
+/// radius of the filter along both axes, weighted by it. Filters wider than
+/// a pixel blur the image a little, but alias less than [`Filter::Box`].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Filter {
+    /// Every sample contributes only to the pixel it's in, with the same
+    /// weight.
+    #[default]
+    Box,
+    /// The weight falls off linearly from the center of the pixel, reaching
+    /// zero at `radius` pixels.
+    ///
+    /// `radius` must be positive, or samples get no weight at all and the
+    /// image is black. See [`Filter::tent`].
+    Tent { radius: f32 },
+    /// A gaussian with standard deviation `sigma` pixels, shifted down so
+    /// that it reaches zero at `radius` pixels.
+    ///
+    /// Both must be positive, or samples get no weight at all and the image
+    /// is black. See [`Filter::gaussian`].
+    Gaussian { radius: f32, sigma: f32 },
+}
+
+impl Filter {
+    /// Creates a [`Filter::Tent`].
+    ///
+    /// # Panics
+    /// Panics if `radius` is not positive and finite.
+    #[inline(always)]
+    pub fn tent(radius: f32) -> Self {
+        Self::try_tent(radius).unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Same as [`Filter::tent`], but fails instead of panicking.
+    pub fn try_tent(radius: f32) -> Result<Self, BuildError> {
+        check_range("radius", radius, "positive and finite", positive(radius))?;
+        Ok(Filter::Tent { radius })
+    }
+
+    /// Creates a [`Filter::Gaussian`].
+    ///
+    /// # Panics
+    /// Panics if `radius` or `sigma` are not positive and finite.
+    #[inline(always)]
+    pub fn gaussian(radius: f32, sigma: f32) -> Self {
+        Self::try_gaussian(radius, sigma).unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Same as [`Filter::gaussian`], but fails instead of panicking.
+    pub fn try_gaussian(radius: f32, sigma: f32) -> Result<Self, BuildError> {
+        check_range("radius", radius, "positive and finite", positive(radius))?;
+        check_range("sigma", sigma, "positive and finite", positive(sigma))?;
+        Ok(Filter::Gaussian { radius, sigma })
+    }
+
+    /// How far from the center of a pixel, in pixels, samples still
+    /// contribute to it.
+    #[inline(always)]
+    pub fn radius(&self) -> f32 {
+        match *self {
+            Filter::Box => 0.5,
+            Filter::Tent { radius } | Filter::Gaussian { radius, .. } => radius,
+        }
+    }
+
+    /// How many pixels away from its own the samples of a pixel can reach.
+    #[inline(always)]
+    pub fn margin(&self) -> u32 {
+        // samples are at most half a pixel away from the center of their
+        // own pixel
+        (self.radius() - 0.5).ceil().max(0.0) as u32
+    }
+
+    /// The weight of a sample at offset `(dx, dy)` from the center of a
+    /// pixel, in pixels. Zero outside of the [radius](Filter::radius).
+    #[inline(always)]
+    pub fn weight(&self, (dx, dy): (f32, f32)) -> f32 {
+        self.weight_1d(dx) * self.weight_1d(dy)
+    }
+
+    #[inline(always)]
+    fn weight_1d(&self, d: f32) -> f32 {
+        let d = d.abs();
+        match *self {
+            Filter::Box => (d <= 0.5) as u32 as f32,
+            Filter::Tent { radius } => (1.0 - d / radius).max(0.0),
+            Filter::Gaussian { radius, sigma } => {
+                let gaussian = |d: f32| (-d * d / (2.0 * sigma * sigma)).exp();
+                (gaussian(d) - gaussian(radius)).max(0.0)
+            }
+        }
+    }
+}
+
+#[inline(always)]
+fn positive(value: f32) -> bool {
+    value.is_finite() && value > 0.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn degenerate_filters_are_errors() {
+        assert!(Filter::try_tent(0.0).is_err());
+        assert!(Filter::try_tent(-1.0).is_err());
+        assert!(Filter::try_tent(f32::INFINITY).is_err());
+        assert!(Filter::try_gaussian(1.5, 0.0).is_err());
+        assert!(Filter::try_gaussian(0.0, 0.5).is_err());
+        assert!(Filter::try_gaussian(1.5, f32::NAN).is_err());
+
+        assert_eq!(Filter::tent(1.0), Filter::Tent { radius: 1.0 });
+        assert_eq!(
+            Filter::gaussian(1.5, 0.5),
+            Filter::Gaussian {
+                radius: 1.5,
+                sigma: 0.5
+            }
+        );
+
+        // built directly, they give no weight to any sample, not even at
+        // the center of the pixel
+        let degenerate = [
+            Filter::Tent { radius: 0.0 },
+            Filter::Gaussian {
+                radius: 1.5,
+                sigma: 0.0,
+            },
+        ];
+        for filter in degenerate {
+            for offset in [(0.0, 0.0), (0.25, 0.0), (0.5, 0.5)] {
+                assert_eq!(filter.weight(offset), 0.0, "{filter:?} at {offset:?}");
+            }
+        }
+    }
+}
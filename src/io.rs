@@ -0,0 +1,81 @@
+//! Loading scene assets from files, and saving renders to them.
+
+use crate::{
+    background::{EnvironmentMap, HdrError},
+    shape::mesh::{Mesh, ObjError, ObjMesh, StlError},
+};
+use picture::prelude::{ImgView, Pixel};
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::Path,
+};
+
+mod exr;
+mod hdr;
+
+pub use exr::write_exr;
+pub use hdr::write_hdr;
+
+/// Loads a mesh from the Wavefront OBJ file at `path`. See
+/// [`Mesh::from_obj`].
+pub fn load_obj(path: impl AsRef<Path>) -> Result<ObjMesh, ObjError> {
+    let file = File::open(path)?;
+    Mesh::from_obj(BufReader::new(file))
+}
+
+/// Loads a mesh from the STL file at `path`. See [`Mesh::from_stl`].
+pub fn load_stl(path: impl AsRef<Path>, weld_epsilon: Option<f32>) -> Result<Mesh, StlError> {
+    let file = File::open(path)?;
+    Mesh::from_stl(BufReader::new(file), weld_epsilon)
+}
+
+/// Loads an environment map from the Radiance HDR file at `path`. See
+/// [`EnvironmentMap::from_hdr`].
+pub fn load_hdr(path: impl AsRef<Path>) -> Result<EnvironmentMap, HdrError> {
+    let file = File::open(path)?;
+    EnvironmentMap::from_hdr(BufReader::new(file))
+}
+
+/// Loads a scene from the JSON file at `path`. See
+/// [`Scene::from_reader`](crate::scene::Scene::from_reader).
+///
+/// # Errors
+/// Fails if the file can't be read, if it isn't valid JSON or if it
+/// describes invalid values. See
+/// [`SceneDescription::try_build`](crate::scene::SceneDescription::try_build).
+#[cfg(feature = "serde")]
+pub fn load_scene(path: impl AsRef<Path>) -> serde_json::Result<crate::scene::Scene> {
+    let file = File::open(path).map_err(serde_json::Error::io)?;
+    crate::scene::Scene::from_reader(BufReader::new(file))
+}
+
+/// Saves an image with linear RGB channels, like the ones produced by
+/// [`Renderer::render`](crate::render::Renderer::render), to an OpenEXR
+/// file at `path`, keeping its full range. See [`write_exr`].
+///
+/// # Errors
+/// Fails with [`std::io::ErrorKind::InvalidInput`] if the image is empty, in
+/// which case no file is created, or if writing the file fails.
+pub fn save_exr<I, P>(image: &I, path: impl AsRef<Path>) -> std::io::Result<()>
+where
+    I: ImgView<Pixel = P>,
+    P: Pixel<Channels = [f32; 3]>,
+{
+    exr::check_not_empty(image.dimensions())?;
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_exr(&mut writer, image)?;
+    writer.flush()
+}
+
+/// Saves an image with linear RGB channels to a Radiance HDR file at
+/// `path`. See [`write_hdr`].
+pub fn save_hdr<I, P>(image: &I, path: impl AsRef<Path>) -> std::io::Result<()>
+where
+    I: ImgView<Pixel = P>,
+    P: Pixel<Channels = [f32; 3]>,
+{
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_hdr(&mut writer, image)?;
+    writer.flush()
+}
@@ -0,0 +1,244 @@
+//! A minimal OpenEXR writer: a single part of scanlines, with 32-bit float
+//! channels and no compression.
+
+use picture::prelude::{ImgView, Pixel};
+use std::io::{self, Write};
+
+/// The magic number every OpenEXR file starts with.
+const MAGIC: u32 = 0x01312f76;
+/// Version 2, single part scanline file.
+const VERSION: u32 = 2;
+/// The pixel type of 32-bit float channels.
+const PIXEL_TYPE_FLOAT: i32 = 2;
+
+/// Fails with [`io::ErrorKind::InvalidInput`] if an image with the given
+/// dimensions is empty, which OpenEXR can't represent.
+pub(super) fn check_not_empty((width, height): (u32, u32)) -> io::Result<()> {
+    if width == 0 || height == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "can't write an empty image",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Writes an attribute of the header.
+fn write_attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+/// Writes `image`, whose channels are linear RGB, to `writer` as an OpenEXR
+/// file.
+///
+/// # Errors
+/// Fails with [`io::ErrorKind::InvalidInput`] if the image is empty, or if
+/// writing to `writer` fails.
+pub fn write_exr<I, P>(mut writer: impl Write, image: &I) -> io::Result<()>
+where
+    I: ImgView<Pixel = P>,
+    P: Pixel<Channels = [f32; 3]>,
+{
+    let (width, height) = image.dimensions();
+    check_not_empty((width, height))?;
+
+    // channels are stored in alphabetical order
+    let mut channels = Vec::new();
+    for name in ["B", "G", "R"] {
+        channels.extend_from_slice(name.as_bytes());
+        channels.push(0);
+        channels.extend_from_slice(&PIXEL_TYPE_FLOAT.to_le_bytes());
+        // linear flag and reserved bytes
+        channels.extend_from_slice(&[0; 4]);
+        // x and y sampling
+        channels.extend_from_slice(&1i32.to_le_bytes());
+        channels.extend_from_slice(&1i32.to_le_bytes());
+    }
+    channels.push(0);
+
+    let window: Vec<u8> = [0, 0, width as i32 - 1, height as i32 - 1]
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect();
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&MAGIC.to_le_bytes());
+    header.extend_from_slice(&VERSION.to_le_bytes());
+    write_attribute(&mut header, "channels", "chlist", &channels);
+    write_attribute(&mut header, "compression", "compression", &[0]);
+    write_attribute(&mut header, "dataWindow", "box2i", &window);
+    write_attribute(&mut header, "displayWindow", "box2i", &window);
+    write_attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    write_attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    write_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    write_attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    header.push(0);
+    writer.write_all(&header)?;
+
+    // every scanline is its own chunk: its y coordinate, its size and its
+    // data, preceded by a table with the offset of every chunk in the file
+    let line_size = width as usize * 3 * 4;
+    let chunks_start = header.len() + height as usize * 8;
+    for y in 0..height as usize {
+        let offset = chunks_start + y * (8 + line_size);
+        writer.write_all(&(offset as u64).to_le_bytes())?;
+    }
+
+    let mut line = Vec::with_capacity(line_size);
+    for y in 0..height {
+        line.clear();
+        for channel in [2, 1, 0] {
+            for x in 0..width {
+                let pixel = image.pixel((x, y)).unwrap();
+                line.extend_from_slice(&pixel.channels()[channel].to_le_bytes());
+            }
+        }
+
+        writer.write_all(&(y as i32).to_le_bytes())?;
+        writer.write_all(&(line_size as i32).to_le_bytes())?;
+        writer.write_all(&line)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use picture::prelude::{ImgBuf, ImgViewMut, RGB};
+    use std::collections::HashMap;
+
+    /// Reads a little endian value of `N` bytes at `offset`, moving it past
+    /// the value.
+    fn take<const N: usize>(bytes: &[u8], offset: &mut usize) -> [u8; N] {
+        let value = bytes[*offset..*offset + N].try_into().unwrap();
+        *offset += N;
+        value
+    }
+
+    /// Reads a null terminated string at `offset`, moving it past the null.
+    fn take_str<'a>(bytes: &'a [u8], offset: &mut usize) -> &'a str {
+        let len = bytes[*offset..].iter().position(|&b| b == 0).unwrap();
+        let value = std::str::from_utf8(&bytes[*offset..*offset + len]).unwrap();
+        *offset += len + 1;
+        value
+    }
+
+    /// Decodes what [`write_exr`] writes, following the layout of the
+    /// OpenEXR specification, into the dimensions and the pixels of the
+    /// image, row by row.
+    fn decode(bytes: &[u8]) -> ((u32, u32), Vec<[f32; 3]>) {
+        let mut offset = 0;
+        assert_eq!(u32::from_le_bytes(take(bytes, &mut offset)), MAGIC);
+        assert_eq!(u32::from_le_bytes(take(bytes, &mut offset)), VERSION);
+
+        let mut attributes = HashMap::new();
+        loop {
+            let name = take_str(bytes, &mut offset);
+            if name.is_empty() {
+                break;
+            }
+
+            let kind = take_str(bytes, &mut offset);
+            let size = i32::from_le_bytes(take(bytes, &mut offset)) as usize;
+            attributes.insert(name, (kind, &bytes[offset..offset + size]));
+            offset += size;
+        }
+
+        assert_eq!(attributes["compression"], ("compression", &[0][..]));
+        let (kind, window) = attributes["dataWindow"];
+        assert_eq!(kind, "box2i");
+        let window: Vec<i32> = window
+            .chunks_exact(4)
+            .map(|value| i32::from_le_bytes(value.try_into().unwrap()))
+            .collect();
+        assert_eq!(window[..2], [0, 0]);
+        let (width, height) = ((window[2] + 1) as u32, (window[3] + 1) as u32);
+
+        let (kind, mut channels) = attributes["channels"];
+        assert_eq!(kind, "chlist");
+        let mut names = Vec::new();
+        while channels[0] != 0 {
+            let mut offset = 0;
+            names.push(take_str(channels, &mut offset));
+            assert_eq!(
+                i32::from_le_bytes(take(channels, &mut offset)),
+                PIXEL_TYPE_FLOAT
+            );
+            channels = &channels[offset + 12..];
+        }
+
+        let mut pixels = vec![[0.0; 3]; (width * height) as usize];
+        for y in 0..height as usize {
+            let mut chunk = u64::from_le_bytes(take(bytes, &mut (offset + y * 8))) as usize;
+            assert_eq!(i32::from_le_bytes(take(bytes, &mut chunk)), y as i32);
+            let size = i32::from_le_bytes(take(bytes, &mut chunk)) as usize;
+            assert_eq!(size, width as usize * names.len() * 4);
+
+            // every channel of the scanline, one after the other
+            for name in &names {
+                let channel = ["R", "G", "B"].iter().position(|c| c == name).unwrap();
+                for x in 0..width as usize {
+                    let value = f32::from_le_bytes(take(bytes, &mut chunk));
+                    pixels[y * width as usize + x][channel] = value;
+                }
+            }
+        }
+
+        ((width, height), pixels)
+    }
+
+    #[test]
+    fn written_images_decode_to_the_same_pixels() {
+        let (width, height) = (7, 3);
+        let value = |x: u32, y: u32, channel: u32| {
+            // negative, huge and tiny values survive too, unlike in LDR files
+            (x as f32 - 2.0) * 10f32.powi(y as i32 * 3 - 3) + channel as f32 * 0.25
+        };
+
+        let mut image = ImgBuf::<RGB<f32>, Vec<_>>::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let pixel = image.pixel_mut((x, y)).unwrap();
+                *pixel = RGB::new(value(x, y, 0), value(x, y, 1), value(x, y, 2));
+            }
+        }
+
+        let mut bytes = Vec::new();
+        write_exr(&mut bytes, &image).unwrap();
+
+        let (dimensions, pixels) = decode(&bytes);
+        assert_eq!(dimensions, (width, height));
+        for y in 0..height {
+            for x in 0..width {
+                let expected = [0, 1, 2].map(|channel| value(x, y, channel));
+                assert_eq!(pixels[(y * width + x) as usize], expected, "at ({x}, {y})");
+            }
+        }
+    }
+
+    #[test]
+    fn empty_images_are_rejected() {
+        let image = ImgBuf::<RGB<f32>, Vec<_>>::new(0, 4);
+        let mut bytes = Vec::new();
+        let err = write_exr(&mut bytes, &image).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(bytes.is_empty());
+    }
+}
@@ -0,0 +1,60 @@
+//! A Radiance HDR (`.hdr`) writer, using the RGBE format without run-length
+//! encoding.
+
+use crate::Vec3;
+use picture::prelude::{ImgView, Pixel};
+use std::io::Write;
+
+/// Converts linear radiance to the shared exponent format, where the three
+/// channels are mantissas of the same power of two.
+#[inline(always)]
+fn vec3_to_rgbe(value: Vec3) -> [u8; 4] {
+    // NaNs become zero, and infinities the brightest value there is
+    let value = Vec3::from_array(value.to_array().map(|c| {
+        if c.is_nan() {
+            0.0
+        } else {
+            c.clamp(0.0, f32::MAX)
+        }
+    }));
+    let max = value.max_element();
+    if max < 1e-32 {
+        return [0; 4];
+    }
+
+    // max = mantissa * 2^exponent, with the mantissa in [0.5, 1). values
+    // past the largest exponent saturate their mantissas instead
+    let mut exponent = (max.log2().floor() as i32 + 1).min(127);
+    if exponent < 127 && max / 2f32.powi(exponent) >= 1.0 {
+        exponent += 1;
+    }
+
+    let scale = 256.0 / 2f32.powi(exponent);
+    let [r, g, b] = value.to_array().map(|c| (c * scale).min(255.0) as u8);
+    [r, g, b, (exponent + 128) as u8]
+}
+
+/// Writes `image`, whose channels are linear RGB, to `writer` as a Radiance
+/// HDR file.
+pub fn write_hdr<I, P>(mut writer: impl Write, image: &I) -> std::io::Result<()>
+where
+    I: ImgView<Pixel = P>,
+    P: Pixel<Channels = [f32; 3]>,
+{
+    let (width, height) = image.dimensions();
+    writer.write_all(b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n")?;
+    writer.write_all(format!("-Y {height} +X {width}\n").as_bytes())?;
+
+    let mut scanline = Vec::with_capacity(width as usize * 4);
+    for y in 0..height {
+        scanline.clear();
+        for x in 0..width {
+            let pixel = image.pixel((x, y)).unwrap();
+            scanline.extend_from_slice(&vec3_to_rgbe(Vec3::from(*pixel.channels())));
+        }
+
+        writer.write_all(&scanline)?;
+    }
+
+    Ok(())
+}
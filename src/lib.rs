@@ -1,9 +1,21 @@
+pub mod accel;
+pub mod background;
 pub mod common;
+pub mod denoise;
+mod distribution;
+pub mod filter;
+pub mod io;
 pub mod light;
 pub mod material;
 pub mod object;
 pub mod render;
+pub mod sampler;
+pub mod scene;
 pub mod shape;
+pub mod solver;
+pub mod stats;
+pub mod texture;
+pub mod tonemap;
 
 pub use glam::Vec3A as Vec3;
 pub const EPSILON: f32 = 0.0001;
@@ -1,5 +1,22 @@
 use crate::Vec3;
-use crate::{common::Color, shape::Shape};
+use crate::{common::Color, render::luminance, shape::Shape};
+use enum_dispatch::enum_dispatch;
+
+/// How the intensity of a [`Light`] falls off with the distance to it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Falloff {
+    /// The intensity is the same at every distance.
+    #[default]
+    None,
+    /// The intensity is divided by the square of the distance, so it's the
+    /// [intensity of the light](Light::intensity) at a distance of one.
+    InverseSquare,
+}
 
 /// A light in a scene.
 pub struct Light {
@@ -9,22 +26,75 @@ pub struct Light {
     pub color: Color,
     /// The intensity of this light.
     pub intensity: f32,
+    /// How the intensity of this light falls off with the distance to its
+    /// surface.
+    pub falloff: Falloff,
+    /// Whether this light is part of the scene at all. Disabled lights are
+    /// skipped entirely: they neither light anything nor can be seen.
+    pub enabled: bool,
 }
 
 impl Light {
+    /// Creates a new, enabled [`Light`] whose intensity doesn't fall off.
     pub fn new(shape: Shape, color: Color, intensity: f32) -> Self {
         Self {
             shape,
             color,
             intensity,
+            falloff: Falloff::None,
+            enabled: true,
         }
     }
 
+    /// Returns this light with its intensity falling off as given.
+    #[inline(always)]
+    pub fn with_falloff(self, falloff: Falloff) -> Self {
+        Self { falloff, ..self }
+    }
+
+    /// Returns this light [enabled](Light::enabled) or not.
+    #[inline(always)]
+    pub fn with_enabled(self, enabled: bool) -> Self {
+        Self { enabled, ..self }
+    }
+
+    /// The total amount of light emitted by this light, from its whole
+    /// surface: its luminance integrated over its area and over the
+    /// hemisphere of every point. Zero if it's disabled, if its shape can't
+    /// be sampled, or if its intensity or color make the power negative or
+    /// not finite. Lights are sampled in proportion to their power.
+    ///
+    /// The [falloff](Light::falloff) isn't taken into account, so this is
+    /// the power at a distance of one for [`Falloff::InverseSquare`].
     #[inline(always)]
-    pub fn light_ray(&self) -> LightRay {
+    pub fn power(&self) -> f32 {
+        let power = match self.shape.area() {
+            Some(area) if self.enabled => {
+                std::f32::consts::PI * area * luminance(self.color.to_vec3()) * self.intensity
+            }
+            _ => 0.0,
+        };
+
+        // such lights can't be picked by power
+        if power.is_finite() && power > 0.0 {
+            power
+        } else {
+            0.0
+        }
+    }
+
+    /// The light arriving from a point of the surface of this light that is
+    /// `distance` away.
+    #[inline(always)]
+    pub fn light_ray_at(&self, distance: f32) -> LightRay {
+        let intensity = match self.falloff {
+            Falloff::None => self.intensity,
+            Falloff::InverseSquare => self.intensity / (distance * distance),
+        };
+
         LightRay {
             color: self.color,
-            intensity: self.intensity,
+            intensity,
         }
     }
 }
@@ -36,8 +106,341 @@ pub struct LightRay {
 }
 
 impl LightRay {
+    /// A light ray carrying no light at all.
+    pub const ZERO: Self = Self {
+        color: Color::BLACK,
+        intensity: 0.0,
+    };
+
+    /// Creates a [`LightRay`] from a sample, i.e. the color of the light
+    /// already multiplied by its intensity.
+    ///
+    /// # Panics
+    /// Panics if any of the channels is negative.
+    #[inline(always)]
+    pub fn from_sample(sample: Vec3) -> Self {
+        let intensity = sample.max_element();
+        if intensity <= 0.0 {
+            return Self::ZERO;
+        }
+
+        let color = sample / intensity;
+        Self {
+            color: Color::new(color.x, color.y, color.z),
+            intensity,
+        }
+    }
+
     #[inline(always)]
     pub fn to_sample(&self) -> Vec3 {
         self.color.to_vec3() * self.intensity
     }
 }
+
+/// Light arriving at a point from a [`DeltaLight`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Incident {
+    /// The direction from the point towards the light. Normalized.
+    pub direction: Vec3,
+    /// The distance from the point to the light. Infinite for lights that
+    /// are infinitely far away.
+    pub distance: f32,
+    /// The light arriving at the point, ignoring occlusion.
+    pub light: LightRay,
+}
+
+/// Trait for lights that illuminate points from a single direction.
+#[enum_dispatch]
+pub trait Illuminate {
+    /// The light arriving at `point` from this light, if any.
+    fn incident(&self, point: Vec3) -> Option<Incident>;
+}
+
+/// A light infinitely small, emitting the same amount of light in every
+/// direction from a single point.
+pub struct PointLight {
+    pub position: Vec3,
+    pub color: Color,
+    pub intensity: f32,
+    /// Whether this light is part of the scene at all. See
+    /// [`Light::enabled`].
+    pub enabled: bool,
+}
+
+impl PointLight {
+    /// Creates a new, enabled [`PointLight`].
+    #[inline(always)]
+    pub fn new(position: Vec3, color: Color, intensity: f32) -> Self {
+        Self {
+            position,
+            color,
+            intensity,
+            enabled: true,
+        }
+    }
+}
+
+impl Illuminate for PointLight {
+    fn incident(&self, point: Vec3) -> Option<Incident> {
+        let to_light = self.position - point;
+        let distance_sq = to_light.length_squared();
+        let direction = to_light.try_normalize()?;
+
+        // inverse-square falloff
+        Some(Incident {
+            direction,
+            distance: distance_sq.sqrt(),
+            light: LightRay {
+                color: self.color,
+                intensity: self.intensity / distance_sq,
+            },
+        })
+    }
+}
+
+/// A light infinitely far away, whose rays are all parallel and don't fall
+/// off with distance, like the sun.
+pub struct DirectionalLight {
+    /// The direction the light travels in. Normalized.
+    direction: Vec3,
+    pub color: Color,
+    pub intensity: f32,
+    /// Whether this light is part of the scene at all. See
+    /// [`Light::enabled`].
+    pub enabled: bool,
+}
+
+impl DirectionalLight {
+    /// Creates a new, enabled [`DirectionalLight`] whose light travels in
+    /// the given direction.
+    ///
+    /// # Panics
+    /// Panics if `direction` is not normalized.
+    #[inline(always)]
+    pub fn new(direction: Vec3, color: Color, intensity: f32) -> Self {
+        assert!(direction.is_normalized());
+        Self {
+            direction,
+            color,
+            intensity,
+            enabled: true,
+        }
+    }
+
+    /// The direction the light travels in.
+    #[inline(always)]
+    pub fn direction(&self) -> Vec3 {
+        self.direction
+    }
+}
+
+impl Illuminate for DirectionalLight {
+    #[inline(always)]
+    fn incident(&self, _: Vec3) -> Option<Incident> {
+        Some(Incident {
+            direction: -self.direction,
+            distance: f32::INFINITY,
+            light: LightRay {
+                color: self.color,
+                intensity: self.intensity,
+            },
+        })
+    }
+}
+
+/// A point light that only emits light inside a cone, fading out smoothly
+/// between an inner and an outer angle.
+pub struct SpotLight {
+    position: Vec3,
+    /// The direction the cone points in. Normalized.
+    direction: Vec3,
+    pub color: Color,
+    pub intensity: f32,
+    /// The cosine of the half-angle inside of which the light is at full
+    /// intensity.
+    cos_inner: f32,
+    /// The cosine of the half-angle outside of which there's no light.
+    cos_outer: f32,
+    /// Whether this light is part of the scene at all. See
+    /// [`Light::enabled`].
+    pub enabled: bool,
+}
+
+impl SpotLight {
+    /// Creates a new, enabled [`SpotLight`]. The angles are half-angles of the cone,
+    /// measured from `direction`, in radians.
+    ///
+    /// # Panics
+    /// Panics if `direction` is not normalized, if any of the angles is not
+    /// in the \[0..π) range or if `inner_angle` is bigger than `outer_angle`.
+    pub fn new(
+        position: Vec3,
+        direction: Vec3,
+        color: Color,
+        intensity: f32,
+        inner_angle: f32,
+        outer_angle: f32,
+    ) -> Self {
+        assert!(direction.is_normalized());
+        assert!((0.0..std::f32::consts::PI).contains(&inner_angle));
+        assert!((0.0..std::f32::consts::PI).contains(&outer_angle));
+        assert!(inner_angle <= outer_angle);
+
+        Self {
+            position,
+            direction,
+            color,
+            intensity,
+            cos_inner: inner_angle.cos(),
+            cos_outer: outer_angle.cos(),
+            enabled: true,
+        }
+    }
+
+    #[inline(always)]
+    pub fn position(&self) -> Vec3 {
+        self.position
+    }
+
+    #[inline(always)]
+    pub fn direction(&self) -> Vec3 {
+        self.direction
+    }
+
+    /// How much of the light's intensity goes in the given direction, from
+    /// zero to one.
+    #[inline(always)]
+    fn falloff(&self, direction: Vec3) -> f32 {
+        let cos = self.direction.dot(direction);
+        if cos >= self.cos_inner {
+            return 1.0;
+        }
+
+        if cos <= self.cos_outer {
+            return 0.0;
+        }
+
+        // smoothstep
+        let x = (cos - self.cos_outer) / (self.cos_inner - self.cos_outer);
+        x * x * (3.0 - 2.0 * x)
+    }
+}
+
+impl Illuminate for SpotLight {
+    fn incident(&self, point: Vec3) -> Option<Incident> {
+        let to_light = self.position - point;
+        let distance_sq = to_light.length_squared();
+        let direction = to_light.try_normalize()?;
+
+        let falloff = self.falloff(-direction);
+        if falloff <= 0.0 {
+            return None;
+        }
+
+        Some(Incident {
+            direction,
+            distance: distance_sq.sqrt(),
+            light: LightRay {
+                color: self.color,
+                intensity: self.intensity * falloff / distance_sq,
+            },
+        })
+    }
+}
+
+/// A light whose light comes from a single direction, also known as a
+/// delta light.
+///
+/// Unlike [`Light`]s, these have no shape: rays can never hit them, so
+/// they only contribute to the image through the direct lighting of
+/// diffuse surfaces. Both kinds of lights can be used in the same scene,
+/// and their contributions simply add up.
+///
+/// Like [`Light`]s, they can be [disabled](DeltaLight::enabled) and linked
+/// to objects, see [`Object::light_mask`](crate::object::Object::light_mask).
+#[enum_dispatch(Illuminate)]
+pub enum DeltaLight {
+    Point(PointLight),
+    Directional(DirectionalLight),
+    Spot(SpotLight),
+}
+
+impl DeltaLight {
+    /// Whether this light is part of the scene at all. Disabled lights
+    /// don't light anything.
+    #[inline(always)]
+    pub fn enabled(&self) -> bool {
+        match self {
+            DeltaLight::Point(light) => light.enabled,
+            DeltaLight::Directional(light) => light.enabled,
+            DeltaLight::Spot(light) => light.enabled,
+        }
+    }
+
+    /// Returns this light [enabled](DeltaLight::enabled) or not.
+    #[inline(always)]
+    pub fn with_enabled(mut self, enabled: bool) -> Self {
+        match &mut self {
+            DeltaLight::Point(light) => light.enabled = enabled,
+            DeltaLight::Directional(light) => light.enabled = enabled,
+            DeltaLight::Spot(light) => light.enabled = enabled,
+        }
+
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shape::Sphere;
+
+    #[test]
+    fn spot_lights_are_finite_at_the_edge_of_their_cone() {
+        for (inner, outer) in [(0.5, 0.5), (0.25, 0.5), (0.0, 0.0)] {
+            let light = SpotLight::new(
+                Vec3::ZERO,
+                -Vec3::Y,
+                Color::new(1.0, 1.0, 1.0),
+                1.0,
+                inner,
+                outer,
+            );
+
+            // exactly at both angles from the direction of the light
+            for angle in [inner, outer] {
+                let (sin, cos) = f32::sin_cos(angle);
+                let falloff = light.falloff(Vec3::new(sin, -cos, 0.0));
+                assert!((0.0..=1.0).contains(&falloff), "{falloff} at {angle}");
+            }
+
+            let incident = light.incident(Vec3::new(outer.sin(), -outer.cos(), 0.0));
+            assert!(incident.is_none_or(|incident| incident.light.intensity.is_finite()));
+        }
+    }
+
+    #[test]
+    fn inverse_square_lights_fall_off_with_distance() {
+        let color = Color::new(1.0, 0.5, 0.25);
+        let sphere = || {
+            Sphere {
+                center: Vec3::ZERO,
+                radius: 1.0,
+            }
+            .into()
+        };
+
+        let light = Light::new(sphere(), color, 8.0).with_falloff(Falloff::InverseSquare);
+        let at = |distance| light.light_ray_at(distance).intensity;
+        assert_eq!(at(1.0), 8.0);
+        assert_eq!(at(2.0), at(1.0) / 4.0);
+        assert_eq!(at(4.0), at(1.0) / 16.0);
+        assert_eq!(at(0.5), at(1.0) * 4.0);
+        assert_eq!(light.light_ray_at(3.0).color, color);
+
+        let light = Light::new(sphere(), color, 8.0);
+        for distance in [0.5, 1.0, 2.0, 100.0] {
+            assert_eq!(light.light_ray_at(distance).intensity, 8.0);
+        }
+    }
+}
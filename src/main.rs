@@ -1,15 +1,19 @@
 use pathtracer::{
+    background::Background,
     common::Color,
+    filter::Filter,
     light::{Light, LightRay},
     material::Simple,
-    object::Object,
-    render::{Camera, Renderer, Scene},
-    shape::{Plane, Shape, Sphere},
+    render::{Aovs, Camera, RenderOptions, Renderer, Scene},
+    sampler::SamplerKind,
+    shape::{MovingSphere, Shape, Sphere},
+    texture::{Checker, SolidColor},
+    tonemap::{Encoding, Exposure, ToneMap},
     *,
 };
 use picture::{
     formats::png::PngEncoder,
-    prelude::{ImgBuf, RGB, RGB8},
+    prelude::{ImgBuf, RGB, RGB8, RGBA8},
 };
 use rayon::prelude::{IntoParallelIterator, ParallelIterator};
 use std::{io::Write, sync::Arc, time::Duration};
@@ -33,49 +37,53 @@ fn render_anim(duration: Duration, fps: f32) {
 
     // materials
     let material_red = Arc::new(Simple {
-        color: Color::new(1.0, 0.0, 0.0),
+        albedo: Box::new(SolidColor(Color::new(1.0, 0.0, 0.0))),
         diffuse: 1.0,
         fuzzyness: 0.0,
     });
 
     let material_green = Arc::new(Simple {
-        color: Color::new(0.0, 1.0, 0.0),
+        albedo: Box::new(SolidColor(Color::new(0.0, 1.0, 0.0))),
         diffuse: 1.0,
         fuzzyness: 0.0,
     });
 
-    let material_blue = Arc::new(Simple {
-        color: Color::new(0.2, 0.2, 1.0),
+    let material_floor = Arc::new(Simple {
+        albedo: Box::new(Checker {
+            a: Color::new(0.2, 0.2, 1.0),
+            b: Color::new(0.9, 0.9, 0.9),
+            scale: 1.0,
+        }),
         diffuse: 0.3,
         fuzzyness: 0.05,
     });
 
     let material_white = Arc::new(Simple {
-        color: Color::new(1.0, 1.0, 1.0),
+        albedo: Box::new(SolidColor(Color::new(1.0, 1.0, 1.0))),
         diffuse: 1.0,
         fuzzyness: 0.0,
     });
 
     let material_mirror = Arc::new(Simple {
-        color: Color::new(1.0, 1.0, 1.0),
+        albedo: Box::new(SolidColor(Color::new(1.0, 1.0, 1.0))),
         diffuse: 0.0,
         fuzzyness: 0.0,
     });
 
     let material_mirror_fuzzy = Arc::new(Simple {
-        color: Color::new(0.5, 0.8, 0.8),
+        albedo: Box::new(SolidColor(Color::new(0.5, 0.8, 0.8))),
         diffuse: 0.2,
         fuzzyness: 0.08,
     });
 
     let material_pink = Arc::new(Simple {
-        color: Color::new(0.94, 0.3, 0.85),
+        albedo: Box::new(SolidColor(Color::new(0.94, 0.3, 0.85))),
         diffuse: 0.5,
         fuzzyness: 0.02,
     });
 
     let material_black = Arc::new(Simple {
-        color: Color::new(0.05, 0.05, 0.05),
+        albedo: Box::new(SolidColor(Color::new(0.05, 0.05, 0.05))),
         diffuse: 1.0,
         fuzzyness: 0.0,
     });
@@ -91,137 +99,151 @@ fn render_anim(duration: Duration, fps: f32) {
             1.0,
         );
 
-        // box
-        let floor = Object {
-            shape: Shape::from(Plane::new(Vec3::new(0.0, -5.0, 0.0), Vec3::Y)),
-            material: material_blue.clone(),
+        // floating spheres, moving during the frame
+        let phase = |time: f32| 2.0 * std::f32::consts::PI * time / duration_secs;
+        let moving = |center: &dyn Fn(f32) -> Vec3, radius: f32| MovingSphere {
+            center0: center(time),
+            center1: center(time + frame_time),
+            radius,
         };
 
-        let wall_left = Object {
-            shape: Shape::from(Plane::new(Vec3::new(-5.0, 0.0, 0.0), Vec3::X)),
-            material: material_red.clone(),
-        };
-
-        let wall_right = Object {
-            shape: Shape::from(Plane::new(Vec3::new(5.0, 0.0, 0.0), -Vec3::X)),
-            material: material_green.clone(),
-        };
-
-        let wall_front = Object {
-            shape: Shape::from(Plane::new(Vec3::new(0.0, 0.0, 5.0), -Vec3::Z)),
-            material: material_white.clone(),
-        };
-
-        let ceil = Object {
-            shape: Shape::from(Plane::new(Vec3::new(0.0, 5.0, 0.0), -Vec3::Y)),
-            material: material_mirror_fuzzy.clone(),
-        };
-
-        // floating spheres
-        let sphere_mirror = Object {
-            shape: Shape::from(Sphere {
-                center: Vec3::new(
-                    3.0 * (2.0 * std::f32::consts::PI * time / duration_secs
-                        + 3.0 * std::f32::consts::PI / 2.0)
-                        .sin(),
-                    -3.0,
-                    -3.0,
-                ),
-                radius: 1.0,
-            }),
-            material: material_mirror.clone(),
-        };
-
-        let sphere_pink = Object {
-            shape: Shape::from(Sphere {
-                center: Vec3::new(
-                    3.0,
-                    3.0 * (2.0 * std::f32::consts::PI * time / duration_secs
-                        + 3.0 * std::f32::consts::PI / 2.0)
-                        .sin(),
-                    3.0,
+        let scene = Scene::builder()
+            .camera(camera)
+            // box
+            .plane(Vec3::new(0.0, -5.0, 0.0), Vec3::Y, material_floor.clone())
+            .plane(Vec3::new(-5.0, 0.0, 0.0), Vec3::X, material_red.clone())
+            .plane(Vec3::new(5.0, 0.0, 0.0), -Vec3::X, material_green.clone())
+            .plane(Vec3::new(0.0, 0.0, 5.0), -Vec3::Z, material_white.clone())
+            .plane(
+                Vec3::new(0.0, 5.0, 0.0),
+                -Vec3::Y,
+                material_mirror_fuzzy.clone(),
+            )
+            .shape(
+                moving(
+                    &|time| {
+                        Vec3::new(
+                            3.0 * (phase(time) + 3.0 * std::f32::consts::PI / 2.0).sin(),
+                            -3.0,
+                            -3.0,
+                        )
+                    },
+                    1.0,
                 ),
-                radius: 2.0,
-            }),
-            material: material_pink.clone(),
-        };
-
-        let sphere_black_a = Object {
-            shape: Shape::from(Sphere {
-                center: Vec3::new(
-                    2.0 * (2.0 * std::f32::consts::PI * time / duration_secs).sin(),
-                    3.5,
-                    2.0 * (2.0 * std::f32::consts::PI * time / duration_secs).cos(),
+                material_mirror.clone(),
+            )
+            .shape(
+                moving(
+                    &|time| {
+                        Vec3::new(
+                            3.0,
+                            3.0 * (phase(time) + 3.0 * std::f32::consts::PI / 2.0).sin(),
+                            3.0,
+                        )
+                    },
+                    2.0,
                 ),
-                radius: 0.5,
-            }),
-            material: material_black.clone(),
-        };
-
-        let sphere_black_b = Object {
-            shape: Shape::from(Sphere {
-                center: Vec3::new(
-                    -2.0 * (2.0 * std::f32::consts::PI * time / duration_secs).sin(),
-                    -3.5,
-                    -2.0 * (2.0 * std::f32::consts::PI * time / duration_secs).cos(),
+                material_pink.clone(),
+            )
+            .shape(
+                moving(
+                    &|time| Vec3::new(2.0 * phase(time).sin(), 3.5, 2.0 * phase(time).cos()),
+                    0.5,
                 ),
-                radius: 0.5,
-            }),
-            material: material_black.clone(),
-        };
-
-        // light sphere
-        let light = Light::new(
-            Shape::from(Sphere {
-                center: Vec3::new(
-                    0.0,
-                    1.0 * (2.0 * std::f32::consts::PI * time / duration_secs).sin(),
-                    0.0,
+                material_black.clone(),
+            )
+            .shape(
+                moving(
+                    &|time| Vec3::new(-2.0 * phase(time).sin(), -3.5, -2.0 * phase(time).cos()),
+                    0.5,
                 ),
-                radius: 2.0,
-            }),
-            Color::new(1.0, 1.0, 1.0),
-            2048.0,
-        );
-
-        let scene = Scene {
-            camera,
-            objects: vec![
-                floor,
-                wall_left,
-                wall_right,
-                wall_front,
-                ceil,
-                sphere_mirror,
-                sphere_pink,
-                sphere_black_a,
-                sphere_black_b,
-            ],
-            lights: vec![light],
-        };
+                material_black.clone(),
+            )
+            // light sphere
+            .add_light(Light::new(
+                Shape::from(Sphere {
+                    center: Vec3::new(0.0, 1.0 * phase(time).sin(), 0.0),
+                    radius: 2.0,
+                }),
+                Color::new(1.0, 1.0, 1.0),
+                2048.0,
+            ))
+            .build()
+            .build();
 
         let mut buffer = ImgBuf::<RGB<f32>, Vec<_>>::new(512, 512);
-        Renderer {
+        let renderer = Renderer {
             sample_count: 128,
             indirect_count: 4,
-            max_value: 1024.0,
-            ambient_light: LightRay {
+            exposure: Exposure::Manual { max_value: 1024.0 },
+            background: Background::solid(LightRay {
                 color: Color::new(0.0, 0.0, 0.0),
                 intensity: 0.0,
-            },
-        }
-        .render(&scene, &mut buffer);
+            }),
+            rr_min_depth: 2,
+            tone_map: ToneMap::Clamp,
+            exposure_compensation: 0.0,
+            sample_clamp: None,
+            indirect_clamp: None,
+            seed: frame as u64,
+            tile_size: 32,
+            parallel: true,
+            sampler: SamplerKind::Independent,
+            stratified: true,
+            transparent_background: false,
+            region: None,
+            motion_blur: true,
+            filter: Filter::Box,
+            adaptive: None,
+            encoding: Encoding::Linear,
+        };
+        renderer.render(&scene, &mut buffer);
 
         let result = buffer.map_vec(|x| {
-            RGB8::new(
-                (x.r.sqrt() * 255.0) as u8,
-                (x.g.sqrt() * 255.0) as u8,
-                (x.b.sqrt() * 255.0) as u8,
-            )
+            let [r, g, b] = Color::new(x.r, x.g, x.b).to_srgb8();
+            RGB8::new(r, g, b)
         });
 
         let encoded = PngEncoder::default().encode(result).unwrap();
         let mut f = std::fs::File::create(format!("{frame}.png")).unwrap();
         f.write_all(&encoded[..]).unwrap();
+
+        // the floating spheres alone, over a transparent background
+        let mut scene = scene.into_inner();
+        scene.objects.drain(..5);
+        let scene = scene.build();
+        let renderer = Renderer {
+            transparent_background: true,
+            ..renderer
+        };
+
+        let mut buffer = ImgBuf::<RGB<f32>, Vec<_>>::new(512, 512);
+        let mut alpha = Vec::new();
+        let options = RenderOptions {
+            aovs: Some(Aovs {
+                alpha: Some(&mut alpha),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        renderer.render_with(&scene, &mut buffer, options);
+
+        // the colors are premultiplied by alpha, but the ones of PNGs aren't
+        let mut alpha = alpha.into_iter();
+        let result = buffer.map_vec(|x| {
+            let alpha = alpha.next().unwrap();
+            let color = if alpha > 0.0 {
+                (Vec3::new(x.r, x.g, x.b) / alpha).min(Vec3::ONE)
+            } else {
+                Vec3::ZERO
+            };
+
+            let [r, g, b] = Color::new(color.x, color.y, color.z).to_srgb8();
+            RGBA8::new(r, g, b, (alpha * 255.0).round() as u8)
+        });
+
+        let encoded = PngEncoder::default().encode(result).unwrap();
+        let mut f = std::fs::File::create(format!("{frame}_spheres.png")).unwrap();
+        f.write_all(&encoded[..]).unwrap();
     });
 }
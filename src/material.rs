@@ -1,61 +1,303 @@
-use rand::{
-    rngs::{SmallRng, ThreadRng},
-    Rng, SeedableRng,
-};
+use rand::Rng;
 
 use crate::{
     common::{Color, Ray},
+    light::LightRay,
+    sampler::Sampler,
+    shape::Intersection,
+    texture::Texture,
     Vec3,
 };
 
 /// A material. Dictates how light scatters off of [objects](Object) made
 /// of it.
-pub trait Material {
-    /// Scatters a ray from the given point and normal.
-    fn scatter(&self, ray: Ray, point: Vec3, normal: Vec3) -> Ray;
-    fn color(&self, point: Vec3, normal: Vec3) -> Color;
+///
+/// Materials are shared between the threads rendering a scene, so they must
+/// be [`Send`] and [`Sync`].
+pub trait Material: Send + Sync {
+    /// Scatters a ray that hit a surface made of this material at the given
+    /// intersection, or returns [`None`] if the ray was absorbed.
+    ///
+    /// All randomness must come from `rng`, which is owned by the renderer,
+    /// so that renders with the same seed are reproducible.
+    fn scatter(&self, ray: Ray, intersection: &Intersection, rng: &mut Sampler) -> Option<Scatter>;
+    /// The probability density, per unit solid angle, of
+    /// [`Material::scatter`] scattering a ray travelling along `incoming`
+    /// into the direction `outgoing`, at a surface with the given shading
+    /// normal, which faces against `incoming`. Every direction is
+    /// normalized.
+    ///
+    /// The renderer uses this to weight the light found by
+    /// [diffuse](Scatter::diffuse) bounces against the light it samples
+    /// directly.
+    ///
+    /// Directions that are only scattered into by ideal specular
+    /// reflections or refractions, like those of mirrors or glass, have no
+    /// density: materials return [`DELTA_PDF`] for them. Defaults to the
+    /// density of directions uniformly distributed over the hemisphere
+    /// around `normal`.
+    #[inline(always)]
+    fn pdf(&self, _incoming: Vec3, outgoing: Vec3, normal: Vec3) -> f32 {
+        if normal.dot(outgoing) > 0.0 {
+            1.0 / (2.0 * std::f32::consts::PI)
+        } else {
+            0.0
+        }
+    }
+    /// The base color of this material at the given intersection.
+    fn color(&self, intersection: &Intersection) -> Color;
+    /// The light emitted by this material at the given intersection. Most
+    /// materials don't emit any light, which is the default.
+    #[inline(always)]
+    fn emitted(&self, _intersection: &Intersection) -> LightRay {
+        LightRay::ZERO
+    }
+}
+
+/// The [density](Material::pdf) of directions that are only scattered into
+/// by ideal specular reflections or refractions, which can't be sampled any
+/// other way. The renderer never weights the light found along them against
+/// the light it samples directly, so all of it counts.
+pub const DELTA_PDF: f32 = 0.0;
+
+/// A ray scattered off of a [`Material`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Scatter {
+    /// The scattered ray.
+    pub ray: Ray,
+    /// The attenuation that should be multiplied into the path.
+    pub attenuation: Color,
+    /// Whether the ray was scattered by an ideal diffuse (lambertian)
+    /// reflection, with `attenuation` as its albedo. Light reaching these
+    /// bounces is sampled directly by the renderer.
+    pub diffuse: bool,
+}
+
+/// The point of the unit disk a pair of uniformly distributed numbers in the
+/// \[0..1) range maps to, such that the points are uniformly distributed
+/// too. Uses the concentric mapping, which keeps nearby pairs nearby, so
+/// stratified pairs stay stratified.
+#[inline(always)]
+fn concentric_disk((u, v): (f32, f32)) -> (f32, f32) {
+    let (x, y) = (2.0 * u - 1.0, 2.0 * v - 1.0);
+    if x == 0.0 && y == 0.0 {
+        return (0.0, 0.0);
+    }
+
+    let (r, theta) = if x.abs() > y.abs() {
+        (x, std::f32::consts::FRAC_PI_4 * (y / x))
+    } else {
+        (
+            y,
+            std::f32::consts::FRAC_PI_2 - std::f32::consts::FRAC_PI_4 * (x / y),
+        )
+    };
+
+    (r * theta.cos(), r * theta.sin())
+}
+
+/// The direction of the hemisphere around `normal` a pair of uniformly
+/// distributed numbers in the \[0..1) range maps to, such that the
+/// directions are distributed with density `cos(theta) / pi`, where `theta`
+/// is the angle to `normal`.
+///
+/// This is Malley's method: points uniformly distributed on the unit disk
+/// are projected up onto the hemisphere.
+#[inline(always)]
+fn cosine_hemisphere(normal: Vec3, pair: (f32, f32)) -> Vec3 {
+    let (x, y) = concentric_disk(pair);
+    let z = (1.0 - x * x - y * y).max(0.0).sqrt();
+    let (tangent, bitangent) = normal.any_orthonormal_pair();
+
+    (x * tangent + y * bitangent + z * normal).normalize()
+}
+
+/// A uniformly distributed random unit vector.
+///
+/// This always draws exactly two numbers from `rng`, which keeps the
+/// dimensions of a [low-discrepancy sampler](crate::sampler::SamplerKind)
+/// lined up between samples.
+#[inline(always)]
+fn random_unit_vector(rng: &mut Sampler) -> Vec3 {
+    let z: f32 = rng.gen_range(-1.0..1.0);
+    let phi = rng.gen_range(0.0..2.0 * std::f32::consts::PI);
+    let r = (1.0 - z * z).max(0.0).sqrt();
+
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
 }
 
 pub struct Simple {
-    pub color: Color,
+    /// The color of the surface, which attenuates scattered rays.
+    pub albedo: Box<dyn Texture>,
+    /// The probability of rays being scattered diffusely instead of being
+    /// reflected.
     pub diffuse: f32,
+    /// How much reflected rays are randomly perturbed.
     pub fuzzyness: f32,
 }
 
 impl Material for Simple {
-    fn scatter(&self, ray: Ray, point: Vec3, normal: Vec3) -> Ray {
-        let mut rng = SmallRng::from_rng(rand::thread_rng()).unwrap();
-        let random_vec_unit_sphere = |rng: &mut SmallRng| loop {
-            let v = Vec3::new(
-                rng.gen_range(-1.0..1.0),
-                rng.gen_range(-1.0..1.0),
-                rng.gen_range(-1.0..1.0),
-            );
-            if v.length_squared() < 1.0 {
-                return v.normalize();
-            }
-        };
-
+    fn scatter(&self, ray: Ray, intersection: &Intersection, rng: &mut Sampler) -> Option<Scatter> {
+        let Intersection { point, normal, .. } = *intersection;
+        let attenuation = self.color(intersection);
         if self.diffuse > rng.gen_range(0.0..1.0) {
-            // diffuse
-            let center = point + normal;
-            let random = random_vec_unit_sphere(&mut rng);
+            // diffuse, with the density of the lambertian brdf, so the albedo
+            // is all that attenuates the path. stratified, if the renderer
+            // stratifies samples
+            let dir = cosine_hemisphere(normal, rng.next_2d());
 
-            let dir = ((center + random) - point).normalize();
-
-            Ray::new(point, dir)
+            Some(Scatter {
+                ray: Ray::new(point, dir),
+                attenuation,
+                diffuse: true,
+            })
         } else {
             // reflection
             let factor = 2.0 * ray.direction().dot(normal);
             let dir = ray.direction() - factor * normal;
-            let fuzz = self.fuzzyness * random_vec_unit_sphere(&mut rng);
+            let fuzz = self.fuzzyness * random_unit_vector(rng);
+            let dir = (dir + fuzz).normalize();
+            if dir.dot(intersection.geometric_normal) <= 0.0 {
+                // fuzzed into the surface
+                return None;
+            }
+
+            Some(Scatter {
+                ray: Ray::new(point, dir),
+                attenuation,
+                diffuse: false,
+            })
+        }
+    }
+
+    /// Diffuse bounces are cosine distributed, and taken with a probability
+    /// of [`Simple::diffuse`]. Reflections are concentrated around a single
+    /// direction, so they add nothing to the density, which is
+    /// [`DELTA_PDF`] for perfect mirrors.
+    #[inline(always)]
+    fn pdf(&self, _: Vec3, outgoing: Vec3, normal: Vec3) -> f32 {
+        self.diffuse * normal.dot(outgoing).max(0.0) / std::f32::consts::PI
+    }
+
+    #[inline(always)]
+    fn color(&self, intersection: &Intersection) -> Color {
+        self.albedo
+            .sample(intersection.point, intersection.normal, intersection.uv)
+    }
+}
+
+/// A material that emits light and doesn't scatter any, turning the object
+/// made of it into a light source.
+pub struct Emissive {
+    pub color: Color,
+    pub intensity: f32,
+}
+
+impl Material for Emissive {
+    #[inline(always)]
+    fn scatter(&self, _: Ray, _: &Intersection, _: &mut Sampler) -> Option<Scatter> {
+        None
+    }
 
-            Ray::new(point, (dir + fuzz).normalize())
+    /// Nothing is ever scattered.
+    #[inline(always)]
+    fn pdf(&self, _: Vec3, _: Vec3, _: Vec3) -> f32 {
+        0.0
+    }
+
+    #[inline(always)]
+    fn color(&self, _: &Intersection) -> Color {
+        self.color
+    }
+
+    #[inline(always)]
+    fn emitted(&self, _: &Intersection) -> LightRay {
+        LightRay {
+            color: self.color,
+            intensity: self.intensity,
         }
     }
+}
 
+/// A material that scatters light equally in every direction. Meant to be
+/// used with [media](crate::shape::ConstantMedium), like fog or smoke.
+pub struct Isotropic {
+    pub color: Color,
+}
+
+impl Material for Isotropic {
+    fn scatter(&self, _: Ray, intersection: &Intersection, rng: &mut Sampler) -> Option<Scatter> {
+        let dir = random_unit_vector(rng);
+
+        Some(Scatter {
+            ray: Ray::new(intersection.point, dir),
+            attenuation: self.color,
+            diffuse: false,
+        })
+    }
+
+    /// Every direction of the sphere is equally likely.
     #[inline(always)]
-    fn color(&self, _: Vec3, _: Vec3) -> Color {
+    fn pdf(&self, _: Vec3, _: Vec3, _: Vec3) -> f32 {
+        1.0 / (4.0 * std::f32::consts::PI)
+    }
+
+    #[inline(always)]
+    fn color(&self, _: &Intersection) -> Color {
         self.color
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    #[test]
+    fn cosine_hemisphere_is_cosine_distributed() {
+        const SAMPLES: usize = 200_000;
+        const BINS: usize = 10;
+
+        let mut rng = SmallRng::seed_from_u64(0);
+        let normals = [Vec3::Z, -Vec3::Y, Vec3::new(1.0, 2.0, -3.0).normalize()];
+        for normal in normals {
+            let (tangent, _) = normal.any_orthonormal_pair();
+            let mut cos_bins = [0usize; BINS];
+            let mut quadrants = [0usize; 4];
+            let mut cos_sum = 0.0;
+            for _ in 0..SAMPLES {
+                let direction = cosine_hemisphere(normal, (rng.gen(), rng.gen()));
+                assert!((direction.length() - 1.0).abs() < 1e-4, "{direction}");
+
+                let cos = direction.dot(normal);
+                assert!(cos >= -1e-6, "{direction} is below {normal}");
+                cos_bins[((cos * BINS as f32) as usize).min(BINS - 1)] += 1;
+                cos_sum += cos as f64;
+
+                let side = direction.reject_from(normal);
+                let angle = side.cross(tangent).dot(normal).atan2(side.dot(tangent));
+                let quadrant = (angle + std::f32::consts::PI) / std::f32::consts::FRAC_PI_2;
+                quadrants[(quadrant as usize).min(3)] += 1;
+            }
+
+            // with density cos(theta) / pi, the fraction of directions with
+            // cos(theta) between a and b is b² - a²
+            for (bin, &count) in cos_bins.iter().enumerate() {
+                let (a, b) = (bin as f64 / BINS as f64, (bin + 1) as f64 / BINS as f64);
+                let expected = (b * b - a * a) * SAMPLES as f64;
+                let error = (count as f64 - expected).abs() / expected;
+                assert!(error < 0.05, "{normal}, bin {bin}: {count} vs {expected}");
+            }
+
+            // the mean of cos(theta) is 2/3
+            let mean = cos_sum / SAMPLES as f64;
+            assert!((mean - 2.0 / 3.0).abs() < 5e-3, "{normal}: {mean}");
+
+            // and every direction around the normal is equally likely
+            for (quadrant, &count) in quadrants.iter().enumerate() {
+                let error = (count as f64 / (SAMPLES / 4) as f64 - 1.0).abs();
+                assert!(error < 0.02, "{normal}, quadrant {quadrant}: {count}");
+            }
+        }
+    }
+}
@@ -6,4 +6,50 @@ use std::sync::Arc;
 pub struct Object {
     pub shape: Shape,
     pub material: Arc<dyn Material>,
+    /// The lights this object is lit by, as a bitmask over their indices in
+    /// [`Scene::lights`](crate::render::Scene::lights): bit `i` is set if
+    /// the `i`th light lights this object. [`None`] if every light does.
+    ///
+    /// [Delta lights](crate::light::DeltaLight) come right after them: the
+    /// `i`th one of
+    /// [`Scene::delta_lights`](crate::render::Scene::delta_lights) is linked
+    /// through bit `lights.len() + i`.
+    ///
+    /// Unlinked lights still light the object indirectly, through other
+    /// objects. Only the first 64 lights, counting delta lights, can be
+    /// linked.
+    pub light_mask: Option<u64>,
+}
+
+impl Object {
+    /// Creates a new [`Object`], lit by every light.
+    #[inline(always)]
+    pub fn new(shape: Shape, material: Arc<dyn Material>) -> Self {
+        Self {
+            shape,
+            material,
+            light_mask: None,
+        }
+    }
+
+    /// Returns this object lit only by the lights in `light_mask`. See
+    /// [`Object::light_mask`].
+    #[inline(always)]
+    pub fn with_light_mask(self, light_mask: u64) -> Self {
+        Self {
+            light_mask: Some(light_mask),
+            ..self
+        }
+    }
+
+    /// Whether the light at `index` in
+    /// [`Scene::lights`](crate::render::Scene::lights), or the delta light
+    /// at `index - lights.len()` in
+    /// [`Scene::delta_lights`](crate::render::Scene::delta_lights), lights
+    /// this object. See [`Object::light_mask`].
+    #[inline(always)]
+    pub fn is_lit_by(&self, index: usize) -> bool {
+        self.light_mask
+            .is_none_or(|mask| index < 64 && mask & (1 << index) != 0)
+    }
 }
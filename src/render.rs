@@ -1,14 +1,31 @@
 use crate::{
-    common::Ray,
-    light::{Light, LightRay},
+    background::Background,
+    common::{check_normalized, check_range, mix64, BuildError, Ray},
+    filter::Filter,
+    light::{Illuminate, Light, LightRay},
     object::Object,
+    sampler::{Sampler, SamplerKind},
     shape::{Intersect, Intersection},
-    Vec3,
+    stats::{self, RenderStats},
+    tonemap::{self, Encoding, Exposure, ToneMap},
+    Vec3, EPSILON,
 };
 use float_ord::FloatOrd;
 use picture::{prelude::Pixel, view::ImgViewMut};
+use rand::Rng;
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+use std::{
+    ops::Range,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+mod progressive;
 
-#[derive(Debug)]
+pub use crate::scene::{PreparedScene, Scene};
+pub use progressive::ProgressiveRenderer;
+
+#[derive(Clone, Copy, Debug)]
 pub struct ViewPlane {
     pub top_left: Vec3,
     pub top_right: Vec3,
@@ -16,16 +33,40 @@ pub struct ViewPlane {
     pub bottom_right: Vec3,
 }
 
+/// How a [`Camera`] projects the scene onto its view plane.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(tag = "type", rename_all = "snake_case")
+)]
+pub enum Projection {
+    /// Rays spread out from the position of the camera, so farther objects
+    /// look smaller. `fov` is the vertical field of view, in radians.
+    Perspective { fov: f32 },
+    /// Rays are all parallel to the direction of the camera, so objects
+    /// keep their size no matter how far they are. `scale` is the height of
+    /// the view rectangle.
+    Orthographic { scale: f32 },
+}
+
 /// A camera in space that can be used to render what it sees.
 pub struct Camera {
     /// The position of this camera in space.
     position: Vec3,
     /// The direction this camera is pointed in. Normalized.
     direction: Vec3,
-    /// The field of view of this camera, in radians.
-    fov: f32,
+    /// The direction considered to be up for this camera. Normalized.
+    up: Vec3,
+    /// The projection of this camera.
+    projection: Projection,
     /// The aspect ratio (width / height) of the view plane.
     aspect_ratio: f32,
+    /// The diameter of the lens of this camera. Zero for a pinhole camera,
+    /// where everything is in focus.
+    aperture: f32,
+    /// The distance from the camera to the plane that's in perfect focus.
+    focus_distance: f32,
 }
 
 impl Camera {
@@ -35,18 +76,116 @@ impl Camera {
     /// Panics if either `direction` is not normalized or `fov` is not
     /// in the `[0, 2pi)` range.
     pub fn new(position: Vec3, direction: Vec3, fov: f32, aspect_ratio: f32) -> Self {
-        assert!(0.0 <= fov);
-        assert!(fov < 2.0 * std::f32::consts::PI);
-        assert!(direction.is_normalized());
+        Self::try_new(position, direction, fov, aspect_ratio).unwrap_or_else(|err| panic!("{err}"))
+    }
 
-        Self {
+    /// Same as [`Camera::new`], but fails instead of panicking.
+    pub fn try_new(
+        position: Vec3,
+        direction: Vec3,
+        fov: f32,
+        aspect_ratio: f32,
+    ) -> Result<Self, BuildError> {
+        check_range(
+            "fov",
+            fov,
+            "in the [0, 2pi) range",
+            (0.0..2.0 * std::f32::consts::PI).contains(&fov),
+        )?;
+        check_normalized("direction", direction)?;
+
+        Ok(Self {
             position,
             direction,
-            fov,
+            up: Vec3::Y,
+            projection: Projection::Perspective { fov },
             aspect_ratio,
+            aperture: 0.0,
+            focus_distance: 1.0,
+        })
+    }
+
+    /// Creates a new camera at `position` looking at `target`, with `up`
+    /// as the direction considered to be up. `fov` is in radians.
+    ///
+    /// # Panics
+    /// Panics if `target` is the same as `position`, `up` is zero or `fov`
+    /// is not in the `[0, 2pi)` range.
+    pub fn look_at(position: Vec3, target: Vec3, up: Vec3, fov: f32, aspect_ratio: f32) -> Self {
+        let direction = (target - position)
+            .try_normalize()
+            .expect("target must be different from position");
+        let up = up.try_normalize().expect("up must not be zero");
+
+        Self {
+            up,
+            ..Self::new(position, direction, fov, aspect_ratio)
         }
     }
 
+    /// Creates a new camera with an orthographic projection, whose view
+    /// rectangle is `scale` units tall.
+    ///
+    /// # Panics
+    /// Panics if either `direction` is not normalized or `scale` is not
+    /// positive.
+    pub fn orthographic(position: Vec3, direction: Vec3, scale: f32, aspect_ratio: f32) -> Self {
+        Self::try_orthographic(position, direction, scale, aspect_ratio)
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Same as [`Camera::orthographic`], but fails instead of panicking.
+    pub fn try_orthographic(
+        position: Vec3,
+        direction: Vec3,
+        scale: f32,
+        aspect_ratio: f32,
+    ) -> Result<Self, BuildError> {
+        check_range("scale", scale, "positive", scale > 0.0)?;
+        check_normalized("direction", direction)?;
+
+        Ok(Self {
+            position,
+            direction,
+            up: Vec3::Y,
+            projection: Projection::Orthographic { scale },
+            aspect_ratio,
+            aperture: 0.0,
+            focus_distance: 1.0,
+        })
+    }
+
+    #[inline(always)]
+    pub fn projection(&self) -> Projection {
+        self.projection
+    }
+
+    /// Gives this camera a lens with the given aperture (diameter), focused
+    /// at `focus_distance`. Objects at that distance stay sharp, while
+    /// nearer and farther ones get blurred.
+    ///
+    /// # Panics
+    /// Panics if `aperture` is negative or `focus_distance` is not
+    /// positive.
+    pub fn with_depth_of_field(mut self, aperture: f32, focus_distance: f32) -> Self {
+        assert!(aperture >= 0.0);
+        assert!(focus_distance > 0.0);
+
+        self.aperture = aperture;
+        self.focus_distance = focus_distance;
+        self
+    }
+
+    #[inline(always)]
+    pub fn aperture(&self) -> f32 {
+        self.aperture
+    }
+
+    #[inline(always)]
+    pub fn focus_distance(&self) -> f32 {
+        self.focus_distance
+    }
+
     /// Returns the direction this camera is looking at.
     /// Alias of [`Camera::z_axis`]. Normalized.
     #[inline(always)]
@@ -54,13 +193,52 @@ impl Camera {
         self.direction
     }
 
+    /// Returns the direction considered to be up for this camera.
+    /// Normalized.
+    #[inline(always)]
+    pub fn up(&self) -> Vec3 {
+        self.up
+    }
+
+    /// Sets the direction considered to be up for this camera, which
+    /// controls its roll.
+    ///
+    /// # Panics
+    /// Panics if `up` is zero.
+    #[inline(always)]
+    pub fn set_up(&mut self, up: Vec3) {
+        self.up = up.try_normalize().expect("up must not be zero");
+    }
+
+    /// Builder version of [`Camera::set_up`].
+    #[inline(always)]
+    pub fn with_up(mut self, up: Vec3) -> Self {
+        self.set_up(up);
+        self
+    }
+
     /// Returns the local x axis of this camera. Normalized.
+    ///
+    /// If the camera is looking straight along its up direction, there's
+    /// no way to tell its roll: the world axis most perpendicular to its
+    /// direction is used as up instead.
     #[inline(always)]
     pub fn x_axis(&self) -> Vec3 {
-        Vec3::Y
+        self.up
             .cross(self.direction)
             .try_normalize()
-            .unwrap_or(Vec3::X)
+            .unwrap_or_else(|| {
+                let abs = self.direction.abs();
+                let fallback_up = if abs.x <= abs.y && abs.x <= abs.z {
+                    Vec3::X
+                } else if abs.y <= abs.z {
+                    Vec3::Y
+                } else {
+                    Vec3::Z
+                };
+
+                fallback_up.cross(self.direction).normalize()
+            })
     }
 
     /// Returns the local y axis of this camera. Normalized.
@@ -80,9 +258,14 @@ impl Camera {
 
     /// Returns the 4 extreme points of the view plane
     /// defined by this camera.
+    ///
+    /// For perspective cameras it's at distance one in front of the camera,
+    /// while for orthographic ones it goes through the camera's position.
     pub fn plane(&self) -> ViewPlane {
-        let plane_center = self.position + self.z_axis();
-        let half_height = (self.fov / 2.0).tan();
+        let (plane_center, half_height) = match self.projection {
+            Projection::Perspective { fov } => (self.position + self.z_axis(), (fov / 2.0).tan()),
+            Projection::Orthographic { scale } => (self.position, scale / 2.0),
+        };
         let half_width = half_height * self.aspect_ratio;
 
         let top_center = plane_center + self.y_axis() * half_height;
@@ -100,133 +283,2845 @@ impl Camera {
             bottom_right,
         }
     }
+
+    /// Generates a ray going through the point of the view plane at `(x_t,
+    /// y_t)`, where `(0, 0)` is the top left corner and `(1, 1)` the bottom
+    /// right one.
+    ///
+    /// If this camera has an aperture, the ray starts at a random point of
+    /// the lens and goes through the point of the focus plane the pinhole
+    /// ray would hit. Rays always start at the view plane.
+    ///
+    /// Orthographic cameras ignore the aperture.
+    pub fn ray(&self, plane: &ViewPlane, (x_t, y_t): (f32, f32), rng: &mut impl Rng) -> Ray {
+        let plane_point_top = plane.top_left.lerp(plane.top_right, x_t);
+        let plane_point_bottom = plane.bottom_left.lerp(plane.bottom_right, x_t);
+        let plane_point = plane_point_top.lerp(plane_point_bottom, y_t);
+
+        if let Projection::Orthographic { .. } = self.projection {
+            return Ray::new(plane_point, self.z_axis());
+        }
+
+        if self.aperture == 0.0 {
+            let direction = (plane_point - self.position).normalize();
+            return Ray::new(plane_point, direction);
+        }
+
+        // the view plane is at distance one, so scaling the offset to it
+        // gives the point at the focus distance.
+        let focus_point = self.position + (plane_point - self.position) * self.focus_distance;
+
+        // uniformly sample a point of the lens
+        let radius = self.aperture / 2.0 * rng.gen_range(0.0f32..1.0).sqrt();
+        let angle = rng.gen_range(0.0..2.0 * std::f32::consts::PI);
+        let lens_point = self.position
+            + self.x_axis() * radius * angle.cos()
+            + self.y_axis() * radius * angle.sin();
+
+        let direction = (focus_point - lens_point).normalize();
+        let start = lens_point + direction / direction.dot(self.z_axis());
+        Ray::new(start, direction)
+    }
+}
+
+/// How far a render started with [`Renderer::render_with`] got.
+#[derive(Clone, Copy, Debug)]
+pub struct RenderProgress {
+    pub tiles_completed: usize,
+    pub tile_count: usize,
+    /// The time since the render started.
+    pub elapsed: Duration,
+    /// An estimate of the time left, assuming the remaining tiles take as
+    /// long as the completed ones did on average.
+    pub remaining: Duration,
+}
+
+/// Options for [`Renderer::render_with`].
+#[derive(Default)]
+pub struct RenderOptions<'a> {
+    /// Called after every completed tile. When rendering in parallel, this
+    /// might be called from multiple threads at once.
+    pub progress: Option<&'a (dyn Fn(RenderProgress) + Sync)>,
+    /// When set, no more tiles are started and the render is cancelled.
+    pub cancel: Option<&'a AtomicBool>,
+    /// If present, filled with the amount of samples taken for every pixel,
+    /// row by row, which is 0 for pixels that weren't rendered. Useful as a
+    /// heatmap when sampling adaptively.
+    pub sample_counts: Option<&'a mut Vec<u32>>,
+    /// If present, the auxiliary outputs to fill.
+    pub aovs: Option<Aovs<'a>>,
+    /// If present, filled with the [statistics](RenderStats) of the render.
+    pub stats: Option<&'a mut RenderStats>,
+}
+
+/// The values of the auxiliary outputs of a pixel, from where the ray
+/// through its center first hits the scene.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AovSample {
+    /// The distance along the ray, which starts at the view plane.
+    pub depth: f32,
+    /// The world space normal of the surface, facing the camera.
+    pub normal: Vec3,
+    /// The [color](crate::material::Material::color) of the material of the
+    /// surface, or the color of the light if it's a light.
+    pub albedo: Vec3,
+    /// The index of the object in [`Scene::objects`], or [`u32::MAX`] if
+    /// it's a light.
+    pub object_id: u32,
+}
+
+impl AovSample {
+    /// The values of pixels where nothing is hit: infinite depth, zero
+    /// normal and albedo, and an object id of [`u32::MAX`].
+    pub const MISS: Self = Self {
+        depth: f32::INFINITY,
+        normal: Vec3::ZERO,
+        albedo: Vec3::ZERO,
+        object_id: u32::MAX,
+    };
+}
+
+impl Default for AovSample {
+    #[inline(always)]
+    fn default() -> Self {
+        Self::MISS
+    }
+}
+
+/// Auxiliary outputs (AOVs) of [`Renderer::render_with`], which are useful
+/// for compositing and denoising. Every output that is present is filled
+/// with one value per pixel, row by row.
+///
+/// They only need a single ray per pixel, so they're very cheap compared to
+/// the image itself.
+#[derive(Default)]
+pub struct Aovs<'a> {
+    /// The [`AovSample::depth`] of every pixel.
+    pub depth: Option<&'a mut Vec<f32>>,
+    /// The [`AovSample::normal`] of every pixel.
+    pub normal: Option<&'a mut Vec<Vec3>>,
+    /// The [`AovSample::albedo`] of every pixel.
+    pub albedo: Option<&'a mut Vec<Vec3>>,
+    /// The [`AovSample::object_id`] of every pixel, which tells which object
+    /// is seen through it.
+    pub object_id: Option<&'a mut Vec<u32>>,
+    /// The coverage of every pixel: the fraction of its samples whose camera
+    /// ray hit something. Unlike the other outputs, it comes from the
+    /// samples themselves, so it's antialiased. Zero for pixels that weren't
+    /// rendered.
+    pub alpha: Option<&'a mut Vec<f32>>,
+    /// The values of pixels where nothing is hit, and of pixels that weren't
+    /// rendered because the render was cancelled.
+    pub miss: AovSample,
+}
+
+/// The outcome of [`Renderer::render_with`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RenderResult {
+    /// Every pixel was rendered.
+    Completed,
+    /// The render was cancelled, so only some of the pixels were rendered.
+    Cancelled,
+}
+
+/// A rectangle of pixels, starting at `(x, y)` and extending towards the
+/// bottom right.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    /// The amount of pixels in this rectangle.
+    #[inline(always)]
+    pub fn area(&self) -> u32 {
+        self.width * self.height
+    }
+}
+
+/// Settings for adaptive sampling, where every pixel only gets as many
+/// samples as it needs to converge.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AdaptiveSampling {
+    /// The amount of samples every pixel gets before checking whether it
+    /// converged. Convergence is then checked again after every batch of
+    /// this many samples.
+    pub min_samples: u32,
+    /// The maximum amount of samples a pixel can get.
+    pub max_samples: u32,
+    /// A pixel converged once the 95% confidence interval of its luminance
+    /// in the final image is within this fraction of its mean luminance.
+    pub threshold: f32,
+}
+
+/// How many samples are taken for every pixel.
+#[derive(Clone, Debug)]
+enum Sampling<'a> {
+    /// The samples with indices in the range. If `initial` is present, they
+    /// are added to the sums it has for every pixel, row by row, in the same
+    /// order they would have been if all of them were taken at once.
+    Fixed {
+        samples: Range<u32>,
+        initial: Option<&'a [PixelSum]>,
+    },
+    Adaptive(AdaptiveSampling),
+}
+
+/// The sum of the samples contributing to a pixel, weighted by the
+/// [filter](Renderer::filter).
+#[derive(Clone, Copy, Debug, Default)]
+struct PixelSum {
+    sum: Vec3,
+    /// The sum of the weights of the samples.
+    weight: f32,
+    /// The amount of samples taken inside of the pixel. With filters wider
+    /// than a pixel, samples of other pixels contribute to it too.
+    count: u32,
+    /// The weighted amount of samples whose camera ray hit something.
+    hits: f32,
+}
+
+impl PixelSum {
+    #[inline(always)]
+    fn add(&mut self, sample: Vec3, hit: bool, weight: f32) {
+        self.sum += sample * weight;
+        self.weight += weight;
+        self.hits += hit as u32 as f32 * weight;
+    }
+
+    /// The weighted average of the samples.
+    #[inline(always)]
+    fn average(&self) -> Vec3 {
+        if self.weight > 0.0 {
+            self.sum / self.weight
+        } else {
+            Vec3::ZERO
+        }
+    }
+
+    /// The weighted fraction of the samples whose camera ray hit something.
+    #[inline(always)]
+    fn coverage(&self) -> f32 {
+        if self.weight > 0.0 {
+            self.hits / self.weight
+        } else {
+            0.0
+        }
+    }
+}
+
+/// The running mean and variance of the luminance of the samples of a
+/// pixel, with Welford's algorithm, used for adaptive sampling.
+#[derive(Clone, Copy, Debug, Default)]
+struct Convergence {
+    count: u32,
+    mean: f32,
+    squared_deviations: f32,
+    converged: bool,
+}
+
+impl Convergence {
+    /// Adds the luminance of a sample, and checks whether the pixel
+    /// converged if a batch of samples was completed.
+    fn add(&mut self, luminance: f32, adaptive: &AdaptiveSampling) {
+        self.count += 1;
+        let count = self.count as f32;
+        let delta = luminance - self.mean;
+        self.mean += delta / count;
+        self.squared_deviations += delta * (luminance - self.mean);
+
+        // convergence is only checked after every batch of `min_samples`,
+        // since checking after every sample makes pixels more likely to stop
+        // right after a lucky streak
+        if self.count.is_multiple_of(adaptive.min_samples.max(2)) {
+            let variance = self.squared_deviations / (count - 1.0);
+            let error = 1.96 * (variance / count).sqrt();
+            self.converged = error <= adaptive.threshold * self.mean;
+        }
+    }
+}
+
+/// The relative luminance of a linear sample.
+#[inline(always)]
+pub(crate) fn luminance(sample: Vec3) -> f32 {
+    sample.dot(Vec3::new(0.2126, 0.7152, 0.0722))
+}
+
+/// Scales `sample` down so that its luminance is at most `max_luminance`.
+#[inline(always)]
+fn clamp_luminance(sample: Vec3, max_luminance: f32) -> Vec3 {
+    let luminance = luminance(sample);
+    if luminance > max_luminance {
+        sample * (max_luminance / luminance)
+    } else {
+        sample
+    }
+}
+
+/// The power heuristic weight of a sample taken with density `pdf`, when
+/// the other strategy would have taken it with density `other`.
+#[inline(always)]
+fn power_heuristic(pdf: f32, other: f32) -> f32 {
+    if other <= 0.0 {
+        return 1.0;
+    }
+
+    let (pdf, other) = (pdf * pdf, other * other);
+    pdf / (pdf + other)
 }
 
-pub struct Scene {
-    pub camera: Camera,
-    pub objects: Vec<Object>,
-    pub lights: Vec<Light>,
+/// The seed of the rays of the path of the sample with index `sample`, for
+/// shapes that scatter at random. See [`Ray::seed`].
+#[inline(always)]
+fn path_seed(seed: u64, sample: u32) -> u64 {
+    mix64(seed ^ mix64(sample as u64).rotate_left(32))
 }
 
 pub struct Renderer {
+    /// The amount of samples taken for every pixel, unless sampling
+    /// adaptively.
     pub sample_count: u32,
+    /// The maximum amount of bounces a path can take. When Russian roulette
+    /// is active (see [`Renderer::rr_min_depth`]) this becomes a soft
+    /// maximum: it still bounds path length, but most paths terminate
+    /// before reaching it.
     pub indirect_count: u32,
-    pub max_value: f32,
-    pub ambient_light: LightRay,
+    /// How the light values of the image are scaled before tone mapping.
+    pub exposure: Exposure,
+    /// The light seen by rays that don't hit anything, and by paths that
+    /// run out of bounces, for scenes without an
+    /// [environment](Scene::environment).
+    pub background: Background,
+    /// The amount of bounces after which paths start being randomly
+    /// terminated with a probability based on how much energy they still
+    /// carry (Russian roulette). Surviving paths are weighted accordingly,
+    /// so the result stays unbiased.
+    pub rr_min_depth: u32,
+    /// The tone mapping applied to the final image. [`ToneMap::Clamp`] just
+    /// clips everything that is still above one after exposure.
+    pub tone_map: ToneMap,
+    /// Adjusts [`Renderer::exposure`], in stops: every sample is also
+    /// multiplied by `2^exposure_compensation` before tone mapping. Zero
+    /// leaves it unchanged.
+    ///
+    /// This is the easiest way of brightening or darkening a render without
+    /// touching its lights or `max_value`. Pixels are exposed, then tone
+    /// mapped, then clamped to the `[0, 1]` range.
+    pub exposure_compensation: f32,
+    /// The maximum luminance of a single sample. Brighter samples are scaled
+    /// down before being averaged, which gets rid of fireflies caused by
+    /// rare paths carrying a lot of energy, at the cost of some bias.
+    ///
+    /// The bias makes bright parts of the image darker than they should be,
+    /// and it doesn't go away with more samples. The lower the clamp, the
+    /// cleaner and darker the image.
+    pub sample_clamp: Option<f32>,
+    /// Like [`Renderer::sample_clamp`], but only applies to the light each
+    /// sample finds indirectly, after bouncing off of at least two
+    /// surfaces. Fireflies mostly come from there, so this removes most of
+    /// them while keeping directly lit surfaces, and lights themselves,
+    /// unbiased.
+    pub indirect_clamp: Option<f32>,
+    /// The seed of every random choice made while rendering. Rendering the
+    /// same scene with the same seed always gives the same image.
+    pub seed: u64,
+    /// The size of the square tiles [`Renderer::render`] splits the image
+    /// into. 32 is a good default.
+    pub tile_size: u32,
+    /// Whether tiles are rendered in parallel, on rayon's thread pool. The
+    /// result is the same either way.
+    pub parallel: bool,
+    /// The sequence the random numbers used by every sample come from.
+    pub sampler: SamplerKind,
+    /// Whether samples are stratified when [`Renderer::sample_count`] is a
+    /// perfect square `n * n`: the pixel and the directions of diffuse
+    /// bounces are split into `n` by `n` strata, and every sample takes a
+    /// different one. This spreads samples more evenly, so there's less
+    /// noise than with independent ones.
+    pub stratified: bool,
+    /// Whether camera rays that hit nothing see black instead of the
+    /// background, so that the image can be composited over something else
+    /// using [`Aovs::alpha`]. Its colors are then premultiplied by alpha.
+    /// The background still lights the scene either way.
+    pub transparent_background: bool,
+    /// If present, only the pixels inside this rectangle are rendered, and
+    /// the others are left untouched. Rendered pixels are exactly the same
+    /// as in a render of the whole image.
+    pub region: Option<Rect>,
+    /// Whether every sample is cast at a random time of the shutter, in the
+    /// \[0..1) range, so that [moving shapes](crate::shape::MovingSphere)
+    /// get blurred along their motion. Otherwise, every sample is cast at
+    /// time zero.
+    pub motion_blur: bool,
+    /// The filter samples are weighted by when reconstructing pixels.
+    pub filter: Filter,
+    /// If present, pixels are sampled adaptively instead of getting
+    /// [`Renderer::sample_count`] samples each. Ignored by the
+    /// [`ProgressiveRenderer`].
+    pub adaptive: Option<AdaptiveSampling>,
+    /// How the final values are encoded in the buffer rendered into: linear,
+    /// or already encoded as sRGB, after tone mapping.
+    pub encoding: Encoding,
 }
 
 impl Renderer {
-    pub fn render<I, P>(&self, scene: &Scene, buffer: &mut I)
+    /// The light seen by rays that don't hit anything, if the
+    /// [background](Renderer::background) is the same in every direction.
+    #[deprecated(note = "use `Renderer::background` instead")]
+    #[inline(always)]
+    pub fn ambient_light(&self) -> Option<LightRay> {
+        match self.background {
+            Background::Solid(light) => Some(light),
+            _ => None,
+        }
+    }
+
+    /// Makes rays that don't hit anything see `light`, in every direction.
+    #[deprecated(note = "set `Renderer::background` to a `Background::solid` instead")]
+    #[inline(always)]
+    pub fn set_ambient_light(&mut self, light: LightRay) {
+        self.background = Background::solid(light);
+    }
+
+    /// Renders the scene into `buffer`, in [`Renderer::tile_size`] by
+    /// [`Renderer::tile_size`] tiles, and returns the statistics of the
+    /// render.
+    pub fn render<I, P>(&self, scene: &PreparedScene, buffer: &mut I) -> RenderStats
     where
         I: ImgViewMut<Pixel = P>,
         P: Pixel<Channels = [f32; 3]>,
     {
-        let (buffer_width, buffer_height) = buffer.dimensions();
-        let plane = scene.camera.plane();
+        self.render_tiles(scene, buffer, self.tile_size, |_, _| ())
+    }
+
+    /// Renders the scene in `tile_size` by `tile_size` tiles, calling
+    /// `on_tile(completed, total)` after each tile is done.
+    ///
+    /// When [`Renderer::parallel`] is set, tiles are rendered in parallel,
+    /// so `on_tile` might be called from multiple threads at once. The
+    /// `completed` count it receives is still unique for every call.
+    ///
+    /// Returns the statistics of the render.
+    ///
+    /// # Panics
+    /// Panics if `tile_size` is zero.
+    pub fn render_tiles<I, P>(
+        &self,
+        scene: &PreparedScene,
+        buffer: &mut I,
+        tile_size: u32,
+        on_tile: impl Fn(usize, usize) + Send + Sync,
+    ) -> RenderStats
+    where
+        I: ImgViewMut<Pixel = P>,
+        P: Pixel<Channels = [f32; 3]>,
+    {
+        let (sums, mut stats) = self.render_sums(
+            scene,
+            buffer.dimensions(),
+            &self.sampling(),
+            tile_size,
+            None,
+            on_tile,
+        );
 
-        for y in 0..buffer_height {
-            let y_t = (y as f32) / (buffer_height as f32);
-            for x in 0..buffer_width {
-                let x_t = (x as f32) / (buffer_width as f32);
-
-                let plane_point_top = plane.top_left.lerp(plane.top_right, x_t);
-                let plane_point_bottom = plane.bottom_left.lerp(plane.bottom_right, x_t);
-                let plane_point = plane_point_top.lerp(plane_point_bottom, y_t);
-                let direction = (plane_point - scene.camera.position).normalize();
-
-                let ray = Ray::new(plane_point, direction);
-                let mut channels = [0f32; 3];
-                for _ in 0..self.sample_count {
-                    let light_ray = self.trace_ray(ray, scene, self.indirect_count + 1);
-                    let sample = light_ray.to_sample();
-
-                    channels[0] += sample.x;
-                    channels[1] += sample.y;
-                    channels[2] += sample.z;
-                }
-                channels[0] /= self.sample_count as f32;
-                channels[1] /= self.sample_count as f32;
-                channels[2] /= self.sample_count as f32;
-
-                let pixel = buffer.pixel_mut((x, y)).unwrap();
-                pixel.channels_mut()[..].copy_from_slice(&channels[..]);
-            }
-        }
-
-        // dyn linear
-        // let mut max = Vec3::ZERO;
-        // for channel in buffer.pixels().map(|p| p.channels()) {
-        //     let sample = Vec3::from(*channel);
-        //     if sample.length_squared() > max.length_squared() {
-        //         max = sample;
-        //     }
-        // }
-        // let max_len = max.length();
-        // for channel in buffer.pixels_mut().map(|p| p.channels_mut()) {
-        //     channel[0] /= max_len;
-        //     channel[1] /= max_len;
-        //     channel[2] /= max_len;
-        // }
+        let start = Instant::now();
+        self.resolve(&sums, buffer);
+        stats.resolve_time = start.elapsed();
 
-        for channel in buffer.pixels_mut().map(|p| p.channels_mut()) {
-            channel[0] = (channel[0] / self.max_value).clamp(0.0, 1.0);
-            channel[1] = (channel[1] / self.max_value).clamp(0.0, 1.0);
-            channel[2] = (channel[2] / self.max_value).clamp(0.0, 1.0);
+        stats
+    }
+
+    /// Renders the scene into `buffer` like [`Renderer::render`], reporting
+    /// progress and checking for cancellation as described by `options`.
+    ///
+    /// Cancellation is checked before every tile is started, so tiles that
+    /// were already being rendered still finish. If the render is
+    /// cancelled, the pixels of finished tiles are still written to
+    /// `buffer` and the others are left untouched.
+    pub fn render_with<I, P>(
+        &self,
+        scene: &PreparedScene,
+        buffer: &mut I,
+        options: RenderOptions,
+    ) -> RenderResult
+    where
+        I: ImgViewMut<Pixel = P>,
+        P: Pixel<Channels = [f32; 3]>,
+    {
+        let start = Instant::now();
+        let on_tile = |completed: usize, total: usize| {
+            let Some(progress) = options.progress else {
+                return;
+            };
+
+            let elapsed = start.elapsed();
+            let remaining = elapsed.mul_f64((total - completed) as f64 / completed as f64);
+            progress(RenderProgress {
+                tiles_completed: completed,
+                tile_count: total,
+                elapsed,
+                remaining,
+            });
+        };
+
+        let (sums, mut stats) = self.render_sums(
+            scene,
+            buffer.dimensions(),
+            &self.sampling(),
+            self.tile_size,
+            options.cancel,
+            on_tile,
+        );
+
+        let resolve_start = Instant::now();
+        self.resolve(&sums, buffer);
+        stats.resolve_time = resolve_start.elapsed();
+
+        if let Some(sample_counts) = options.sample_counts {
+            sample_counts.clear();
+            sample_counts.extend(sums.iter().map(|sum| sum.map_or(0, |sum| sum.count)));
+        }
+
+        if let Some(mut aovs) = options.aovs {
+            if let Some(alpha) = aovs.alpha.take() {
+                alpha.clear();
+                alpha.extend(sums.iter().map(|sum| sum.map_or(0.0, |sum| sum.coverage())));
+            }
+
+            let aov_start = Instant::now();
+            self.render_aovs(scene, buffer.dimensions(), &sums, aovs);
+            stats.aov_time = aov_start.elapsed();
+        }
+
+        if let Some(render_stats) = options.stats {
+            *render_stats = stats;
         }
+
+        let rendered = sums.iter().flatten().count();
+        if rendered == self.region(buffer.dimensions()).area() as usize {
+            RenderResult::Completed
+        } else {
+            RenderResult::Cancelled
+        }
+    }
+
+    /// Renders the scene into `buffer` like [`Renderer::render`], until
+    /// `cancel` is set, and returns the statistics of the render.
+    ///
+    /// This is [`Renderer::render_with`] with only
+    /// [`RenderOptions::cancel`] set. The [pixels](RenderStats::pixels) and
+    /// [tiles](RenderStats::tiles) of the statistics tell how much of the
+    /// image was rendered before the render was cancelled.
+    pub fn render_with_cancel<I, P>(
+        &self,
+        scene: &PreparedScene,
+        buffer: &mut I,
+        cancel: &AtomicBool,
+    ) -> RenderStats
+    where
+        I: ImgViewMut<Pixel = P>,
+        P: Pixel<Channels = [f32; 3]>,
+    {
+        let mut stats = RenderStats::default();
+        let options = RenderOptions {
+            cancel: Some(cancel),
+            stats: Some(&mut stats),
+            ..Default::default()
+        };
+        self.render_with(scene, buffer, options);
+
+        stats
     }
 
-    pub fn trace_ray(&self, ray: Ray, scene: &Scene, depth: u32) -> LightRay {
-        if depth == 0 {
-            // ambient color
-            return self.ambient_light;
+    /// Renders the scene into `buffer` for about `budget`, instead of
+    /// taking [`Renderer::sample_count`] samples for every pixel, and
+    /// returns the statistics of the render, including the amount of
+    /// samples every pixel got on average.
+    ///
+    /// Samples are taken one pass over the image at a time, until the
+    /// budget runs out. The first pass is always completed, and no tiles
+    /// are started after the budget ran out, so this takes at most one pass
+    /// or one tile longer than the budget, whichever is longer. Pixels of
+    /// an unfinished pass might get one sample more than the others.
+    ///
+    /// [Adaptive sampling](Renderer::adaptive) is ignored.
+    pub fn render_for<I, P>(
+        &self,
+        scene: &PreparedScene,
+        buffer: &mut I,
+        budget: Duration,
+    ) -> RenderStats
+    where
+        I: ImgViewMut<Pixel = P>,
+        P: Pixel<Channels = [f32; 3]>,
+    {
+        let start = Instant::now();
+        let dimensions = buffer.dimensions();
+        let first_pass = Sampling::Fixed {
+            samples: 0..1,
+            initial: None,
+        };
+        let (mut sums, mut stats) = self.render_sums(
+            scene,
+            dimensions,
+            &first_pass,
+            self.tile_size,
+            None,
+            |_, _| (),
+        );
+
+        let out_of_time = AtomicBool::new(false);
+        let check_time = |_, _| {
+            if start.elapsed() >= budget {
+                out_of_time.store(true, Ordering::Relaxed);
+            }
+        };
+
+        let mut pass = 1;
+        while !out_of_time.load(Ordering::Relaxed) && start.elapsed() < budget {
+            let initial: Vec<_> = sums.iter().map(|sum| sum.unwrap_or_default()).collect();
+            let sampling = Sampling::Fixed {
+                samples: pass..pass + 1,
+                initial: Some(&initial),
+            };
+            let (pass_sums, pass_stats) = self.render_sums(
+                scene,
+                dimensions,
+                &sampling,
+                self.tile_size,
+                Some(&out_of_time),
+                check_time,
+            );
+
+            // the new sums include the old ones
+            for (sum, new) in sums.iter_mut().zip(pass_sums) {
+                if new.is_some() {
+                    *sum = new;
+                }
+            }
+
+            stats += pass_stats;
+            pass += 1;
         }
 
-        let closest_hit_object = scene
-            .objects
-            .iter()
-            .filter_map(|obj: &Object| {
-                obj.shape
-                    .intersection(ray)
-                    .map(|intersection| (obj, intersection))
-            })
-            .min_by_key(|(_, intersection)| FloatOrd(intersection.t));
+        let resolve_start = Instant::now();
+        self.resolve(&sums, buffer);
+        stats.resolve_time = resolve_start.elapsed();
+
+        // every pass counted the pixels it rendered
+        stats.pixels = sums.iter().flatten().count() as u64;
+        stats
+    }
+
+    /// Fills `aovs` for the pixels of a `width` by `height` image which have
+    /// a sum in `sums`.
+    fn render_aovs(
+        &self,
+        scene: &PreparedScene,
+        (width, height): (u32, u32),
+        sums: &[Option<PixelSum>],
+        aovs: Aovs,
+    ) {
+        let plane = scene.camera.plane();
+        let aov_sample = |index: usize| {
+            let position = (index as u32 % width, index as u32 / width);
+            sums[index]
+                .and_then(|_| self.aov_sample(scene, &plane, position, (width, height)))
+                .unwrap_or(aovs.miss)
+        };
+
+        let samples: Vec<_> = if self.parallel {
+            (0..sums.len()).into_par_iter().map(aov_sample).collect()
+        } else {
+            (0..sums.len()).map(aov_sample).collect()
+        };
+
+        if let Some(depth) = aovs.depth {
+            depth.clear();
+            depth.extend(samples.iter().map(|sample| sample.depth));
+        }
+
+        if let Some(normal) = aovs.normal {
+            normal.clear();
+            normal.extend(samples.iter().map(|sample| sample.normal));
+        }
+
+        if let Some(albedo) = aovs.albedo {
+            albedo.clear();
+            albedo.extend(samples.iter().map(|sample| sample.albedo));
+        }
+
+        if let Some(object_id) = aovs.object_id {
+            object_id.clear();
+            object_id.extend(samples.iter().map(|sample| sample.object_id));
+        }
+    }
+
+    /// The [`AovSample`] of the pixel at `(x, y)` of a `width` by `height`
+    /// image, or [`None`] if the ray through its center hits nothing.
+    fn aov_sample(
+        &self,
+        scene: &PreparedScene,
+        plane: &ViewPlane,
+        (x, y): (u32, u32),
+        (width, height): (u32, u32),
+    ) -> Option<AovSample> {
+        let x_t = (x as f32 + 0.5) / (width as f32);
+        let y_t = (y as f32 + 0.5) / (height as f32);
+        let ray = scene
+            .camera
+            .ray(plane, (x_t, y_t), &mut self.sample_rng((x, y), 0));
 
-        let closest_hit_light = scene
+        let object = scene.intersect(ray).map(|(index, intersection)| {
+            let albedo = scene.objects[index].material.color(&intersection);
+            (intersection, albedo.to_vec3(), index as u32)
+        });
+
+        let light = scene
             .lights
             .iter()
-            .filter_map(|light: &Light| {
+            .filter(|light| light.enabled)
+            .filter_map(|light| {
                 light
                     .shape
                     .intersection(ray)
-                    .map(|intersection| (light, intersection))
+                    .map(|intersection| (intersection, light.color.to_vec3(), u32::MAX))
             })
-            .min_by_key(|(_, intersection)| FloatOrd(intersection.t));
+            .min_by_key(|(intersection, ..)| FloatOrd(intersection.t));
+
+        let (intersection, albedo, object_id) = match (object, light) {
+            (Some(object), Some(light)) => {
+                if light.0.t < object.0.t {
+                    light
+                } else {
+                    object
+                }
+            }
+            (object, light) => object.or(light)?,
+        };
+
+        Some(AovSample {
+            depth: intersection.t,
+            normal: intersection.normal,
+            albedo,
+            object_id,
+        })
+    }
+
+    /// How [`Renderer::render`] samples pixels.
+    fn sampling(&self) -> Sampling<'static> {
+        match self.adaptive {
+            Some(adaptive) => Sampling::Adaptive(adaptive),
+            None => Sampling::Fixed {
+                samples: 0..self.sample_count,
+                initial: None,
+            },
+        }
+    }
+
+    /// The part of a `width` by `height` image that gets rendered.
+    #[inline(always)]
+    fn region(&self, (width, height): (u32, u32)) -> Rect {
+        self.region.unwrap_or(Rect {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        })
+    }
 
-        let obj_color = |obj: &Object, intersection: Intersection| {
-            let new_ray = obj
-                .material
-                .scatter(ray, intersection.point, intersection.normal);
+    /// Renders the samples given by `sampling` for every pixel of the
+    /// [region](Renderer::region) of a `width` by `height` image, in
+    /// `tile_size` by `tile_size` tiles. Returns the sum of the samples of
+    /// every pixel, row by row, or `None` for the pixels outside of the
+    /// region or of tiles skipped because `cancel` was set, along with the
+    /// statistics of the rendered tiles.
+    ///
+    /// # Panics
+    /// Panics if `tile_size` is zero or if the region isn't inside of the
+    /// image.
+    fn render_sums(
+        &self,
+        scene: &PreparedScene,
+        (width, height): (u32, u32),
+        sampling: &Sampling,
+        tile_size: u32,
+        cancel: Option<&AtomicBool>,
+        on_tile: impl Fn(usize, usize) + Send + Sync,
+    ) -> (Vec<Option<PixelSum>>, RenderStats) {
+        assert!(tile_size > 0);
+        let start = Instant::now();
 
-            let light_ray = self.trace_ray(new_ray, scene, depth - 1);
-            let mat_color = obj.material.color(intersection.point, intersection.normal);
+        let region = self.region((width, height));
+        assert!(region.x + region.width <= width && region.y + region.height <= height);
+        let region_end = (region.x + region.width, region.y + region.height);
+
+        let plane = scene.camera.plane();
+        let tiles_x = region.width.div_ceil(tile_size);
+        let tiles_y = region.height.div_ceil(tile_size);
+        let total = (tiles_x * tiles_y) as usize;
+        let completed = AtomicUsize::new(0);
 
-            LightRay {
-                color: light_ray.color * mat_color,
-                intensity: light_ray.intensity,
+        let render_tile = |tile: u32| {
+            if cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed)) {
+                return None;
             }
+
+            let x_start = region.x + (tile % tiles_x) * tile_size;
+            let y_start = region.y + (tile / tiles_x) * tile_size;
+            let x_end = (x_start + tile_size).min(region_end.0);
+            let y_end = (y_start + tile_size).min(region_end.1);
+
+            let tile = Rect {
+                x: x_start,
+                y: y_start,
+                width: x_end - x_start,
+                height: y_end - y_start,
+            };
+            let (pixels, stats) =
+                stats::counted(|| self.render_tile(scene, &plane, tile, (width, height), sampling));
+
+            on_tile(completed.fetch_add(1, Ordering::Relaxed) + 1, total);
+            Some(((x_start, y_start), pixels, stats))
         };
 
-        match (closest_hit_light, closest_hit_object) {
-            (None, None) => self.ambient_light,
-            (None, Some((obj, intersection))) => obj_color(obj, intersection),
-            (Some((light, _)), None) => light.light_ray(),
-            (Some((light, light_intersection)), Some((obj, obj_intersection))) => {
-                if light_intersection.t < obj_intersection.t {
-                    light.light_ray()
-                } else {
-                    obj_color(obj, obj_intersection)
-                }
+        // render the tiles, then put them together
+        let tiles: Vec<_> = if self.parallel {
+            (0..tiles_x * tiles_y)
+                .into_par_iter()
+                .filter_map(render_tile)
+                .collect()
+        } else {
+            (0..tiles_x * tiles_y).filter_map(render_tile).collect()
+        };
+
+        let mut sums = vec![None; (width * height) as usize];
+        let mut stats = RenderStats {
+            tiles: tiles.len() as u64,
+            ..Default::default()
+        };
+        for ((x_start, y_start), pixels, tile_stats) in tiles {
+            stats += tile_stats;
+
+            let tile_width = (x_start + tile_size).min(region_end.0) - x_start;
+            for (index, channels) in pixels.into_iter().enumerate() {
+                let x = x_start + index as u32 % tile_width;
+                let y = y_start + index as u32 / tile_width;
+                sums[(y * width + x) as usize] = Some(channels);
             }
         }
+
+        stats.pixels = sums.iter().flatten().count() as u64;
+        stats.sampling_time = start.elapsed();
+        (sums, stats)
+    }
+
+    /// Writes the exposed and tone mapped average of the samples of every
+    /// pixel, given their sums row by row, into `buffer`. Pixels without a
+    /// sum are left untouched, and don't count towards automatic exposure.
+    fn resolve<I, P>(&self, sums: &[Option<PixelSum>], buffer: &mut I)
+    where
+        I: ImgViewMut<Pixel = P>,
+        P: Pixel<Channels = [f32; 3]>,
+    {
+        let averages = sums.iter().flatten().map(PixelSum::average);
+        let scale =
+            self.exposure_compensation.exp2() * self.exposure.scale(averages.map(luminance));
+
+        let (width, _) = buffer.dimensions();
+        for (index, sum) in sums.iter().enumerate() {
+            let Some(sum) = sum else {
+                continue;
+            };
+
+            let x = index as u32 % width;
+            let y = index as u32 / width;
+
+            let average = sum.average();
+            let pixel = buffer.pixel_mut((x, y)).unwrap();
+            let mapped = self.tone_map.apply(average * scale);
+            *pixel.channels_mut() = self.encoding.apply(mapped).to_array();
+        }
+    }
+
+    /// Converts a buffer produced by [`Renderer::render`], which is linear,
+    /// to sRGB by applying its transfer function to every channel.
+    ///
+    /// Rendering only does this by itself when [`Renderer::encoding`] is
+    /// [`Encoding::Srgb`], so this should only be called on linear buffers,
+    /// right before converting them to a displayable format.
+    pub fn tonemap_to_srgb<I, P>(&self, buffer: &mut I)
+    where
+        I: ImgViewMut<Pixel = P>,
+        P: Pixel<Channels = [f32; 3]>,
+    {
+        for channel in buffer.pixels_mut().map(|p| p.channels_mut()) {
+            *channel = channel.map(tonemap::linear_to_srgb);
+        }
+    }
+
+    /// The [`Sampler`] used for the sample with index `sample` of the pixel
+    /// at `(x, y)`. It only depends on the seed, the position of the pixel
+    /// and the index, so the order in which samples are taken doesn't change
+    /// the result.
+    #[inline(always)]
+    fn sample_rng(&self, position: (u32, u32), sample: u32) -> Sampler {
+        Sampler::new(self.sampler, self.pixel_seed(position), sample).with_strata(self.strata())
+    }
+
+    /// The seed of the pixel at `(x, y)`, which its samples are derived
+    /// from.
+    #[inline(always)]
+    fn pixel_seed(&self, (x, y): (u32, u32)) -> u64 {
+        let position = (x as u64) << 32 | y as u64;
+        mix64(self.seed ^ mix64(position))
+    }
+
+    /// Renders the samples given by `sampling` for the pixels of `tile`, in
+    /// a `width` by `height` image, and returns their sums row by row.
+    ///
+    /// With filters wider than a pixel, the samples of the pixels around the
+    /// tile contribute to it too, so they're rendered as well. Samples are
+    /// always added to a pixel in the same order, by index and then by the
+    /// position of the pixel they were taken in, so pixels don't depend on
+    /// how the image is split into tiles nor on how many samples are taken
+    /// at a time.
+    fn render_tile(
+        &self,
+        scene: &PreparedScene,
+        plane: &ViewPlane,
+        tile: Rect,
+        (width, height): (u32, u32),
+        sampling: &Sampling,
+    ) -> Vec<PixelSum> {
+        let (samples, initial, adaptive) = match sampling {
+            Sampling::Fixed { samples, initial } => (samples.clone(), *initial, None),
+            Sampling::Adaptive(adaptive) => (0..adaptive.max_samples, None, Some(adaptive)),
+        };
+
+        let tile_end = (tile.x + tile.width, tile.y + tile.height);
+        let mut sums = Vec::with_capacity(tile.area() as usize);
+        for y in tile.y..tile_end.1 {
+            for x in tile.x..tile_end.0 {
+                sums.push(initial.map_or_else(PixelSum::default, |initial| {
+                    initial[(y * width + x) as usize]
+                }));
+            }
+        }
+
+        // the pixels whose samples can reach the tile
+        let margin = self.filter.margin();
+        let mut taken = 0;
+        let mut sources = Vec::new();
+        for y in tile.y.saturating_sub(margin)..(tile_end.1 + margin).min(height) {
+            for x in tile.x.saturating_sub(margin)..(tile_end.0 + margin).min(width) {
+                sources.push(((x, y), Convergence::default()));
+            }
+        }
+
+        for sample in samples {
+            let mut sampled = false;
+            for ((x, y), convergence) in sources.iter_mut() {
+                if convergence.converged {
+                    continue;
+                }
+
+                let (value, hit, jitter) =
+                    self.render_sample(scene, plane, (*x, *y), (width, height), sample);
+                sampled = true;
+
+                if let Some(adaptive) = adaptive {
+                    convergence.add(self.adaptive_luminance(value), adaptive);
+                }
+
+                // splat the sample into the pixels of the tile it reaches
+                let targets_x =
+                    x.saturating_sub(margin).max(tile.x)..(*x + margin + 1).min(tile_end.0);
+                let targets_y =
+                    y.saturating_sub(margin).max(tile.y)..(*y + margin + 1).min(tile_end.1);
+                for target_y in targets_y {
+                    for target_x in targets_x.clone() {
+                        let index = (target_y - tile.y) * tile.width + (target_x - tile.x);
+                        let sum = &mut sums[index as usize];
+                        if (target_x, target_y) == (*x, *y) {
+                            sum.count += 1;
+                            taken += 1;
+                        }
+
+                        let offset = (
+                            *x as f32 + jitter.0 - (target_x as f32 + 0.5),
+                            *y as f32 + jitter.1 - (target_y as f32 + 0.5),
+                        );
+                        let weight = self.filter.weight(offset);
+                        if weight > 0.0 {
+                            sum.add(value, hit, weight);
+                        }
+                    }
+                }
+            }
+
+            if !sampled {
+                break;
+            }
+        }
+
+        stats::count(|stats| stats.samples += taken);
+        sums
+    }
+
+    /// The luminance `sample` would have in the final image, which is what
+    /// adaptive sampling measures the error of.
+    #[inline(always)]
+    fn adaptive_luminance(&self, sample: Vec3) -> f32 {
+        // with automatic exposure, the scale of the final image isn't known
+        // yet. the error is relative, so that only loses the clipping of
+        // values above white
+        match self.exposure {
+            Exposure::Manual { max_value } => {
+                let scale = self.exposure_compensation.exp2() / max_value;
+                luminance((sample * scale).min(Vec3::ONE))
+            }
+            Exposure::Auto { .. } => luminance(sample),
+        }
+    }
+
+    /// The amount of strata along each axis pixels and diffuse bounces are
+    /// split into, which is only done when [`Renderer::stratified`] is set
+    /// and [`Renderer::sample_count`] is a perfect square.
+    #[inline(always)]
+    fn strata(&self) -> Option<u32> {
+        let strata = (self.sample_count as f64).sqrt() as u32;
+        (self.stratified && strata > 1 && strata * strata == self.sample_count).then_some(strata)
+    }
+
+    /// Renders the sample with index `sample` of the pixel at `(x, y)` of a
+    /// `width` by `height` image, jittered inside of it.
+    ///
+    /// If the pixel is split into strata, each sample is jittered inside of
+    /// its own stratum instead, so they cover the pixel more evenly.
+    ///
+    /// Also returns whether the camera ray hit anything, and where the
+    /// sample is inside of the pixel, from `(0, 0)` at its top left corner
+    /// to `(1, 1)` at its bottom right one.
+    fn render_sample(
+        &self,
+        scene: &PreparedScene,
+        plane: &ViewPlane,
+        (x, y): (u32, u32),
+        (width, height): (u32, u32),
+        sample: u32,
+    ) -> (Vec3, bool, (f32, f32)) {
+        let rng = &mut self.sample_rng((x, y), sample);
+
+        // jitter the sample inside the pixel
+        let jitter = rng.next_2d();
+
+        let x_t = (x as f32 + jitter.0) / (width as f32);
+        let y_t = (y as f32 + jitter.1) / (height as f32);
+
+        let seed = path_seed(self.pixel_seed((x, y)), sample);
+        let mut ray = scene.camera.ray(plane, (x_t, y_t), rng).with_seed(seed);
+        if self.motion_blur {
+            ray = ray.with_time(rng.gen_range(0.0..1.0));
+        }
+
+        let (sample, hit) = self.trace(ray, scene, self.indirect_count + 1, rng);
+        // a single broken sample, e.g. from a degenerate shape, would
+        // otherwise spoil the whole pixel
+        let sample = if sample.is_finite() {
+            sample
+        } else {
+            Vec3::ZERO
+        };
+
+        match self.sample_clamp {
+            Some(max_luminance) => (clamp_luminance(sample, max_luminance), hit, jitter),
+            None => (sample, hit, jitter),
+        }
+    }
+
+    /// The background of `scene`: its [environment](Scene::environment), or
+    /// [`Renderer::background`] if it has none.
+    #[inline(always)]
+    fn background<'a>(&'a self, scene: &'a PreparedScene) -> &'a Background {
+        scene.environment.as_ref().unwrap_or(&self.background)
+    }
+
+    /// Traces a single ray through the scene, bouncing at most `depth - 1`
+    /// times. Returns no light if the path carried back anything that isn't
+    /// finite.
+    ///
+    /// This is the sample with index zero of [`Renderer::trace_sample`], so
+    /// tracing the same ray again always gives the same light. Estimates
+    /// should average over many samples with that instead.
+    #[inline(always)]
+    pub fn trace_ray(&self, ray: Ray, scene: &PreparedScene, depth: u32) -> LightRay {
+        self.trace_sample(ray, scene, depth, 0)
+    }
+
+    /// Same as [`Renderer::trace_ray`], but traces the sample with index
+    /// `sample`. The randomness of the path only depends on
+    /// [`Renderer::seed`] and `sample`, so tracing the same ray with the same
+    /// index always gives the same light.
+    pub fn trace_sample(
+        &self,
+        ray: Ray,
+        scene: &PreparedScene,
+        depth: u32,
+        sample: u32,
+    ) -> LightRay {
+        let mut rng = Sampler::new(self.sampler, self.seed, sample);
+        let ray = ray.with_seed(path_seed(self.seed, sample));
+        let (sample, _) = self.trace(ray, scene, depth, &mut rng);
+        if sample.is_finite() {
+            LightRay::from_sample(sample.max(Vec3::ZERO))
+        } else {
+            LightRay::ZERO
+        }
+    }
+
+    /// Traces a path starting with `ray`, bouncing at most `depth - 1`
+    /// times, and returns the light it carries back, as a sample, and
+    /// whether `ray` itself hit anything. The light isn't checked, so it
+    /// might not be finite.
+    ///
+    /// Paths are traced iteratively, so `depth` can be arbitrarily big
+    /// without growing the stack.
+    fn trace(
+        &self,
+        mut ray: Ray,
+        scene: &PreparedScene,
+        depth: u32,
+        rng: &mut Sampler,
+    ) -> (Vec3, bool) {
+        let mut radiance = Vec3::ZERO;
+        // the product of all attenuations along the path so far
+        let mut throughput = Vec3::ONE;
+        // the throughput, also weighted by the russian roulette survival
+        // probabilities, which is what scales the light found at a vertex
+        let mut weight = Vec3::ONE;
+        // the density of the direction of the last bounce, if it was
+        // diffuse, in which case the light it finds in sampleable lights was
+        // also sampled [directly](Renderer::sample_direct), and both are
+        // weighted by multiple importance sampling
+        let mut diffuse_pdf = None;
+        // the object the last bounce scattered off of, which only sees the
+        // lights it's lit by
+        let mut receiver: Option<&Object> = None;
+
+        // the light found at a vertex, after `interactions` surfaces between
+        // it and the camera, clamped if it was found indirectly
+        let found = |interactions: u32, light: Vec3| match self.indirect_clamp {
+            Some(max_luminance) if interactions >= 2 => clamp_luminance(light, max_luminance),
+            _ => light,
+        };
+
+        for bounce in 0..depth {
+            stats::count(|stats| {
+                if bounce == 0 {
+                    stats.primary_rays += 1;
+                } else {
+                    stats.secondary_rays += 1;
+                }
+
+                stats.intersection_tests += scene.lights.len() as u64;
+            });
+
+            let closest_hit_object = scene.closest_hit(ray);
+
+            let closest_hit_light = scene
+                .lights
+                .iter()
+                .enumerate()
+                .filter(|(_, light)| light.enabled)
+                .filter_map(|(index, light): (usize, &Light)| {
+                    light
+                        .shape
+                        .intersection(ray)
+                        .map(|intersection| (index, light, intersection))
+                })
+                .min_by_key(|(.., intersection)| FloatOrd(intersection.t));
+
+            // lights only count if they're in front of every object
+            let closest_hit_light = closest_hit_light.filter(|(.., light_intersection)| {
+                closest_hit_object
+                    .is_none_or(|(_, obj_intersection)| light_intersection.t < obj_intersection.t)
+            });
+
+            if let Some((index, light, light_intersection)) = closest_hit_light {
+                if !receiver.is_none_or(|obj| obj.is_lit_by(index)) {
+                    return (radiance, true);
+                }
+
+                let mis = diffuse_pdf.map_or(1.0, |pdf| {
+                    let light_pdf = self.light_pdf(scene, index, ray, &light_intersection);
+                    power_heuristic(pdf, light_pdf)
+                });
+
+                let light_ray = light.light_ray_at(light_intersection.t);
+                radiance += found(bounce, weight * mis * light_ray.to_sample());
+
+                return (radiance, true);
+            }
+
+            let Some((obj, intersection)) = closest_hit_object else {
+                if bounce == 0 && self.transparent_background {
+                    return (Vec3::ZERO, false);
+                }
+
+                let mis = diffuse_pdf.map_or(1.0, |pdf| {
+                    power_heuristic(pdf, self.background_pdf(scene, ray.direction()))
+                });
+                let background = self.background(scene).light(ray.direction()).to_sample();
+                radiance += found(bounce, weight * mis * background);
+                return (radiance, bounce > 0);
+            };
+
+            radiance += found(
+                bounce,
+                weight * obj.material.emitted(&intersection).to_sample(),
+            );
+
+            let Some(scatter) = obj.material.scatter(ray, &intersection, rng) else {
+                // absorbed
+                return (radiance, true);
+            };
+
+            let attenuation = scatter.attenuation.to_vec3();
+            throughput *= attenuation;
+
+            let pdf = |outgoing| {
+                obj.material
+                    .pdf(ray.direction(), outgoing, intersection.normal)
+            };
+            if scatter.diffuse {
+                let direct = self.sample_direct(scene, obj, &intersection, pdf, ray.time(), rng)
+                    + self.delta_direct(scene, obj, &intersection, ray.time());
+                radiance += found(bounce + 1, weight * attenuation * direct);
+            }
+
+            // russian roulette
+            let survival = if bounce >= self.rr_min_depth {
+                throughput.max_element().min(1.0)
+            } else {
+                1.0
+            };
+
+            if rng.gen_range(0.0..1.0) >= survival {
+                return (radiance, true);
+            }
+
+            weight *= attenuation / survival;
+            // scattered rays are cast at the same time as the path
+            diffuse_pdf = scatter.diffuse.then(|| pdf(scatter.ray.direction()));
+            receiver = Some(obj);
+            ray = scatter
+                .ray
+                .with_time(ray.time())
+                .with_seed(mix64(ray.seed()));
+        }
+
+        // out of bounces
+        let mis = diffuse_pdf.map_or(1.0, |pdf| {
+            power_heuristic(pdf, self.background_pdf(scene, ray.direction()))
+        });
+        let background = self.background(scene).light(ray.direction()).to_sample();
+        radiance += found(depth, weight * mis * background);
+        (radiance, depth > 0)
+    }
+
+    /// Estimates the light arriving directly from the scene's lights at a
+    /// point of an ideal diffuse surface, already weighted by the lambertian
+    /// BRDF (without the albedo).
+    ///
+    /// This works by sampling a point on a light, chosen randomly in
+    /// proportion to its [power](Light::power), and casting a shadow ray
+    /// towards it. Lights whose shapes can't be sampled contribute nothing
+    /// here, and are only found by scattered rays. The shadow ray is cast at
+    /// `time`. Only the lights that light `object` contribute, see
+    /// [`Object::light_mask`]. Backgrounds that can be
+    /// [sampled](Background::sample) are picked like another light, and
+    /// sampled by direction instead.
+    ///
+    /// Scattered rays can find the same light, so the estimate is weighted
+    /// by multiple importance sampling against `bsdf_pdf`, the density of
+    /// the material scattering rays towards a direction.
+    fn sample_direct(
+        &self,
+        scene: &PreparedScene,
+        object: &Object,
+        intersection: &Intersection,
+        bsdf_pdf: impl Fn(Vec3) -> f32,
+        time: f32,
+        rng: &mut Sampler,
+    ) -> Vec3 {
+        let background_probability = self.background_probability(scene);
+        let u = rng.gen_range(0.0..1.0);
+        if u < background_probability {
+            let Some((direction, light, pdf)) = self.background(scene).sample(rng) else {
+                return Vec3::ZERO;
+            };
+
+            let cos_surface = intersection.normal.dot(direction);
+            if cos_surface <= 0.0 {
+                return Vec3::ZERO;
+            }
+
+            // lights block the background too, like they do for scattered
+            // rays
+            stats::count(|stats| {
+                stats.shadow_rays += 1;
+                stats.intersection_tests += scene.lights.len() as u64;
+            });
+            let shadow_ray = Ray::new(intersection.point, direction).with_time(time);
+            if scene.occluded(shadow_ray, f32::INFINITY)
+                || scene
+                    .lights
+                    .iter()
+                    .any(|light| light.enabled && light.shape.occluded(shadow_ray, f32::INFINITY))
+            {
+                return Vec3::ZERO;
+            }
+
+            let pdf = pdf * background_probability;
+            let mis = power_heuristic(pdf, bsdf_pdf(direction));
+            return light.to_sample() * mis * cos_surface / (std::f32::consts::PI * pdf);
+        }
+
+        let Some(distribution) = scene.light_distribution() else {
+            return Vec3::ZERO;
+        };
+
+        // lights share the range of `u` left by the background
+        let (index, _) =
+            distribution.sample((u - background_probability) / (1.0 - background_probability));
+        let light = &scene.lights[index];
+        if !object.is_lit_by(index) {
+            return Vec3::ZERO;
+        }
+
+        let Some((light_point, light_normal, area_pdf)) = light.shape.sample_surface(rng) else {
+            return Vec3::ZERO;
+        };
+
+        let to_light = light_point - intersection.point;
+        let distance_sq = to_light.length_squared();
+        let Some(direction) = to_light.try_normalize() else {
+            return Vec3::ZERO;
+        };
+
+        let cos_surface = intersection.normal.dot(direction);
+        let cos_light = -light_normal.dot(direction);
+        if cos_surface <= 0.0 || cos_light <= 0.0 {
+            return Vec3::ZERO;
+        }
+
+        stats::count(|stats| stats.shadow_rays += 1);
+        if scene.is_occluded(intersection.point, light_point, time) {
+            return Vec3::ZERO;
+        }
+
+        // probability density of having sampled this direction: the area
+        // density converted to solid angle, times the chance of having
+        // picked this light.
+        let pdf = area_pdf * distance_sq / cos_light * self.light_probability(scene, index);
+        let mis = power_heuristic(pdf, bsdf_pdf(direction));
+        let light_ray = light.light_ray_at(distance_sq.sqrt());
+        light_ray.to_sample() * mis * cos_surface / (std::f32::consts::PI * pdf)
+    }
+
+    /// The probability of [`Renderer::sample_direct`] picking the background
+    /// of `scene` instead of one of its lights. Zero if it can't be sampled.
+    ///
+    /// The background is infinitely far away, so its power can't be
+    /// compared with the one of the lights: it's picked as often as the
+    /// lights would each be if they were picked uniformly. Disabled lights
+    /// aren't counted.
+    #[inline(always)]
+    fn background_probability(&self, scene: &PreparedScene) -> f32 {
+        if !self.background(scene).is_sampleable() {
+            0.0
+        } else if scene.light_distribution().is_none() {
+            1.0
+        } else {
+            1.0 / (scene.enabled_light_count() + 1) as f32
+        }
+    }
+
+    /// The probability of [`Renderer::sample_direct`] picking the light at
+    /// `index` in the lights of `scene`.
+    #[inline(always)]
+    fn light_probability(&self, scene: &PreparedScene, index: usize) -> f32 {
+        scene.light_distribution().map_or(0.0, |distribution| {
+            distribution.probability(index) * (1.0 - self.background_probability(scene))
+        })
+    }
+
+    /// The density, per unit solid angle, with which
+    /// [`Renderer::sample_direct`] would have sampled the direction of `ray`,
+    /// which hit the light at `index` at `intersection`. Zero if the light
+    /// can't be sampled or if it was hit from the side that faces away from
+    /// the samples.
+    fn light_pdf(
+        &self,
+        scene: &PreparedScene,
+        index: usize,
+        ray: Ray,
+        intersection: &Intersection,
+    ) -> f32 {
+        let Some(area) = scene.lights[index].shape.area() else {
+            return 0.0;
+        };
+
+        let cos_light = -intersection.normal.dot(ray.direction());
+        if !intersection.front_face || cos_light <= 0.0 {
+            return 0.0;
+        }
+
+        // surfaces are sampled uniformly, see `Shape::area`
+        let distance = intersection.t;
+        distance * distance / (cos_light * area) * self.light_probability(scene, index)
+    }
+
+    /// The density, per unit solid angle, with which
+    /// [`Renderer::sample_direct`] would have sampled `direction` from the
+    /// background of `scene`. Zero if it can't be sampled.
+    fn background_pdf(&self, scene: &PreparedScene, direction: Vec3) -> f32 {
+        let pdf = self.background(scene).pdf(direction);
+        if pdf <= 0.0 {
+            return 0.0;
+        }
+
+        pdf * self.background_probability(scene)
+    }
+
+    /// Computes the light arriving directly from the scene's
+    /// [delta lights](DeltaLight) at a point of an ideal diffuse surface,
+    /// weighted like in [`Renderer::sample_direct`].
+    ///
+    /// Delta lights can't be sampled randomly nor hit by rays, so every one
+    /// of them that is enabled and [lights](Object::is_lit_by) `object` is
+    /// tested with a shadow ray, cast at `time`.
+    fn delta_direct(
+        &self,
+        scene: &PreparedScene,
+        object: &Object,
+        intersection: &Intersection,
+        time: f32,
+    ) -> Vec3 {
+        // delta lights are linked after the other lights
+        let first_index = scene.lights.len();
+        scene
+            .delta_lights
+            .iter()
+            .enumerate()
+            .filter(|&(index, light)| light.enabled() && object.is_lit_by(first_index + index))
+            .filter_map(|(_, light)| light.incident(intersection.point))
+            .filter_map(|incident| {
+                let cos_surface = intersection.normal.dot(incident.direction);
+                if cos_surface <= 0.0 {
+                    return None;
+                }
+
+                stats::count(|stats| stats.shadow_rays += 1);
+                let shadow_ray = Ray::new(intersection.point, incident.direction).with_time(time);
+                if scene.occluded(shadow_ray, incident.distance - EPSILON) {
+                    return None;
+                }
+
+                Some(incident.light.to_sample() * cos_surface / std::f32::consts::PI)
+            })
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        background::EnvironmentMap,
+        common::Color,
+        distribution::Distribution,
+        light::{DeltaLight, PointLight, SpotLight},
+        material::{Emissive, Isotropic, Simple},
+        object::Object,
+        shape::{ConstantMedium, Cuboid, Plane, Shape, Sphere},
+        texture::SolidColor,
+    };
+    use glam::Affine3A;
+    use picture::prelude::{ImgBuf, ImgView, RGB};
+    use std::sync::Arc;
+
+    fn simple(color: Color, diffuse: f32) -> Arc<Simple> {
+        Arc::new(Simple {
+            albedo: Box::new(SolidColor(color)),
+            diffuse,
+            fuzzyness: 0.0,
+        })
+    }
+
+    pub(super) fn scene() -> PreparedScene {
+        scene_with_light(Light::new(
+            Sphere {
+                center: Vec3::new(0.0, 4.0, 0.0),
+                radius: 1.0,
+            }
+            .into(),
+            Color::new(1.0, 1.0, 1.0),
+            4.0,
+        ))
+    }
+
+    fn scene_with_light(light: Light) -> PreparedScene {
+        Scene::builder()
+            .camera(Camera::new(
+                Vec3::new(0.0, 1.0, -6.0),
+                Vec3::new(0.0, -0.1, 1.0).normalize(),
+                60f32.to_radians(),
+                1.0,
+            ))
+            .plane(
+                Vec3::new(0.0, -1.0, 0.0),
+                Vec3::Y,
+                simple(Color::new(0.8, 0.8, 0.8), 1.0),
+            )
+            .sphere(
+                Vec3::new(-1.2, 0.0, 0.0),
+                1.0,
+                simple(Color::new(0.9, 0.5, 0.3), 1.0),
+            )
+            .sphere(
+                Vec3::new(1.2, 0.0, 0.0),
+                1.0,
+                simple(Color::new(1.0, 1.0, 1.0), 0.0),
+            )
+            .add_light(light)
+            .build()
+            .build()
+    }
+
+    /// A scene with nothing but a white floor going through the origin, lit
+    /// by `light` and seen from straight above.
+    fn floor(light: impl Into<DeltaLight>) -> PreparedScene {
+        Scene::builder()
+            .camera(Camera::orthographic(
+                Vec3::new(0.0, 4.0, 0.0),
+                -Vec3::Y,
+                4.0,
+                1.0,
+            ))
+            .plane(Vec3::ZERO, Vec3::Y, simple(Color::new(1.0, 1.0, 1.0), 1.0))
+            .add_delta_light(light)
+            .build()
+            .build()
+    }
+
+    pub(super) fn renderer() -> Renderer {
+        Renderer {
+            sample_count: 4,
+            indirect_count: 4,
+            exposure: Exposure::Manual { max_value: 1.0 },
+            background: Background::solid(LightRay {
+                color: Color::new(0.2, 0.3, 0.5),
+                intensity: 1.0,
+            }),
+            rr_min_depth: 2,
+            tone_map: ToneMap::Clamp,
+            exposure_compensation: 0.0,
+            sample_clamp: None,
+            indirect_clamp: None,
+            seed: 7,
+            tile_size: 16,
+            parallel: true,
+            sampler: SamplerKind::Independent,
+            stratified: false,
+            transparent_background: false,
+            region: None,
+            motion_blur: false,
+            filter: Filter::Box,
+            adaptive: None,
+            encoding: Encoding::Linear,
+        }
+    }
+
+    /// The raw bytes of the pixels of `buffer`.
+    pub(super) fn bytes(buffer: &ImgBuf<RGB<f32>, Vec<RGB<f32>>>) -> Vec<u8> {
+        buffer
+            .pixels()
+            .flat_map(|pixel| [pixel.r, pixel.g, pixel.b])
+            .flat_map(f32::to_le_bytes)
+            .collect()
+    }
+
+    /// 64 bit FNV-1a, which unlike the hasher of the standard library is
+    /// guaranteed to stay the same.
+    fn fnv1a(bytes: &[u8]) -> u64 {
+        bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        })
+    }
+
+    #[test]
+    fn seeded_renders_are_deterministic() {
+        let scene = scene();
+        let renderer = renderer();
+        let render = |threads| {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+
+            let mut buffer = ImgBuf::<RGB<f32>, Vec<_>>::new(64, 64);
+            pool.install(|| renderer.render(&scene, &mut buffer));
+            bytes(&buffer)
+        };
+
+        let single = render(1);
+        let multi = render(4);
+        assert!(single == multi, "renders differ with the thread count");
+
+        // pins the exact image, so that the same seed keeps giving the same
+        // render across versions. changes to how samples are taken or paths
+        // are traced are expected to update it, but nothing else should
+        assert_eq!(fnv1a(&single), 6326875910854022507);
+    }
+
+    /// The paths `Renderer::trace` used to take recursively, for scenes
+    /// without diffuse surfaces, where no light is sampled directly.
+    fn trace_recursive(
+        renderer: &Renderer,
+        ray: Ray,
+        scene: &PreparedScene,
+        depth: u32,
+        bounce: u32,
+        throughput: Vec3,
+        rng: &mut Sampler,
+    ) -> Vec3 {
+        let background = renderer
+            .background(scene)
+            .light(ray.direction())
+            .to_sample();
+        if bounce == depth {
+            return background;
+        }
+
+        let object = scene.closest_hit(ray);
+        let light = scene
+            .lights
+            .iter()
+            .filter_map(|light| Some((light, light.shape.intersection(ray)?)))
+            .min_by_key(|(_, intersection)| FloatOrd(intersection.t))
+            .filter(|(_, light)| object.as_ref().is_none_or(|(_, obj)| light.t < obj.t));
+        if let Some((light, intersection)) = light {
+            return light.light_ray_at(intersection.t).to_sample();
+        }
+
+        let Some((obj, intersection)) = object else {
+            return background;
+        };
+
+        let emitted = obj.material.emitted(&intersection).to_sample();
+        let Some(scatter) = obj.material.scatter(ray, &intersection, rng) else {
+            return emitted;
+        };
+        assert!(!scatter.diffuse);
+
+        let attenuation = scatter.attenuation.to_vec3();
+        let throughput = throughput * attenuation;
+        let survival = if bounce >= renderer.rr_min_depth {
+            throughput.max_element().min(1.0)
+        } else {
+            1.0
+        };
+
+        if rng.gen_range(0.0..1.0) >= survival {
+            return emitted;
+        }
+
+        let ray = scatter
+            .ray
+            .with_time(ray.time())
+            .with_seed(mix64(ray.seed()));
+        let rest = trace_recursive(renderer, ray, scene, depth, bounce + 1, throughput, rng);
+        emitted + attenuation / survival * rest
+    }
+
+    #[test]
+    fn iterative_paths_match_recursive_ones() {
+        let fuzzy = |color| {
+            Arc::new(Simple {
+                albedo: Box::new(SolidColor(color)),
+                diffuse: 0.0,
+                fuzzyness: 0.2,
+            })
+        };
+
+        let scene = Scene::builder()
+            .camera(Camera::new(Vec3::ZERO, Vec3::Z, 1.0, 1.0))
+            .plane(
+                Vec3::new(0.0, -1.0, 0.0),
+                Vec3::Y,
+                fuzzy(Color::new(0.9, 0.9, 0.8)),
+            )
+            .sphere(
+                Vec3::new(-1.0, 0.0, 4.0),
+                1.0,
+                fuzzy(Color::new(0.9, 0.4, 0.4)),
+            )
+            .sphere(
+                Vec3::new(1.0, 0.0, 4.0),
+                1.0,
+                fuzzy(Color::new(0.4, 0.9, 0.4)),
+            )
+            .add_light(Light::new(
+                Sphere {
+                    center: Vec3::new(0.0, 3.0, 4.0),
+                    radius: 1.0,
+                }
+                .into(),
+                Color::new(1.0, 1.0, 1.0),
+                4.0,
+            ))
+            .build()
+            .build();
+        let renderer = renderer();
+
+        for index in 0..256 {
+            let direction = Vec3::new(
+                index as f32 / 128.0 - 1.0,
+                (index % 16) as f32 / 32.0 - 0.25,
+                1.0,
+            );
+            let ray = Ray::new(Vec3::ZERO, direction.normalize());
+
+            let mut rng = Sampler::new(SamplerKind::Independent, 1, index);
+            let (iterative, _) = renderer.trace(ray, &scene, 8, &mut rng);
+            let mut rng = Sampler::new(SamplerKind::Independent, 1, index);
+            let recursive = trace_recursive(&renderer, ray, &scene, 8, 0, Vec3::ONE, &mut rng);
+
+            assert!(
+                (iterative - recursive).abs().max_element()
+                    <= 1e-5 * recursive.max_element().max(1.0),
+                "{iterative} != {recursive}"
+            );
+        }
+    }
+
+    #[test]
+    fn deep_paths_dont_grow_the_stack() {
+        // two parallel mirrors, bouncing the ray back and forth forever
+        let mirror = simple(Color::new(1.0, 1.0, 1.0), 0.0);
+        let scene = Scene::builder()
+            .camera(Camera::new(Vec3::ZERO, Vec3::Z, 1.0, 1.0))
+            .plane(Vec3::new(0.0, 0.0, -1.0), Vec3::Z, mirror.clone())
+            .plane(Vec3::new(0.0, 0.0, 1.0), -Vec3::Z, mirror)
+            .build()
+            .build();
+        let renderer = Renderer {
+            rr_min_depth: u32::MAX,
+            ..renderer()
+        };
+
+        let light = renderer.trace_ray(Ray::new(Vec3::ZERO, Vec3::Z), &scene, 100_000);
+        assert_eq!(
+            light.to_sample(),
+            renderer.background.light(Vec3::Z).to_sample()
+        );
+
+        let renderer = Renderer {
+            indirect_count: 64,
+            ..renderer
+        };
+        let mut buffer = ImgBuf::<RGB<f32>, Vec<_>>::new(8, 8);
+        renderer.render(&scene, &mut buffer);
+    }
+
+    fn rmse(a: &ImgBuf<RGB<f32>, Vec<RGB<f32>>>, b: &ImgBuf<RGB<f32>, Vec<RGB<f32>>>) -> f32 {
+        let squared: f32 = a
+            .pixels()
+            .zip(b.pixels())
+            .flat_map(|(a, b)| [a.r - b.r, a.g - b.g, a.b - b.b])
+            .map(|difference| difference * difference)
+            .sum();
+
+        (squared / (3 * a.pixels().count()) as f32).sqrt()
+    }
+
+    #[test]
+    fn sampling_lights_directly_converges_faster() {
+        // a small light, which scattered rays rarely find
+        let sphere = || Sphere {
+            center: Vec3::new(0.0, 4.0, 0.0),
+            radius: 0.25,
+        };
+        // as bright as the light of the other tests
+        let light = |shape| Light::new(shape, Color::new(1.0, 1.0, 1.0), 64.0);
+        let sampled = scene_with_light(light(sphere().into()));
+        // transformed shapes can't be sampled, so paths only find this light
+        // by scattering into it
+        let scattered =
+            scene_with_light(light(Shape::from(sphere()).transformed(Affine3A::IDENTITY)));
+
+        let render = |scene: &PreparedScene, sample_count, seed| {
+            // lit by the light alone
+            let renderer = Renderer {
+                sample_count,
+                seed,
+                background: Background::solid(LightRay::ZERO),
+                ..renderer()
+            };
+            let mut buffer = ImgBuf::<RGB<f32>, Vec<_>>::new(24, 24);
+            renderer.render(scene, &mut buffer);
+            buffer
+        };
+
+        let reference = render(&sampled, 1024, 0);
+        let direct = rmse(&render(&sampled, 16, 1), &reference);
+        let indirect = rmse(&render(&scattered, 160, 1), &reference);
+        assert!(direct < indirect, "{direct} >= {indirect}");
+
+        // both converge to the same image
+        let total = |buffer: &ImgBuf<RGB<f32>, Vec<RGB<f32>>>| {
+            buffer
+                .pixels()
+                .map(|pixel| pixel.r + pixel.g + pixel.b)
+                .sum::<f32>()
+        };
+        let (expected, converged) = (total(&reference), total(&render(&scattered, 1024, 2)));
+        assert!(
+            (converged / expected - 1.0).abs() < 0.05,
+            "{converged} != {expected}"
+        );
+    }
+
+    #[test]
+    fn sampling_lights_by_power_converges_faster() {
+        let light = |x, intensity| {
+            Light::new(
+                Sphere {
+                    center: Vec3::new(x, 3.0, 0.0),
+                    radius: 0.5,
+                }
+                .into(),
+                Color::new(1.0, 1.0, 1.0),
+                intensity,
+            )
+        };
+        // a dim light and a huge one, out of view, lighting a floor
+        let scene = || {
+            Scene::builder()
+                .camera(Camera::look_at(
+                    Vec3::new(0.0, 3.0, 0.0),
+                    Vec3::new(0.0, -1.0, 0.0),
+                    Vec3::Z,
+                    60f32.to_radians(),
+                    1.0,
+                ))
+                .plane(
+                    Vec3::new(0.0, -1.0, 0.0),
+                    Vec3::Y,
+                    simple(Color::new(0.8, 0.8, 0.8), 1.0),
+                )
+                .add_light(light(-4.0, 0.05))
+                .add_light(light(4.0, 50.0))
+                .build()
+                .build()
+        };
+        let by_power = scene();
+        let mut uniform = scene();
+        uniform.set_light_distribution(Distribution::new([1.0, 1.0]));
+
+        let render = |scene: &PreparedScene, sample_count, seed| {
+            let renderer = Renderer {
+                sample_count,
+                seed,
+                indirect_count: 0,
+                exposure: Exposure::Manual { max_value: 100.0 },
+                background: Background::solid(LightRay::ZERO),
+                ..renderer()
+            };
+            let mut buffer = ImgBuf::<RGB<f32>, Vec<_>>::new(24, 24);
+            renderer.render(scene, &mut buffer);
+            buffer
+        };
+
+        let reference = render(&by_power, 1024, 0);
+        let by_power = rmse(&render(&by_power, 8, 1), &reference);
+        let uniform = rmse(&render(&uniform, 8, 1), &reference);
+        assert!(by_power < 0.8 * uniform, "{by_power} >= {uniform}");
+    }
+
+    #[test]
+    fn lights_with_invalid_intensities_are_never_picked() {
+        for intensity in [-1.0, f32::NAN, f32::INFINITY, 1e38] {
+            let scene = scene_with_light(Light::new(
+                Sphere {
+                    center: Vec3::new(0.0, 4.0, 0.0),
+                    radius: 1.0,
+                }
+                .into(),
+                Color::new(1.0, 1.0, 1.0),
+                intensity,
+            ));
+            assert!(scene.light_distribution().is_none(), "{intensity}");
+        }
+    }
+
+    /// A white sphere over another, both lit directly by a red light to
+    /// their side, but the bottom one only if `bottom_mask` links it.
+    fn linked_scene(light_enabled: bool, bottom_mask: u64) -> PreparedScene {
+        let white = simple(Color::new(1.0, 1.0, 1.0), 1.0);
+        let sphere = |y| {
+            Shape::from(Sphere {
+                center: Vec3::new(0.0, y, 0.0),
+                radius: 1.0,
+            })
+        };
+
+        Scene::builder()
+            .camera(Camera::new(
+                Vec3::new(0.0, 0.0, -6.0),
+                Vec3::Z,
+                60f32.to_radians(),
+                1.0,
+            ))
+            .add_object(Object::new(sphere(1.3), white.clone()).with_light_mask(1))
+            .add_object(Object::new(sphere(-1.3), white).with_light_mask(bottom_mask))
+            .add_light(
+                Light::new(
+                    Sphere {
+                        center: Vec3::new(4.0, 0.0, -2.0),
+                        radius: 0.5,
+                    }
+                    .into(),
+                    Color::new(1.0, 0.0, 0.0),
+                    16.0,
+                )
+                .with_enabled(light_enabled),
+            )
+            .build()
+            .build()
+    }
+
+    /// Renders [`linked_scene`] with direct light only, and returns the
+    /// pixels at the middle of the top and bottom spheres.
+    fn render_linked(light_enabled: bool, bottom_mask: u64) -> [[f32; 3]; 2] {
+        let renderer = Renderer {
+            sample_count: 16,
+            indirect_count: 0,
+            background: Background::solid(LightRay::ZERO),
+            ..renderer()
+        };
+
+        let mut buffer = ImgBuf::<RGB<f32>, Vec<_>>::new(32, 32);
+        renderer.render(&linked_scene(light_enabled, bottom_mask), &mut buffer);
+        [(16, 10), (16, 21)].map(|coords| {
+            let pixel = buffer.pixel(coords).unwrap();
+            [pixel.r, pixel.g, pixel.b]
+        })
+    }
+
+    #[test]
+    fn lights_only_light_linked_objects() {
+        let [top, bottom] = render_linked(true, 1);
+        assert!(top[0] > 0.0 && bottom[0] > 0.0, "linked spheres are unlit");
+
+        let [top, bottom] = render_linked(true, 0);
+        assert!(top[0] > 0.0, "the linked sphere is unlit");
+        assert_eq!(top[1..], [0.0, 0.0]);
+        assert_eq!(bottom, [0.0; 3]);
+
+        // the light can't be sampled, and nothing else lights the spheres
+        let [top, bottom] = render_linked(false, 1);
+        assert_eq!(top, [0.0; 3]);
+        assert_eq!(bottom, [0.0; 3]);
+    }
+
+    #[test]
+    fn disabled_lights_are_invisible() {
+        let renderer = Renderer {
+            background: Background::solid(LightRay::ZERO),
+            ..renderer()
+        };
+        let towards_light = Ray::new(Vec3::new(4.0, 0.0, -6.0), Vec3::Z);
+
+        let seen = renderer.trace_ray(towards_light, &linked_scene(true, 1), 1);
+        assert_eq!(seen.color, Color::new(1.0, 0.0, 0.0));
+        assert_eq!(seen.intensity, 16.0);
+
+        let seen = renderer.trace_ray(towards_light, &linked_scene(false, 1), 1);
+        assert_eq!(seen.to_sample(), Vec3::ZERO);
+    }
+
+    #[test]
+    fn disabled_lights_dont_dilute_the_background() {
+        let environment = EnvironmentMap::new(2, 2, vec![Vec3::ONE; 4]);
+        let light = |enabled| {
+            Light::new(
+                Sphere {
+                    center: Vec3::ZERO,
+                    radius: 1.0,
+                }
+                .into(),
+                Color::new(1.0, 1.0, 1.0),
+                1.0,
+            )
+            .with_enabled(enabled)
+        };
+        let scene = Scene::builder()
+            .camera(Camera::new(Vec3::ZERO, Vec3::Z, 1.0, 1.0))
+            .environment(Background::environment(environment))
+            .add_light(light(true))
+            .add_light(light(false))
+            .add_light(light(false))
+            .build()
+            .build();
+
+        let renderer = renderer();
+        assert_eq!(renderer.background_probability(&scene), 0.5);
+        assert_eq!(renderer.light_probability(&scene, 0), 0.5);
+        assert_eq!(renderer.light_probability(&scene, 1), 0.0);
+    }
+
+    #[test]
+    fn fog_darkens_with_density() {
+        let renderer = Renderer {
+            sample_count: 16,
+            background: Background::solid(LightRay::ZERO),
+            ..renderer()
+        };
+
+        // a glowing wall, seen through a slab of grey fog
+        let brightness = |density| {
+            let fog = ConstantMedium::new(
+                Cuboid {
+                    min: Vec3::new(-20.0, -20.0, -2.0),
+                    max: Vec3::new(20.0, 20.0, 2.0),
+                }
+                .into(),
+                density,
+            );
+            let scene = Scene::builder()
+                .camera(Camera::new(
+                    Vec3::new(0.0, 0.0, -6.0),
+                    Vec3::Z,
+                    60f32.to_radians(),
+                    1.0,
+                ))
+                .plane(
+                    Vec3::new(0.0, 0.0, 4.0),
+                    -Vec3::Z,
+                    Arc::new(Emissive {
+                        color: Color::new(1.0, 1.0, 1.0),
+                        intensity: 1.0,
+                    }),
+                )
+                .shape(
+                    fog,
+                    Arc::new(Isotropic {
+                        color: Color::new(0.5, 0.5, 0.5),
+                    }),
+                )
+                .build()
+                .build();
+
+            let mut buffer = ImgBuf::<RGB<f32>, Vec<_>>::new(16, 16);
+            renderer.render(&scene, &mut buffer);
+            buffer.pixels().map(|pixel| pixel.g).sum::<f32>() / 256.0
+        };
+
+        let thin = brightness(0.01);
+        let medium = brightness(0.3);
+        let thick = brightness(3.0);
+        assert!(thin > 0.9, "thin fog is too dark: {thin}");
+        assert!(
+            thin > medium && medium > thick,
+            "{thin} > {medium} > {thick} doesn't hold"
+        );
+        assert!(thick < 0.5 * thin, "thick fog is too bright: {thick}");
+    }
+
+    #[test]
+    fn traced_rays_depend_on_the_sample() {
+        let scene = scene();
+        let renderer = renderer();
+        // towards the diffuse sphere, whose light is different in every path
+        let ray = Ray::new(Vec3::new(-1.2, 0.0, -6.0), Vec3::Z);
+        let trace = |sample| renderer.trace_sample(ray, &scene, 5, sample).to_sample();
+
+        assert_eq!(trace(3), trace(3));
+
+        let mut samples: Vec<[u32; 3]> = (0..32)
+            .map(|sample| trace(sample).to_array().map(f32::to_bits))
+            .collect();
+        samples.sort_unstable();
+        samples.dedup();
+        assert!(
+            samples.len() > 4,
+            "only {} different samples",
+            samples.len()
+        );
+    }
+
+    #[test]
+    fn point_lights_fall_off_across_the_floor() {
+        let height = 2.0;
+        let intensity = 8.0;
+        let scene = floor(PointLight::new(
+            Vec3::new(0.0, height, 0.0),
+            Color::new(1.0, 1.0, 1.0),
+            intensity,
+        ));
+
+        let renderer = renderer();
+        for x in [0.0, 0.5, 1.0, 2.0, 4.0, 8.0] {
+            let ray = Ray::new(Vec3::new(x, 1.0, 0.0), -Vec3::Y);
+            let (object, intersection) = scene.closest_hit(ray).unwrap();
+            let light = renderer.delta_direct(&scene, object, &intersection, 0.0);
+
+            // inverse-square falloff, times the cosine at the floor
+            let distance_sq = x * x + height * height;
+            let cos = height / distance_sq.sqrt();
+            let expected = intensity * cos / (distance_sq * std::f32::consts::PI);
+            assert!(
+                (light - Vec3::splat(expected)).abs().max_element() <= 1e-4 * expected,
+                "{light} at {x}, expected {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn spot_lights_light_a_disk_as_wide_as_their_cone() {
+        let height = 2.0;
+        let angle = 30f32.to_radians();
+        let scene = floor(SpotLight::new(
+            Vec3::new(0.0, height, 0.0),
+            -Vec3::Y,
+            Color::new(1.0, 1.0, 1.0),
+            4.0 * std::f32::consts::PI,
+            angle,
+            angle,
+        ));
+
+        let mut renderer = renderer();
+        renderer.background = Background::solid(LightRay::ZERO);
+
+        // the view is 4 units wide
+        let size = 128;
+        let mut buffer = ImgBuf::<RGB<f32>, Vec<_>>::new(size, size);
+        renderer.render(&scene, &mut buffer);
+
+        let lit = buffer.pixels().filter(|pixel| pixel.r > 0.0).count();
+        let pixel_area = (4.0 / size as f32).powi(2);
+        let radius = (lit as f32 * pixel_area / std::f32::consts::PI).sqrt();
+        let expected = height * angle.tan();
+        assert!(
+            (radius - expected).abs() < 4.0 / size as f32,
+            "radius {radius}, expected {expected}"
+        );
+    }
+
+    #[test]
+    fn srgb_renders_are_encoded_linear_renders() {
+        let scene = scene();
+        let render = |encoding| {
+            let renderer = Renderer {
+                encoding,
+                ..renderer()
+            };
+
+            let mut buffer = ImgBuf::<RGB<f32>, Vec<_>>::new(32, 32);
+            renderer.render(&scene, &mut buffer);
+            buffer
+        };
+
+        let linear = render(Encoding::Linear);
+        let srgb = render(Encoding::Srgb);
+        for (linear, srgb) in linear.pixels().zip(srgb.pixels()) {
+            let encoded = [linear.r, linear.g, linear.b].map(tonemap::linear_to_srgb);
+            assert_eq!(encoded, [srgb.r, srgb.g, srgb.b]);
+        }
+    }
+
+    #[test]
+    fn gradient_backgrounds_go_from_horizon_to_zenith() {
+        let horizon = LightRay {
+            color: Color::new(1.0, 1.0, 1.0),
+            intensity: 1.0,
+        };
+        let zenith = LightRay {
+            color: Color::new(0.5, 0.7, 1.0),
+            intensity: 1.0,
+        };
+
+        let renderer = Renderer {
+            background: Background::gradient(horizon, zenith),
+            ..renderer()
+        };
+        let scene = Scene::builder()
+            .camera(Camera::new(Vec3::ZERO, Vec3::Z, 1.0, 1.0))
+            .build()
+            .build();
+
+        let seen = |direction: Vec3| {
+            let ray = Ray::new(Vec3::ZERO, direction.normalize());
+            renderer.trace_ray(ray, &scene, 4).to_sample()
+        };
+        let assert_sees = |direction: Vec3, expected: Vec3| {
+            let seen = seen(direction);
+            assert!(
+                (seen - expected).abs().max_element() < 1e-6,
+                "{seen} towards {direction}, expected {expected}"
+            );
+        };
+
+        assert_sees(Vec3::Y, zenith.to_sample());
+        for direction in [Vec3::X, Vec3::Z, -Vec3::X, Vec3::new(1.0, 0.0, -1.0)] {
+            assert_sees(direction, horizon.to_sample());
+        }
+
+        // 30 degrees up is halfway, and looking down is like the horizon
+        let halfway = horizon.to_sample().lerp(zenith.to_sample(), 0.5);
+        assert_sees(Vec3::new(3f32.sqrt(), 1.0, 0.0), halfway);
+        assert_sees(-Vec3::Y, horizon.to_sample());
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn ambient_light_is_a_solid_background() {
+        let light = LightRay {
+            color: Color::new(0.1, 0.2, 0.3),
+            intensity: 2.0,
+        };
+
+        let mut renderer = renderer();
+        renderer.set_ambient_light(light);
+        assert_eq!(renderer.ambient_light(), Some(light));
+        assert_eq!(renderer.background.light(Vec3::Y), light);
+        assert_eq!(renderer.background.light(-Vec3::X), light);
+
+        renderer.background = Background::gradient(light, LightRay::ZERO);
+        assert_eq!(renderer.ambient_light(), None);
+    }
+
+    #[test]
+    fn delta_lights_can_be_disabled_and_linked() {
+        let light = |enabled| {
+            DeltaLight::from(PointLight::new(
+                Vec3::new(0.0, 2.0, 0.0),
+                Color::new(1.0, 1.0, 1.0),
+                4.0,
+            ))
+            .with_enabled(enabled)
+        };
+
+        // the area light is far away, so it's the delta light that's linked
+        // through the second bit
+        let build = |enabled| {
+            Scene::builder()
+                .camera(Camera::new(Vec3::ZERO, Vec3::Z, 1.0, 1.0))
+                .plane(Vec3::ZERO, Vec3::Y, simple(Color::new(1.0, 1.0, 1.0), 1.0))
+                .add_light(Light::new(
+                    Sphere {
+                        center: Vec3::new(0.0, 1000.0, 0.0),
+                        radius: 1.0,
+                    }
+                    .into(),
+                    Color::new(1.0, 1.0, 1.0),
+                    1.0,
+                ))
+                .add_delta_light(light(enabled))
+                .build()
+                .build()
+        };
+
+        let renderer = renderer();
+        let direct = |scene: &PreparedScene, mask: Option<u64>| {
+            let ray = Ray::new(Vec3::new(0.0, 1.0, 0.0), -Vec3::Y);
+            let (object, intersection) = scene.closest_hit(ray).unwrap();
+            let object = Object {
+                light_mask: mask,
+                ..Object::new(
+                    Plane::new(Vec3::ZERO, Vec3::Y).into(),
+                    object.material.clone(),
+                )
+            };
+
+            renderer.delta_direct(scene, &object, &intersection, 0.0)
+        };
+
+        let enabled = build(true);
+        assert!(direct(&enabled, None).min_element() > 0.0);
+        assert!(direct(&enabled, Some(0b10)).min_element() > 0.0);
+        assert_eq!(direct(&enabled, Some(0b01)), Vec3::ZERO);
+        assert_eq!(direct(&enabled, Some(0)), Vec3::ZERO);
+
+        let disabled = build(false);
+        assert_eq!(direct(&disabled, None), Vec3::ZERO);
+        assert_eq!(direct(&disabled, Some(0b10)), Vec3::ZERO);
+    }
+
+    #[test]
+    fn cancelled_renders_return_early_with_finished_tiles() {
+        let scene = scene();
+        let renderer = Renderer {
+            sample_count: 32,
+            parallel: false,
+            ..renderer()
+        };
+
+        let size = 64;
+        let untouched = RGB::new(-1.0, -1.0, -1.0);
+        let blank = || {
+            let mut buffer = ImgBuf::<RGB<f32>, Vec<_>>::new(size, size);
+            for y in 0..size {
+                for x in 0..size {
+                    *buffer.pixel_mut((x, y)).unwrap() = untouched;
+                }
+            }
+            buffer
+        };
+
+        let start = Instant::now();
+        renderer.render(&scene, &mut blank());
+        let full_time = start.elapsed();
+
+        // cancel as soon as the first row of tiles is done
+        let tiles_per_row = (size / renderer.tile_size) as usize;
+        let cancel = AtomicBool::new(false);
+        let progress = |progress: RenderProgress| {
+            if progress.tiles_completed >= tiles_per_row {
+                cancel.store(true, Ordering::Relaxed);
+            }
+        };
+
+        let mut buffer = blank();
+        let mut stats = RenderStats::default();
+        let options = RenderOptions {
+            progress: Some(&progress),
+            cancel: Some(&cancel),
+            stats: Some(&mut stats),
+            ..Default::default()
+        };
+        let start = Instant::now();
+        let result = renderer.render_with(&scene, &mut buffer, options);
+        let cancelled_time = start.elapsed();
+
+        assert_eq!(result, RenderResult::Cancelled);
+        assert_eq!(stats.tiles, tiles_per_row as u64);
+        assert_eq!(stats.pixels, (size * renderer.tile_size) as u64);
+        for y in 0..size {
+            for x in 0..size {
+                let pixel = *buffer.pixel((x, y)).unwrap();
+                if y < renderer.tile_size {
+                    assert_ne!(pixel, untouched, "({x}, {y}) wasn't rendered");
+                } else {
+                    assert_eq!(pixel, untouched, "({x}, {y}) was rendered");
+                }
+            }
+        }
+
+        // a quarter of the image shouldn't take anywhere near as long as
+        // the whole of it
+        assert!(
+            cancelled_time < full_time / 2,
+            "{cancelled_time:?} cancelled, {full_time:?} in full"
+        );
+    }
+
+    #[test]
+    fn regions_match_the_full_render() {
+        let scene = scene();
+        let size = 48;
+        let region = Rect {
+            x: 13,
+            y: 7,
+            width: 21,
+            height: 30,
+        };
+
+        for filter in [
+            Filter::Box,
+            Filter::Gaussian {
+                radius: 1.5,
+                sigma: 0.5,
+            },
+        ] {
+            let full_renderer = Renderer {
+                filter,
+                ..renderer()
+            };
+            let mut full = ImgBuf::<RGB<f32>, Vec<_>>::new(size, size);
+            full_renderer.render(&scene, &mut full);
+
+            let renderer = Renderer {
+                region: Some(region),
+                filter,
+                ..renderer()
+            };
+            let untouched = RGB::new(-1.0, -1.0, -1.0);
+            let mut crop = ImgBuf::<RGB<f32>, Vec<_>>::new(size, size);
+            for y in 0..size {
+                for x in 0..size {
+                    *crop.pixel_mut((x, y)).unwrap() = untouched;
+                }
+            }
+            renderer.render(&scene, &mut crop);
+
+            for y in 0..size {
+                for x in 0..size {
+                    let inside = (region.x..region.x + region.width).contains(&x)
+                        && (region.y..region.y + region.height).contains(&y);
+                    let expected = if inside {
+                        *full.pixel((x, y)).unwrap()
+                    } else {
+                        untouched
+                    };
+                    assert_eq!(
+                        *crop.pixel((x, y)).unwrap(),
+                        expected,
+                        "{filter:?} at ({x}, {y})"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn constant_images_resolve_exactly_under_every_filter() {
+        // nothing but the background, so every sample has the same value
+        let scene = Scene::builder()
+            .camera(Camera::new(Vec3::ZERO, Vec3::Z, 1.0, 1.0))
+            .build()
+            .build();
+        let background = Vec3::new(0.2, 0.3, 0.5);
+
+        for filter in [
+            Filter::Box,
+            Filter::tent(1.0),
+            Filter::tent(2.5),
+            Filter::gaussian(1.5, 0.5),
+            Filter::gaussian(3.0, 1.0),
+        ] {
+            let renderer = Renderer {
+                filter,
+                ..renderer()
+            };
+            let mut buffer = ImgBuf::<RGB<f32>, Vec<_>>::new(24, 24);
+            renderer.render(&scene, &mut buffer);
+
+            // including at the edges, where part of the filter is outside
+            for pixel in buffer.pixels() {
+                let pixel = Vec3::new(pixel.r, pixel.g, pixel.b);
+                assert!(
+                    (pixel - background).abs().max_element() < 1e-6,
+                    "{pixel} with {filter:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn every_sample_traces_one_primary_ray() {
+        let scene = scene();
+        let (width, height) = (40, 24);
+        for (sample_count, parallel) in [(1, false), (4, true), (9, true)] {
+            let renderer = Renderer {
+                sample_count,
+                parallel,
+                ..renderer()
+            };
+            let mut buffer = ImgBuf::<RGB<f32>, Vec<_>>::new(width, height);
+            let stats = renderer.render(&scene, &mut buffer);
+
+            let samples = (width * height * sample_count) as u64;
+            assert_eq!(stats.primary_rays, samples);
+            assert_eq!(stats.samples, samples);
+            assert_eq!(stats.pixels, (width * height) as u64);
+            assert_eq!(stats.samples_per_pixel(), sample_count as f64);
+
+            // some of the paths bounce, and some light is sampled
+            assert!(stats.secondary_rays > 0);
+            assert!(stats.shadow_rays > 0);
+        }
+    }
+
+    #[test]
+    fn tiny_budgets_complete_the_first_pass_on_time() {
+        let scene = scene();
+        let (width, height) = (40, 24);
+
+        // time a single pass, to know how late render_for may return
+        let single = Renderer {
+            sample_count: 1,
+            ..renderer()
+        };
+        let mut single_pass = ImgBuf::<RGB<f32>, Vec<_>>::new(width, height);
+        let start = Instant::now();
+        single.render(&scene, &mut single_pass);
+        let pass = start.elapsed();
+
+        let budget = Duration::from_millis(1);
+        let mut buffer = ImgBuf::<RGB<f32>, Vec<_>>::new(width, height);
+        let start = Instant::now();
+        let stats = renderer().render_for(&scene, &mut buffer, budget);
+        let elapsed = start.elapsed();
+
+        // the first pass is always completed...
+        assert_eq!(stats.pixels, (width * height) as u64);
+        assert!(stats.samples >= stats.pixels);
+        assert!(stats.samples_per_pixel() >= 1.0);
+        if stats.samples == stats.pixels {
+            assert_eq!(bytes(&buffer), bytes(&single_pass));
+        }
+
+        // ...and at most one more is started, with plenty of slack for
+        // noisy machines
+        let limit = budget + pass * 4 + Duration::from_millis(250);
+        assert!(elapsed <= limit, "took {elapsed:?}, limit {limit:?}");
+    }
+
+    #[test]
+    fn normal_aovs_match_analytic_normals() {
+        let (center, radius) = (Vec3::new(0.3, -0.2, 0.0), 1.0);
+        let scene = Scene::builder()
+            .camera(Camera::new(
+                Vec3::new(0.0, 0.0, -4.0),
+                Vec3::Z,
+                60f32.to_radians(),
+                1.0,
+            ))
+            .sphere(center, radius, simple(Color::new(0.9, 0.5, 0.3), 1.0))
+            .build()
+            .build();
+
+        let (width, height) = (33, 33);
+        let mut buffer = ImgBuf::<RGB<f32>, Vec<_>>::new(width, height);
+        let (mut depth, mut normal) = (Vec::new(), Vec::new());
+        let options = RenderOptions {
+            aovs: Some(Aovs {
+                depth: Some(&mut depth),
+                normal: Some(&mut normal),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        renderer().render_with(&scene, &mut buffer, options);
+        assert_eq!(normal.len(), (width * height) as usize);
+
+        // intersect the ray through the center of every pixel with the
+        // sphere by hand
+        let plane = scene.camera.plane();
+        let mut hits = 0;
+        for y in 0..height {
+            for x in 0..width {
+                let index = (y * width + x) as usize;
+                let position = (
+                    (x as f32 + 0.5) / width as f32,
+                    (y as f32 + 0.5) / height as f32,
+                );
+                let ray = scene.camera.ray(&plane, position, &mut rand::thread_rng());
+
+                let offset = ray.point() - center;
+                let b = offset.dot(ray.direction());
+                let discriminant = b * b - (offset.length_squared() - radius * radius);
+                if discriminant < 0.0 {
+                    assert_eq!(normal[index], Vec3::ZERO, "({x}, {y})");
+                    assert_eq!(depth[index], f32::INFINITY, "({x}, {y})");
+                    continue;
+                }
+
+                let t = -b - discriminant.sqrt();
+                let expected = (ray.point() + ray.direction() * t - center) / radius;
+                assert!(
+                    normal[index].abs_diff_eq(expected, 1e-4),
+                    "({x}, {y}): {} != {expected}",
+                    normal[index]
+                );
+                assert!((depth[index] - t).abs() < 1e-4, "({x}, {y})");
+                hits += 1;
+            }
+        }
+
+        // the sphere covers a good part of the image
+        assert!(hits > 100, "only {hits} hits");
+
+        // and the middle of the image, near the center of the sphere, faces
+        // the camera
+        let front = (height / 2 * width + width / 2) as usize;
+        assert!(normal[front].dot(-Vec3::Z) > 0.9, "{}", normal[front]);
+    }
+
+    #[test]
+    fn auto_exposure_ignores_the_brightness_of_the_scene() {
+        let render = |intensity, exposure| {
+            let scene = scene_with_light(Light::new(
+                Sphere {
+                    center: Vec3::new(0.0, 4.0, 0.0),
+                    radius: 1.0,
+                }
+                .into(),
+                Color::new(1.0, 1.0, 1.0),
+                intensity,
+            ));
+            // a black background, so that all of the light scales together
+            let renderer = Renderer {
+                exposure,
+                background: Background::solid(LightRay::ZERO),
+                tone_map: ToneMap::Reinhard,
+                ..renderer()
+            };
+
+            let mut buffer = ImgBuf::<RGB<f32>, Vec<_>>::new(40, 24);
+            renderer.render(&scene, &mut buffer);
+            buffer
+        };
+
+        let auto = Exposure::Auto {
+            key: 0.18,
+            clip: 0.01,
+        };
+        let dim = render(100.0, auto);
+        let bright = render(10000.0, auto);
+        let difference = dim
+            .pixels()
+            .zip(bright.pixels())
+            .flat_map(|(a, b)| [a.r - b.r, a.g - b.g, a.b - b.b])
+            .fold(0.0f32, |max, difference| max.max(difference.abs()));
+        assert!(difference < 1e-3, "{difference}");
+
+        // the images aren't just black
+        let average: f32 = bright.pixels().map(|pixel| pixel.g).sum::<f32>() / (40 * 24) as f32;
+        assert!(average > 0.1, "{average}");
+
+        // while with a manual exposure, they're nothing alike
+        let manual = Exposure::Manual { max_value: 1.0 };
+        let dim = render(100.0, manual);
+        let bright = render(10000.0, manual);
+        assert!(rmse(&dim, &bright) > 0.1);
+    }
+
+    #[test]
+    fn reinhard_keeps_the_highlights_clamping_loses() {
+        let scene = scene();
+        let render = |tone_map| {
+            // overexposed, so that a good part of the image is brighter
+            // than white
+            let renderer = Renderer {
+                exposure: Exposure::Manual { max_value: 0.25 },
+                tone_map,
+                ..renderer()
+            };
+            let mut buffer = ImgBuf::<RGB<f32>, Vec<_>>::new(40, 24);
+            renderer.render(&scene, &mut buffer);
+            buffer
+        };
+
+        let clamped = render(ToneMap::Clamp);
+        let reinhard = render(ToneMap::Reinhard);
+
+        // the pixels clamping turns white...
+        let white: Vec<_> = clamped
+            .pixels()
+            .zip(reinhard.pixels())
+            .filter(|(clamped, _)| clamped.r == 1.0 && clamped.g == 1.0 && clamped.b == 1.0)
+            .map(|(_, reinhard)| reinhard.r + reinhard.g + reinhard.b)
+            .collect();
+        assert!(white.len() > 50, "only {} white pixels", white.len());
+
+        // ...are never white with reinhard, and still differ from each other
+        assert!(white.iter().all(|&sum| sum < 3.0));
+        let mean = white.iter().sum::<f32>() / white.len() as f32;
+        let variance =
+            white.iter().map(|sum| (sum - mean).powi(2)).sum::<f32>() / white.len() as f32;
+        assert!(variance.sqrt() > 0.01, "{variance}");
+
+        // and reinhard never clips anything at all
+        assert!(reinhard
+            .pixels()
+            .all(|pixel| pixel.r < 1.0 && pixel.g < 1.0 && pixel.b < 1.0));
+    }
+
+    #[test]
+    fn indirect_clamps_remove_fireflies() {
+        // a small and bright light over a floor, next to a mirror. paths
+        // bouncing off the floor and then the mirror rarely find it, and
+        // become fireflies
+        let scene = Scene::builder()
+            .camera(Camera::new(
+                Vec3::new(0.0, 3.0, -6.0),
+                Vec3::new(0.0, -0.5, 1.0).normalize(),
+                60f32.to_radians(),
+                1.6,
+            ))
+            .plane(Vec3::ZERO, Vec3::Y, simple(Color::new(0.8, 0.8, 0.8), 1.0))
+            .plane(
+                Vec3::new(3.0, 0.0, 0.0),
+                -Vec3::X,
+                simple(Color::new(0.9, 0.9, 0.9), 0.0),
+            )
+            .add_light(Light::new(
+                Sphere {
+                    center: Vec3::new(0.0, 2.0, 0.0),
+                    radius: 0.5,
+                }
+                .into(),
+                Color::new(1.0, 1.0, 1.0),
+                64.0,
+            ))
+            .build()
+            .build();
+        let render = |sample_count, indirect_clamp| {
+            // exposed so that nothing reaches white, fireflies included
+            let renderer = Renderer {
+                sample_count,
+                exposure: Exposure::Manual { max_value: 1e3 },
+                background: Background::solid(LightRay::ZERO),
+                indirect_clamp,
+                ..renderer()
+            };
+            let mut buffer = ImgBuf::<RGB<f32>, Vec<_>>::new(64, 40);
+            renderer.render(&scene, &mut buffer);
+            buffer
+                .pixels()
+                .map(|pixel| luminance(Vec3::new(pixel.r, pixel.g, pixel.b)))
+                .collect::<Vec<_>>()
+        };
+
+        // pixels much brighter than they converge to
+        let reference = render(128, None);
+        let outliers = |image: &[f32]| {
+            image
+                .iter()
+                .zip(&reference)
+                .filter(|&(&pixel, &reference)| pixel > 4.0 * reference + 1e-4)
+                .count()
+        };
+
+        let unclamped = render(4, None);
+        let clamped = render(4, Some(1.0));
+        let (before, after) = (outliers(&unclamped), outliers(&clamped));
+        assert!(before >= 10, "only {before} fireflies");
+        assert!(
+            after * 4 <= before,
+            "{after} fireflies left out of {before}"
+        );
+
+        // the light itself is still as bright
+        let brightest = |image: &[f32]| image.iter().copied().fold(0.0, f32::max);
+        assert_eq!(brightest(&clamped), brightest(&unclamped));
+    }
+
+    #[test]
+    fn low_discrepancy_samplers_converge_faster() {
+        let scene = scene();
+        let render = |sampler, sample_count, seed| {
+            let renderer = Renderer {
+                sampler,
+                sample_count,
+                seed,
+                ..renderer()
+            };
+            let mut buffer = ImgBuf::<RGB<f32>, Vec<_>>::new(32, 20);
+            renderer.render(&scene, &mut buffer);
+            buffer
+        };
+
+        // the error of a few renders against a converged one
+        let reference = render(SamplerKind::Independent, 512, 0);
+        let error = |sampler| {
+            (1..=4)
+                .map(|seed| rmse(&render(sampler, 16, seed), &reference))
+                .sum::<f32>()
+        };
+
+        let independent = error(SamplerKind::Independent);
+        for sampler in [SamplerKind::Halton, SamplerKind::Sobol] {
+            let error = error(sampler);
+            assert!(
+                error < 0.9 * independent,
+                "{sampler:?}: {error} vs {independent}"
+            );
+        }
+    }
+
+    #[test]
+    fn jittered_samples_antialias_edges() {
+        // a sphere seen from the front, 339.8 pixels in area
+        let radius = 1.3;
+        let scene = Scene::builder()
+            .camera(Camera::orthographic(
+                Vec3::new(0.0, 0.0, -5.0),
+                Vec3::Z,
+                4.0,
+                1.0,
+            ))
+            .sphere(Vec3::ZERO, radius, simple(Color::new(0.9, 0.5, 0.3), 1.0))
+            .build()
+            .build();
+        let (width, height) = (32, 32);
+        let area = std::f32::consts::PI * radius * radius / (4.0 / width as f32).powi(2);
+
+        for (sample_count, stratified) in [(16, true), (16, false), (10, true)] {
+            let renderer = Renderer {
+                sample_count,
+                stratified,
+                ..renderer()
+            };
+            let mut buffer = ImgBuf::<RGB<f32>, Vec<_>>::new(width, height);
+            let (mut alpha, mut depth) = (Vec::new(), Vec::new());
+            let options = RenderOptions {
+                aovs: Some(Aovs {
+                    alpha: Some(&mut alpha),
+                    depth: Some(&mut depth),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            };
+            renderer.render_with(&scene, &mut buffer, options);
+
+            // pixels on the edge are only partially covered by the samples,
+            // spread over the pixel, while the depth comes from a single ray
+            // through their center, which either sees the sphere or doesn't
+            let case = format!("{sample_count} samples, stratified: {stratified}");
+            let edges: Vec<_> = (0..alpha.len())
+                .filter(|&index| alpha[index] > 0.0 && alpha[index] < 1.0)
+                .collect();
+            assert!(edges.len() > 20, "{case}");
+            let hits = edges
+                .iter()
+                .filter(|&&index| depth[index].is_finite())
+                .count();
+            assert!(hits > 5 && edges.len() - hits > 5, "{case}: {hits} hits");
+
+            // pixels entirely inside or outside of the sphere are covered
+            // completely or not at all
+            for (index, &alpha) in alpha.iter().enumerate() {
+                let (x, y) = (index as u32 % width, index as u32 / width);
+                let center = Vec3::new(
+                    (x as f32 + 0.5) / width as f32 - 0.5,
+                    (y as f32 + 0.5) / height as f32 - 0.5,
+                    0.0,
+                ) * 4.0;
+                let distance = center.length();
+                let half_diagonal = 4.0 / width as f32 / 2.0 * 2f32.sqrt();
+                if distance < radius - half_diagonal {
+                    assert_eq!(alpha, 1.0, "{case} at ({x}, {y})");
+                } else if distance > radius + half_diagonal {
+                    assert_eq!(alpha, 0.0, "{case} at ({x}, {y})");
+                }
+            }
+
+            // and the coverage adds up to the area of the sphere
+            let coverage: f32 = alpha.iter().sum();
+            assert!(
+                (coverage - area).abs() < 0.02 * area,
+                "{case}: {coverage} vs {area}"
+            );
+        }
     }
 }
@@ -0,0 +1,353 @@
+use super::{PixelSum, PreparedScene, Renderer, Sampling};
+use crate::{filter::Filter, stats::RenderStats, Vec3};
+use picture::{prelude::Pixel, view::ImgViewMut};
+use std::io::{self, Read, Write};
+
+/// The first bytes of every checkpoint, followed by its version.
+const CHECKPOINT_MAGIC: &[u8; 4] = b"PTCK";
+const CHECKPOINT_VERSION: u32 = 2;
+
+/// The settings of `renderer` that change the samples it takes, encoded
+/// like they are in checkpoints. Their size doesn't depend on their values.
+fn sample_settings(renderer: &Renderer) -> Vec<u8> {
+    let mut settings = Vec::new();
+    settings.extend(renderer.seed.to_le_bytes());
+    settings.push(renderer.sampler as u8);
+    settings.extend(renderer.indirect_count.to_le_bytes());
+    settings.extend(renderer.rr_min_depth.to_le_bytes());
+    for clamp in [renderer.sample_clamp, renderer.indirect_clamp] {
+        settings.push(clamp.is_some() as u8);
+        settings.extend(clamp.unwrap_or(0.0).to_le_bytes());
+    }
+
+    settings.push(renderer.transparent_background as u8);
+    settings.push(renderer.motion_blur as u8);
+
+    let filter = match renderer.filter {
+        Filter::Box => [0.0; 3],
+        Filter::Tent { radius } => [1.0, radius, 0.0],
+        Filter::Gaussian { radius, sigma } => [2.0, radius, sigma],
+    };
+    for value in filter {
+        settings.extend(value.to_le_bytes());
+    }
+
+    let region = renderer.region;
+    settings.push(region.is_some() as u8);
+    let region = region.map_or([0; 4], |region| {
+        [region.x, region.y, region.width, region.height]
+    });
+    for value in region {
+        settings.extend(value.to_le_bytes());
+    }
+
+    settings
+}
+
+#[inline(always)]
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+/// Renders an image a few samples per pixel at a time, so that it can be
+/// shown while it refines.
+///
+/// Samples are accumulated, and every one of them is seeded by its index, so
+/// taking `n` steps of one sample gives exactly the same image as taking one
+/// step of `n` samples, or calling [`Renderer::render`] with a
+/// [`Renderer::sample_count`] of `n`.
+///
+/// Long renders can be saved to a checkpoint with
+/// [`ProgressiveRenderer::save_checkpoint`] and picked up later with
+/// [`ProgressiveRenderer::resume_from`], giving the same image as if they
+/// were never interrupted.
+pub struct ProgressiveRenderer<'a> {
+    renderer: &'a Renderer,
+    dimensions: (u32, u32),
+    /// The sum of the samples of every pixel, row by row.
+    sums: Vec<PixelSum>,
+    sample_count: u32,
+}
+
+impl<'a> ProgressiveRenderer<'a> {
+    /// Creates a new [`ProgressiveRenderer`] for images of the given
+    /// dimensions, with no samples taken yet. The settings of `renderer`
+    /// are used for everything but the sample count.
+    pub fn new(renderer: &'a Renderer, (width, height): (u32, u32)) -> Self {
+        Self {
+            renderer,
+            dimensions: (width, height),
+            sums: vec![PixelSum::default(); (width * height) as usize],
+            sample_count: 0,
+        }
+    }
+
+    /// The amount of samples taken so far for every pixel.
+    #[inline(always)]
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    /// Takes `samples` more samples for every pixel of the image, and
+    /// returns the statistics of taking them.
+    pub fn step(&mut self, scene: &PreparedScene, samples: u32) -> RenderStats {
+        let (sums, stats) = self.renderer.render_sums(
+            scene,
+            self.dimensions,
+            &Sampling::Fixed {
+                samples: self.sample_count..self.sample_count + samples,
+                initial: Some(&self.sums),
+            },
+            self.renderer.tile_size,
+            None,
+            |_, _| (),
+        );
+
+        // the new sums include the old ones
+        for (sum, new) in self.sums.iter_mut().zip(sums) {
+            if let Some(new) = new {
+                *sum = new;
+            }
+        }
+
+        self.sample_count += samples;
+        stats
+    }
+
+    /// Discards every sample taken so far. Should be called when the scene
+    /// or the camera change.
+    pub fn reset(&mut self) {
+        self.sums.fill(PixelSum::default());
+        self.sample_count = 0;
+    }
+
+    /// Writes the current image, the tone mapped average of the samples
+    /// taken so far, into `buffer`. Pixels outside of the
+    /// [region](Renderer::region) of the renderer are left untouched.
+    ///
+    /// # Panics
+    /// Panics if no samples were taken yet or if the dimensions of `buffer`
+    /// aren't the ones this was created with.
+    pub fn resolve<I, P>(&self, buffer: &mut I)
+    where
+        I: ImgViewMut<Pixel = P>,
+        P: Pixel<Channels = [f32; 3]>,
+    {
+        assert!(self.sample_count > 0);
+        assert_eq!(buffer.dimensions(), self.dimensions);
+
+        // pixels outside of the region of the renderer never get samples
+        let sums: Vec<_> = self
+            .sums
+            .iter()
+            .map(|sum| (sum.count > 0).then_some(*sum))
+            .collect();
+        self.renderer.resolve(&sums, buffer);
+    }
+
+    /// Writes a checkpoint of this renderer to `writer`: the samples taken
+    /// so far, along with the settings of the [`Renderer`] that change them.
+    pub fn save_checkpoint(&self, mut writer: impl Write) -> io::Result<()> {
+        let (width, height) = self.dimensions;
+        writer.write_all(CHECKPOINT_MAGIC)?;
+        writer.write_all(&CHECKPOINT_VERSION.to_le_bytes())?;
+        writer.write_all(&sample_settings(self.renderer))?;
+        for value in [width, height, self.sample_count] {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+
+        let mut row = Vec::with_capacity(width as usize * 24);
+        for sums in self.sums.chunks(width.max(1) as usize) {
+            row.clear();
+            for sum in sums {
+                for value in sum.sum.to_array() {
+                    row.extend(value.to_le_bytes());
+                }
+
+                row.extend(sum.weight.to_le_bytes());
+                row.extend(sum.count.to_le_bytes());
+                row.extend(sum.hits.to_le_bytes());
+            }
+
+            writer.write_all(&row)?;
+        }
+
+        Ok(())
+    }
+
+    /// Creates a new [`ProgressiveRenderer`] with the samples of a
+    /// checkpoint written by [`ProgressiveRenderer::save_checkpoint`], read
+    /// from `reader`. Taking more steps then gives the same image as if the
+    /// render was never interrupted.
+    ///
+    /// `renderer` must have the same settings as the one the checkpoint was
+    /// made with, except for those that don't change the samples, like the
+    /// exposure or the tone map. The scene isn't saved in checkpoints, so
+    /// it's up to the caller to render the same one.
+    ///
+    /// # Errors
+    /// Fails with [`io::ErrorKind::InvalidData`] if the data read isn't a
+    /// checkpoint or if the settings of `renderer` are different.
+    pub fn resume_from(renderer: &'a Renderer, mut reader: impl Read) -> io::Result<Self> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != CHECKPOINT_MAGIC || read_u32(&mut reader)? != CHECKPOINT_VERSION {
+            return Err(invalid(
+                "not a checkpoint, or one of an unsupported version",
+            ));
+        }
+
+        let settings = sample_settings(renderer);
+        let mut saved = vec![0; settings.len()];
+        reader.read_exact(&mut saved)?;
+        if saved != settings {
+            return Err(invalid(
+                "the checkpoint was made with different renderer settings",
+            ));
+        }
+
+        let width = read_u32(&mut reader)?;
+        let height = read_u32(&mut reader)?;
+        let sample_count = read_u32(&mut reader)?;
+        let pixel_count = width
+            .checked_mul(height)
+            .ok_or_else(|| invalid("the checkpoint has invalid dimensions"))?;
+
+        let mut sums = Vec::with_capacity(pixel_count as usize);
+        for _ in 0..pixel_count {
+            let [r, g, b] = [(); 3].map(|_| read_u32(&mut reader).map(f32::from_bits));
+            sums.push(PixelSum {
+                sum: Vec3::new(r?, g?, b?),
+                weight: f32::from_bits(read_u32(&mut reader)?),
+                count: read_u32(&mut reader)?,
+                hits: f32::from_bits(read_u32(&mut reader)?),
+            });
+        }
+
+        Ok(Self {
+            renderer,
+            dimensions: (width, height),
+            sums,
+            sample_count,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        render::{
+            tests::{bytes, renderer, scene},
+            Exposure,
+        },
+        sampler::SamplerKind,
+    };
+    use picture::prelude::{ImgBuf, RGB};
+
+    fn resolved(progressive: &ProgressiveRenderer) -> Vec<u8> {
+        let mut buffer = ImgBuf::<RGB<f32>, Vec<_>>::new(32, 32);
+        progressive.resolve(&mut buffer);
+        bytes(&buffer)
+    }
+
+    #[test]
+    fn resumed_renders_match_uninterrupted_ones() {
+        let scene = scene();
+        let renderer = renderer();
+
+        let mut uninterrupted = ProgressiveRenderer::new(&renderer, (32, 32));
+        uninterrupted.step(&scene, 2);
+        uninterrupted.step(&scene, 3);
+
+        let mut checkpoint = Vec::new();
+        {
+            let mut interrupted = ProgressiveRenderer::new(&renderer, (32, 32));
+            interrupted.step(&scene, 2);
+            interrupted.save_checkpoint(&mut checkpoint).unwrap();
+        }
+
+        let mut resumed = ProgressiveRenderer::resume_from(&renderer, &checkpoint[..]).unwrap();
+        assert_eq!(resumed.sample_count(), 2);
+        resumed.step(&scene, 3);
+
+        assert_eq!(resumed.sample_count(), 5);
+        assert!(
+            resolved(&resumed) == resolved(&uninterrupted),
+            "resumed render differs"
+        );
+    }
+
+    #[test]
+    fn resuming_rejects_different_settings() {
+        let scene = scene();
+        let original = renderer();
+        let mut progressive = ProgressiveRenderer::new(&original, (32, 32));
+        progressive.step(&scene, 1);
+
+        let mut checkpoint = Vec::new();
+        progressive.save_checkpoint(&mut checkpoint).unwrap();
+
+        let changes: [fn(&mut Renderer); 6] = [
+            |renderer| renderer.seed += 1,
+            |renderer| renderer.sampler = SamplerKind::Sobol,
+            |renderer| renderer.indirect_count += 1,
+            |renderer| renderer.sample_clamp = Some(10.0),
+            |renderer| renderer.filter = Filter::Tent { radius: 1.0 },
+            |renderer| renderer.transparent_background = true,
+        ];
+        for change in changes {
+            let mut different = renderer();
+            change(&mut different);
+
+            let err = ProgressiveRenderer::resume_from(&different, &checkpoint[..])
+                .err()
+                .unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+
+        // settings that don't change the samples can be different
+        let mut brighter = renderer();
+        brighter.exposure = Exposure::Manual { max_value: 0.5 };
+        assert!(ProgressiveRenderer::resume_from(&brighter, &checkpoint[..]).is_ok());
+
+        let err = ProgressiveRenderer::resume_from(&original, &b"not a checkpoint"[..])
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // truncated checkpoints fail instead of resuming with missing pixels
+        let truncated = &checkpoint[..checkpoint.len() - 1];
+        assert!(ProgressiveRenderer::resume_from(&original, truncated).is_err());
+    }
+
+    #[test]
+    fn steps_of_one_sample_match_one_step() {
+        let scene = scene();
+        let renderer = renderer();
+
+        let mut single_steps = ProgressiveRenderer::new(&renderer, (32, 32));
+        for _ in 0..4 {
+            single_steps.step(&scene, 1);
+        }
+
+        let mut one_step = ProgressiveRenderer::new(&renderer, (32, 32));
+        one_step.step(&scene, 4);
+
+        assert_eq!(single_steps.sample_count(), 4);
+        assert!(
+            resolved(&single_steps) == resolved(&one_step),
+            "stepping one sample at a time changes the image"
+        );
+
+        // and both match a render with as many samples
+        assert_eq!(renderer.sample_count, 4);
+        let mut buffer = ImgBuf::<RGB<f32>, Vec<_>>::new(32, 32);
+        renderer.render(&scene, &mut buffer);
+        assert!(bytes(&buffer) == resolved(&one_step), "render differs");
+    }
+}
@@ -0,0 +1,304 @@
+use crate::common::mix64;
+use rand::{rngs::SmallRng, Rng, RngCore, SeedableRng};
+
+/// The kind of sequence a [`Sampler`] draws its numbers from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SamplerKind {
+    /// Every number is independent and uniformly random.
+    #[default]
+    Independent,
+    /// The Halton sequence, a low-discrepancy sequence using the radical
+    /// inverse in a different prime base for every dimension.
+    Halton,
+    /// The Sobol sequence, a low-discrepancy sequence in base 2. Usually
+    /// converges a bit faster than [`SamplerKind::Halton`].
+    Sobol,
+}
+
+/// The first primes, which are the bases of the dimensions of the Halton
+/// sequence.
+const HALTON_PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+/// The degree, coefficients and initial direction numbers of the primitive
+/// polynomials of the dimensions of the Sobol sequence after the first one,
+/// from Joe and Kuo.
+const SOBOL_POLYNOMIALS: [(u32, u32, [u32; 6]); 15] = [
+    (1, 0, [1, 0, 0, 0, 0, 0]),
+    (2, 1, [1, 3, 0, 0, 0, 0]),
+    (3, 1, [1, 3, 1, 0, 0, 0]),
+    (3, 2, [1, 1, 1, 0, 0, 0]),
+    (4, 1, [1, 1, 3, 3, 0, 0]),
+    (4, 4, [1, 3, 5, 13, 0, 0]),
+    (5, 2, [1, 1, 5, 5, 17, 0]),
+    (5, 4, [1, 1, 5, 5, 5, 0]),
+    (5, 7, [1, 1, 7, 11, 19, 0]),
+    (5, 11, [1, 1, 5, 1, 1, 0]),
+    (5, 13, [1, 1, 1, 3, 11, 0]),
+    (5, 14, [1, 3, 5, 5, 31, 0]),
+    (6, 1, [1, 3, 3, 9, 7, 49]),
+    (6, 13, [1, 1, 1, 15, 21, 21]),
+    (6, 16, [1, 3, 1, 13, 27, 49]),
+];
+
+/// The direction numbers of every dimension of the Sobol sequence.
+const SOBOL_DIRECTIONS: [[u32; 32]; 16] = sobol_directions();
+
+const fn sobol_directions() -> [[u32; 32]; 16] {
+    let mut directions = [[0; 32]; 16];
+
+    // the first dimension is the radical inverse in base 2
+    let mut bit = 0;
+    while bit < 32 {
+        directions[0][bit] = 1 << (31 - bit);
+        bit += 1;
+    }
+
+    let mut dimension = 1;
+    while dimension < 16 {
+        let (degree, coefficients, initial) = SOBOL_POLYNOMIALS[dimension - 1];
+        let degree = degree as usize;
+
+        let mut bit = 0;
+        while bit < 32 {
+            directions[dimension][bit] = if bit < degree {
+                initial[bit] << (31 - bit)
+            } else {
+                let mut value = directions[dimension][bit - degree];
+                value ^= value >> degree;
+
+                let mut k = 1;
+                while k < degree {
+                    if (coefficients >> (degree - 1 - k)) & 1 == 1 {
+                        value ^= directions[dimension][bit - k];
+                    }
+                    k += 1;
+                }
+
+                value
+            };
+            bit += 1;
+        }
+
+        dimension += 1;
+    }
+
+    directions
+}
+
+/// A pseudorandom permutation of the digits in base `base`, chosen by
+/// `seed`.
+#[inline(always)]
+fn permute_digit(digit: u32, base: u32, seed: u64) -> u32 {
+    // scramble the bits of the digit with bijections on all numbers with as
+    // many bits as the base, until the result is a digit again (which
+    // always happens, since it's a cycle going through the digit itself)
+    let bits = u32::BITS - (base - 1).leading_zeros();
+    let mask = (1 << bits) - 1;
+    let mut digit = digit;
+    loop {
+        digit ^= seed as u32 & mask;
+        digit = digit.wrapping_mul((seed >> 32) as u32 | 1) & mask;
+        digit ^= digit >> (bits / 2 + 1);
+        digit = digit.wrapping_mul((seed >> 16) as u32 | 1) & mask;
+
+        if digit < base {
+            return digit;
+        }
+    }
+}
+
+/// The radical inverse of `index` in base `base`, its digits mirrored
+/// around the decimal point, with every digit permuted randomly depending
+/// on `seed` and the digits before it (Owen scrambling).
+fn scrambled_radical_inverse(mut index: u32, base: u32, seed: u64) -> f64 {
+    let inverse_base = 1.0 / base as f64;
+    let mut scale = inverse_base;
+    let mut value = 0.0;
+    let mut prefix = seed;
+
+    // keep going past the digits of the index, since its leading zeros get
+    // scrambled too, until digits are too small to matter
+    while scale > 1.0 / (1u64 << 32) as f64 {
+        let digit = index % base;
+        value += permute_digit(digit, base, prefix) as f64 * scale;
+
+        prefix = mix64(prefix ^ digit as u64);
+        index /= base;
+        scale *= inverse_base;
+    }
+
+    value
+}
+
+/// Scrambles the bits of `value`, a number in base 2 after the decimal
+/// point, by flipping every one of them depending on `seed` and the bits
+/// before it (Owen scrambling, with the hash by Burley and Vegdahl).
+#[inline(always)]
+fn owen_scramble(value: u32, seed: u32) -> u32 {
+    // the hash spreads lower bits into higher ones, so the bits are
+    // reversed to spread more significant digits into less significant ones
+    let mut value = value.reverse_bits();
+    value ^= value.wrapping_mul(0x3d20adea);
+    value = value.wrapping_add(seed);
+    value = value.wrapping_mul((seed >> 16) | 1);
+    value ^= value.wrapping_mul(0x05526c56);
+    value ^= value.wrapping_mul(0x53a22864);
+    value.reverse_bits()
+}
+
+/// The source of the random numbers used to render a single sample of a
+/// pixel.
+///
+/// Every number drawn from it comes from the next dimension of the
+/// sequence given by its [kind](SamplerKind), at the index of the sample.
+/// Sequences are scrambled differently for every pixel, so that pixels
+/// don't all use the same points. Once a sequence runs out of dimensions,
+/// numbers are independent and uniformly random.
+///
+/// Numbers are only drawn from the sequence through [`RngCore::next_u32`]
+/// and the upper half of [`RngCore::next_u64`], which is what
+/// [`Rng::gen_range`] uses.
+///
+/// Pairs of numbers drawn with [`Sampler::next_2d`] can also be stratified,
+/// see [`Sampler::with_strata`].
+pub struct Sampler {
+    kind: SamplerKind,
+    /// The index of the sample in the sequence.
+    index: u32,
+    /// The next dimension of the sequence to draw from.
+    dimension: u32,
+    /// Seeds the scrambling of every dimension.
+    scramble: u64,
+    /// The amount of strata along each axis pairs of numbers are split into.
+    strata: Option<u32>,
+    /// The amount of pairs of numbers drawn so far.
+    pairs: u32,
+    rng: SmallRng,
+}
+
+impl Sampler {
+    /// Creates a new [`Sampler`] for the sample with index `index` of a
+    /// pixel. `seed` should be different for every pixel.
+    pub fn new(kind: SamplerKind, seed: u64, index: u32) -> Self {
+        Self {
+            kind,
+            index,
+            dimension: 0,
+            scramble: mix64(seed),
+            strata: None,
+            pairs: 0,
+            rng: SmallRng::seed_from_u64(mix64(seed ^ index as u64)),
+        }
+    }
+
+    /// Returns this sampler, with the pairs of numbers drawn with
+    /// [`Sampler::next_2d`] split into `strata` by `strata` strata of the
+    /// unit square, if present.
+    ///
+    /// Every sample of a pixel then draws each pair from a different
+    /// stratum, as long as there are as many samples as strata, which
+    /// spreads them more evenly than independent numbers. Samplers of the
+    /// same pixel must all be created with the same strata.
+    #[inline(always)]
+    pub fn with_strata(self, strata: Option<u32>) -> Self {
+        Self { strata, ..self }
+    }
+
+    /// Draws a pair of numbers in the \[0..1) range, from a stratum of the
+    /// unit square chosen by the index of the sample if this sampler has
+    /// [strata](Sampler::with_strata).
+    pub fn next_2d(&mut self) -> (f32, f32) {
+        let jitter = (self.gen_range(0.0..1.0), self.gen_range(0.0..1.0));
+        let pair = self.pairs;
+        self.pairs += 1;
+
+        let Some(strata) = self.strata else {
+            return jitter;
+        };
+
+        // the strata are shuffled differently for every pair, so the strata
+        // of different pairs of the same sample aren't correlated
+        let cells = strata * strata;
+        let cell = permute_digit(
+            self.index % cells,
+            cells,
+            mix64(self.scramble ^ mix64(pair as u64)),
+        );
+
+        (
+            ((cell % strata) as f32 + jitter.0) / strata as f32,
+            ((cell / strata) as f32 + jitter.1) / strata as f32,
+        )
+    }
+
+    /// The amount of dimensions the sequence of this sampler has.
+    #[inline(always)]
+    pub fn dimensions(&self) -> u32 {
+        match self.kind {
+            SamplerKind::Independent => 0,
+            SamplerKind::Halton => HALTON_PRIMES.len() as u32,
+            SamplerKind::Sobol => SOBOL_DIRECTIONS.len() as u32,
+        }
+    }
+
+    /// The next number of the sequence, in the whole range of [`u32`], or
+    /// [`None`] if it ran out of dimensions.
+    fn next_in_sequence(&mut self) -> Option<u32> {
+        if self.dimension >= self.dimensions() {
+            return None;
+        }
+
+        let dimension = self.dimension as usize;
+        let scramble = mix64(self.scramble ^ dimension as u64);
+        self.dimension += 1;
+
+        match self.kind {
+            SamplerKind::Independent => None,
+            SamplerKind::Halton => {
+                let value =
+                    scrambled_radical_inverse(self.index, HALTON_PRIMES[dimension], scramble);
+
+                Some((value * (1u64 << 32) as f64) as u32)
+            }
+            SamplerKind::Sobol => {
+                let directions = &SOBOL_DIRECTIONS[dimension];
+                let value = (0..32)
+                    .filter(|bit| self.index >> bit & 1 == 1)
+                    .fold(0, |value, bit| value ^ directions[bit]);
+
+                Some(owen_scramble(value, scramble as u32))
+            }
+        }
+    }
+}
+
+impl RngCore for Sampler {
+    #[inline(always)]
+    fn next_u32(&mut self) -> u32 {
+        match self.next_in_sequence() {
+            Some(value) => value,
+            None => self.rng.next_u32(),
+        }
+    }
+
+    #[inline(always)]
+    fn next_u64(&mut self) -> u64 {
+        match self.next_in_sequence() {
+            Some(value) => (value as u64) << 32 | self.rng.next_u32() as u64,
+            None => self.rng.next_u64(),
+        }
+    }
+
+    #[inline(always)]
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.rng.fill_bytes(dest)
+    }
+
+    #[inline(always)]
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.rng.try_fill_bytes(dest)
+    }
+}
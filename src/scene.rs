@@ -0,0 +1,538 @@
+use crate::{
+    accel::{Aabb, Accel, Accelerator, Bounded},
+    background::Background,
+    common::Ray,
+    distribution::Distribution,
+    light::{DeltaLight, Light},
+    object::Object,
+    render::Camera,
+    shape::{Intersect, Intersection, Shape},
+    stats, Vec3, EPSILON,
+};
+use float_ord::FloatOrd;
+use std::ops::Deref;
+
+mod builder;
+#[cfg(feature = "serde")]
+mod description;
+
+pub use builder::SceneBuilder;
+
+#[cfg(feature = "serde")]
+pub use description::{
+    CameraDescription, DeltaLightDescription, DepthOfField, LightDescription, MaterialDescription,
+    ObjectDescription, SceneDescription, ShapeDescription, TextureDescription,
+};
+
+pub struct Scene {
+    pub camera: Camera,
+    pub objects: Vec<Object>,
+    pub lights: Vec<Light>,
+    /// Lights without a shape, which only contribute through direct
+    /// lighting. See [`DeltaLight`].
+    pub delta_lights: Vec<DeltaLight>,
+    /// The light arriving from every direction rays can escape the scene
+    /// in. If present, it's seen instead of the
+    /// [background of the renderer](crate::render::Renderer::background).
+    pub environment: Option<Background>,
+}
+
+impl Scene {
+    /// Creates a [`SceneBuilder`] for a scene with no objects or lights.
+    #[inline(always)]
+    pub fn builder() -> SceneBuilder {
+        SceneBuilder::default()
+    }
+
+    /// Whether the segment going from `from` to `to` is blocked by any
+    /// object at `time`. Lights are ignored, so they never shadow
+    /// themselves.
+    pub fn is_occluded(&self, from: Vec3, to: Vec3, time: f32) -> bool {
+        segment(from, to, time).is_some_and(|(ray, max_t)| self.occluded(ray, max_t))
+    }
+
+    /// Whether any object is hit by `ray` before `max_t`. See
+    /// [`Intersect::occluded`].
+    pub fn occluded(&self, ray: Ray, max_t: f32) -> bool {
+        self.objects
+            .iter()
+            .any(|obj| obj.shape.occluded(ray, max_t))
+    }
+
+    /// The smallest box containing every object in the scene that has a
+    /// bounding box. Unbounded objects, like planes, and lights are ignored.
+    ///
+    /// Returns [`None`] if no object has a bounding box.
+    pub fn bounds(&self) -> Option<Aabb> {
+        self.objects
+            .iter()
+            .filter_map(|obj| obj.shape.aabb())
+            .reduce(|a, b| a.union(b))
+    }
+
+    /// Prepares this scene for rendering by building a
+    /// [`Bvh`](crate::accel::Bvh) over its objects.
+    #[inline(always)]
+    pub fn build(self) -> PreparedScene {
+        self.build_with(Accel::default())
+    }
+
+    /// Same as [`Scene::build`], but builds the given acceleration
+    /// structure instead.
+    pub fn build_with(self, accel: Accel) -> PreparedScene {
+        PreparedScene::new(self, accel)
+    }
+
+    /// Reads a scene from its [description](SceneDescription), in JSON.
+    ///
+    /// # Errors
+    /// Fails if the description isn't valid JSON, or if it has values which
+    /// are invalid for the types they describe. See
+    /// [`SceneDescription::try_build`].
+    #[cfg(feature = "serde")]
+    pub fn from_reader(reader: impl std::io::Read) -> serde_json::Result<Self> {
+        use serde::de::Error;
+
+        SceneDescription::from_reader(reader)?
+            .try_build()
+            .map_err(serde_json::Error::custom)
+    }
+}
+
+/// A [`Scene`] ready to be rendered, with an acceleration structure over its
+/// objects. Dereferences to the scene itself.
+pub struct PreparedScene {
+    scene: Scene,
+    /// Acceleration structure over the bounded objects.
+    accel: Accelerator,
+    /// Maps the primitives of `accel` to indices in `scene.objects`.
+    bounded: Vec<usize>,
+    /// Indices of objects without a bounding box, like planes, which are
+    /// always tested.
+    unbounded: Vec<usize>,
+    /// Distribution over `scene.lights`, by [power](Light::power).
+    light_distribution: Option<Distribution>,
+    /// The amount of [enabled](Light::enabled) lights in `scene.lights`.
+    enabled_lights: usize,
+}
+
+impl PreparedScene {
+    fn new(scene: Scene, accel: Accel) -> Self {
+        let mut bounded = Vec::new();
+        let mut bounds = Vec::new();
+        let mut unbounded = Vec::new();
+        for (index, obj) in scene.objects.iter().enumerate() {
+            match obj.shape.aabb() {
+                Some(aabb) => {
+                    bounded.push(index);
+                    bounds.push(aabb);
+                }
+                None => unbounded.push(index),
+            }
+        }
+
+        Self {
+            accel: accel.build(&bounds),
+            light_distribution: Distribution::new(scene.lights.iter().map(Light::power)),
+            enabled_lights: scene.lights.iter().filter(|light| light.enabled).count(),
+            scene,
+            bounded,
+            unbounded,
+        }
+    }
+
+    /// The distribution lights are picked from by
+    /// [power](Light::power) when sampling them, or [`None`] if none of them
+    /// can be sampled.
+    #[inline(always)]
+    pub(crate) fn light_distribution(&self) -> Option<&Distribution> {
+        self.light_distribution.as_ref()
+    }
+
+    /// Replaces the distribution lights are picked from, so that it can be
+    /// compared with others.
+    #[cfg(test)]
+    pub(crate) fn set_light_distribution(&mut self, distribution: Option<Distribution>) {
+        self.light_distribution = distribution;
+    }
+
+    /// The amount of lights of the scene which are
+    /// [enabled](Light::enabled).
+    #[inline(always)]
+    pub(crate) fn enabled_light_count(&self) -> usize {
+        self.enabled_lights
+    }
+
+    /// Returns the scene back, dropping the acceleration structure.
+    #[inline(always)]
+    pub fn into_inner(self) -> Scene {
+        self.scene
+    }
+
+    /// Replaces the shape of the object at `index`, returning the old one.
+    ///
+    /// The acceleration structure isn't updated, so [`PreparedScene::refit`]
+    /// must be called after all objects are updated and before the scene is
+    /// used again.
+    ///
+    /// # Panics
+    /// Panics if `index` is out of bounds, or if only one of the old and new
+    /// shapes has a bounding box.
+    pub fn update_object(&mut self, index: usize, shape: Shape) -> Shape {
+        let obj = &mut self.scene.objects[index];
+        assert_eq!(
+            obj.shape.aabb().is_some(),
+            shape.aabb().is_some(),
+            "bounded and unbounded shapes can't replace each other"
+        );
+
+        std::mem::replace(&mut obj.shape, shape)
+    }
+
+    /// Updates the acceleration structure after objects were changed with
+    /// [`PreparedScene::update_object`]. For small movements, this is much
+    /// cheaper than building the scene again.
+    pub fn refit(&mut self) {
+        let objects = &self.scene.objects;
+        let bounds: Vec<Aabb> = self
+            .bounded
+            .iter()
+            .map(|&index| objects[index].shape.aabb().unwrap())
+            .collect();
+
+        self.accel.refit(&bounds);
+    }
+
+    /// Same as [`Scene::bounds`], but reuses the bounds of the
+    /// acceleration structure.
+    #[inline(always)]
+    pub fn bounds(&self) -> Option<Aabb> {
+        (!self.bounded.is_empty()).then(|| self.accel.bounds())
+    }
+
+    /// Finds the closest object hit by `ray`, if any.
+    #[inline(always)]
+    pub fn closest_hit(&self, ray: Ray) -> Option<(&Object, Intersection)> {
+        self.intersect(ray)
+            .map(|(index, intersection)| (&self.scene.objects[index], intersection))
+    }
+
+    /// Finds the closest object hit by `ray`, if any, and returns its index
+    /// in [`Scene::objects`] along with the intersection. Useful for
+    /// picking objects.
+    pub fn intersect(&self, ray: Ray) -> Option<(usize, Intersection)> {
+        let objects = &self.scene.objects;
+        let mut tests = self.unbounded.len() as u64;
+        let bounded = self
+            .accel
+            .closest_hit(ray, |index, ray| {
+                tests += 1;
+                objects[self.bounded[index]].shape.intersection(ray)
+            })
+            .map(|(index, intersection)| (self.bounded[index], intersection));
+        stats::count(|stats| stats.intersection_tests += tests);
+
+        let unbounded = self.unbounded.iter().filter_map(|&index| {
+            objects[index]
+                .shape
+                .intersection(ray)
+                .map(|intersection| (index, intersection))
+        });
+
+        bounded
+            .into_iter()
+            .chain(unbounded)
+            .min_by_key(|(_, intersection)| FloatOrd(intersection.t))
+    }
+
+    /// Whether the segment going from `from` to `to` is blocked by any
+    /// object at `time`. Lights are ignored, so they never shadow
+    /// themselves.
+    pub fn is_occluded(&self, from: Vec3, to: Vec3, time: f32) -> bool {
+        segment(from, to, time).is_some_and(|(ray, max_t)| self.occluded(ray, max_t))
+    }
+
+    /// Whether any object is hit by `ray` before `max_t`. Stops at the first
+    /// hit found, so this is cheaper than [`PreparedScene::closest_hit`].
+    pub fn occluded(&self, ray: Ray, max_t: f32) -> bool {
+        let objects = &self.scene.objects;
+        let mut tests = 0;
+        let occluded = self.unbounded.iter().any(|&index| {
+            tests += 1;
+            objects[index].shape.occluded(ray, max_t)
+        }) || self.accel.any_hit(ray, max_t, |index, ray| {
+            tests += 1;
+            objects[self.bounded[index]].shape.occluded(ray, max_t)
+        });
+
+        stats::count(|stats| stats.intersection_tests += tests);
+        occluded
+    }
+}
+
+impl Deref for PreparedScene {
+    type Target = Scene;
+
+    #[inline(always)]
+    fn deref(&self) -> &Self::Target {
+        &self.scene
+    }
+}
+
+/// The ray going from `from` towards `to` at `time`, and the `max_t` it
+/// must stop at to end right before reaching `to`, or [`None`] if both
+/// points are the same.
+#[inline(always)]
+fn segment(from: Vec3, to: Vec3, time: f32) -> Option<(Ray, f32)> {
+    let to_target = to - from;
+    let distance = to_target.length();
+    let direction = to_target.try_normalize()?;
+
+    Some((
+        Ray::new(from, direction).with_time(time),
+        distance - EPSILON,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        accel::BvhBuild,
+        common::Color,
+        material::Simple,
+        shape::{Cuboid, Sphere},
+        texture::SolidColor,
+    };
+    use rand::{rngs::SmallRng, Rng, SeedableRng};
+    use std::sync::Arc;
+
+    /// A scene with lots of overlapping spheres and boxes of every size,
+    /// plus a plane under them, placed randomly from `seed`.
+    fn random_scene(seed: u64) -> Scene {
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let material = Arc::new(Simple {
+            albedo: Box::new(SolidColor(Color::new(0.5, 0.5, 0.5))),
+            diffuse: 1.0,
+            fuzzyness: 0.0,
+        });
+
+        let mut builder = Scene::builder()
+            .camera(Camera::new(Vec3::ZERO, Vec3::Z, 1.0, 1.0))
+            .plane(Vec3::new(0.0, -12.0, 0.0), Vec3::Y, material.clone());
+        for index in 0..200 {
+            let center = Vec3::new(
+                rng.gen_range(-10.0..10.0),
+                rng.gen_range(-10.0..10.0),
+                rng.gen_range(-10.0..10.0),
+            );
+            let size = rng.gen_range(0.05f32..2.0).powi(2);
+            let shape: Shape = if index % 3 == 0 {
+                let half = size * Vec3::new(rng.gen(), rng.gen(), rng.gen()) + 0.01;
+                Cuboid {
+                    min: center - half,
+                    max: center + half,
+                }
+                .into()
+            } else {
+                Sphere {
+                    center,
+                    radius: size,
+                }
+                .into()
+            };
+
+            builder = builder.add_object(Object::new(shape, material.clone()));
+        }
+
+        builder.build()
+    }
+
+    /// Random rays starting both inside and outside of [`random_scene`].
+    fn random_rays(seed: u64) -> impl Iterator<Item = Ray> {
+        let mut rng = SmallRng::seed_from_u64(seed);
+        (0..2000).map(move |_| {
+            let origin = Vec3::new(
+                rng.gen_range(-15.0..15.0),
+                rng.gen_range(-15.0..15.0),
+                rng.gen_range(-15.0..15.0),
+            );
+            let direction = loop {
+                let direction = Vec3::new(
+                    rng.gen_range(-1.0..1.0),
+                    rng.gen_range(-1.0..1.0),
+                    rng.gen_range(-1.0..1.0),
+                );
+                if let Some(direction) = direction.try_normalize() {
+                    break direction;
+                }
+            };
+
+            Ray::new(origin, direction)
+        })
+    }
+
+    /// The index and t of the closest object hit by `ray`, found by testing
+    /// every object.
+    fn brute_force(scene: &Scene, ray: Ray) -> Option<(usize, f32)> {
+        scene
+            .objects
+            .iter()
+            .enumerate()
+            .filter_map(|(index, obj)| obj.shape.intersection(ray).map(|hit| (index, hit.t)))
+            .min_by_key(|&(_, t)| FloatOrd(t))
+    }
+
+    /// Checks that building `accel` over random scenes finds exactly the
+    /// same hits as testing every object.
+    fn assert_matches_brute_force(accel: Accel) {
+        for seed in 0..4 {
+            let scene = random_scene(seed);
+            let brute_force: Vec<_> = random_rays(seed)
+                .map(|ray| (brute_force(&scene, ray), scene.occluded(ray, 8.0)))
+                .collect();
+
+            let prepared = scene.build_with(accel);
+            let mut hits = 0;
+            for (ray, (closest, occluded)) in random_rays(seed).zip(brute_force) {
+                let found = prepared.intersect(ray).map(|(index, hit)| (index, hit.t));
+                assert_eq!(found, closest, "{accel:?} with seed {seed}: {ray:?}");
+                assert_eq!(
+                    prepared.occluded(ray, 8.0),
+                    occluded,
+                    "{accel:?} with seed {seed}: {ray:?}"
+                );
+
+                hits += usize::from(closest.is_some_and(|(index, _)| index != 0));
+            }
+
+            // most rays should hit something other than the plane, or this
+            // isn't testing much
+            assert!(hits > 500, "only {hits} hits");
+        }
+    }
+
+    #[test]
+    fn bvh_matches_brute_force() {
+        assert_matches_brute_force(Accel::Bvh(BvhBuild::Median));
+    }
+
+    #[test]
+    fn sah_bvh_matches_median_bvh() {
+        for buckets in [2, 12, 32] {
+            assert_matches_brute_force(Accel::Bvh(BvhBuild::Sah { buckets }));
+        }
+
+        // and both strategies find the same hits for the same scene
+        let median = random_scene(7).build_with(Accel::Bvh(BvhBuild::Median));
+        let sah = random_scene(7).build_with(Accel::Bvh(BvhBuild::Sah { buckets: 12 }));
+        for ray in random_rays(7) {
+            let median_hit = median.intersect(ray).map(|(index, hit)| (index, hit.t));
+            let sah_hit = sah.intersect(ray).map(|(index, hit)| (index, hit.t));
+            assert_eq!(median_hit, sah_hit, "{ray:?}");
+            assert_eq!(median.occluded(ray, 8.0), sah.occluded(ray, 8.0), "{ray:?}");
+        }
+    }
+
+    #[test]
+    fn grid_matches_brute_force() {
+        // from coarser than the objects to much finer than them
+        for resolution in [1, 4, 16, 64] {
+            assert_matches_brute_force(Accel::Grid { resolution });
+        }
+    }
+
+    #[test]
+    fn refitted_scenes_follow_moving_objects() {
+        let accels = [
+            Accel::Bvh(BvhBuild::Median),
+            Accel::Bvh(BvhBuild::Sah { buckets: 12 }),
+            Accel::Grid { resolution: 16 },
+        ];
+
+        for accel in accels {
+            // a sphere flying from one side of the scene to the other,
+            // through the other objects
+            let sphere = |frame: u32| Sphere {
+                center: Vec3::new(-20.0 + 2.0 * frame as f32, 0.0, 0.0),
+                radius: 0.5,
+            };
+
+            let mut scene = random_scene(3);
+            let moving = scene.objects.len();
+            let material = scene.objects[0].material.clone();
+            scene.objects.push(Object::new(sphere(0).into(), material));
+            let mut scene = scene.build_with(accel);
+
+            for frame in 1..=20 {
+                scene.update_object(moving, sphere(frame).into());
+                scene.refit();
+
+                // from straight above, where no object is, the sphere is
+                // either hit or hidden by another object
+                let center = sphere(frame).center;
+                let above = Ray::new(center + Vec3::new(0.0, 30.0, 0.0), -Vec3::Y);
+                let (_, hit) = scene.intersect(above).unwrap();
+                assert!(hit.t <= 29.5 + EPSILON, "{accel:?} at frame {frame}");
+
+                // and it's not where it was anymore
+                let previous = sphere(frame - 1).center;
+                let above = Ray::new(previous + Vec3::new(0.0, 30.0, 0.0), -Vec3::Y);
+                let hit = scene.intersect(above).map(|(index, _)| index);
+                assert_ne!(hit, Some(moving), "{accel:?} at frame {frame}");
+
+                for ray in random_rays(frame as u64).take(200) {
+                    let found = scene.intersect(ray).map(|(index, hit)| (index, hit.t));
+                    let expected = brute_force(&scene, ray);
+                    assert_eq!(found, expected, "{accel:?} at frame {frame}: {ray:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn intersect_picks_the_closest_object() {
+        let material = Arc::new(Simple {
+            albedo: Box::new(SolidColor(Color::new(0.5, 0.5, 0.5))),
+            diffuse: 1.0,
+            fuzzyness: 0.0,
+        });
+        let sphere =
+            |center, radius| Object::new(Sphere { center, radius }.into(), material.clone());
+
+        // a small sphere in front of a big one, side by side with another
+        let scene = Scene::builder()
+            .camera(Camera::new(Vec3::ZERO, Vec3::Z, 1.0, 1.0))
+            .add_object(sphere(Vec3::new(0.0, 0.0, 10.0), 2.0))
+            .add_object(sphere(Vec3::new(0.0, 0.0, 5.0), 0.5))
+            .add_object(sphere(Vec3::new(4.0, 0.0, 10.0), 1.0))
+            .build()
+            .build_with(Accel::Bvh(BvhBuild::Median));
+
+        // both spheres in front are in the way, but the small one is closer
+        let (index, hit) = scene.intersect(Ray::new(Vec3::ZERO, Vec3::Z)).unwrap();
+        assert_eq!(index, 1);
+        assert!((hit.t - 4.5).abs() < 1e-4, "{}", hit.t);
+
+        // only the big one is in the way
+        let ray = Ray::new(Vec3::new(0.0, 1.0, 0.0), Vec3::Z);
+        let (index, hit) = scene.intersect(ray).unwrap();
+        assert_eq!(index, 0);
+        assert!((hit.t - (10.0 - 3f32.sqrt())).abs() < 1e-4, "{}", hit.t);
+
+        // the one on the side
+        let ray = Ray::new(Vec3::new(4.0, 0.0, 0.0), Vec3::Z);
+        let (index, hit) = scene.intersect(ray).unwrap();
+        assert_eq!(index, 2);
+        assert!((hit.t - 9.0).abs() < 1e-4, "{}", hit.t);
+
+        // and nothing, between them
+        let ray = Ray::new(Vec3::new(2.5, 0.0, 0.0), Vec3::Z);
+        assert!(scene.intersect(ray).is_none());
+
+        // closest_hit agrees
+        let ray = Ray::new(Vec3::ZERO, Vec3::Z);
+        let (object, _) = scene.closest_hit(ray).unwrap();
+        assert!(std::ptr::eq(object, &scene.objects[1]));
+    }
+}
@@ -0,0 +1,101 @@
+use super::Scene;
+use crate::{
+    background::Background,
+    light::{DeltaLight, Light},
+    material::Material,
+    object::Object,
+    render::Camera,
+    shape::{Plane, Shape, Sphere},
+    Vec3,
+};
+use std::sync::Arc;
+
+/// Builds a [`Scene`] one object and light at a time. Created with
+/// [`Scene::builder`].
+#[derive(Default)]
+pub struct SceneBuilder {
+    camera: Option<Camera>,
+    objects: Vec<Object>,
+    lights: Vec<Light>,
+    delta_lights: Vec<DeltaLight>,
+    environment: Option<Background>,
+}
+
+impl SceneBuilder {
+    /// Sets the camera of the scene.
+    #[inline(always)]
+    pub fn camera(mut self, camera: Camera) -> Self {
+        self.camera = Some(camera);
+        self
+    }
+
+    /// Adds an object to the scene.
+    #[inline(always)]
+    pub fn add_object(mut self, object: Object) -> Self {
+        self.objects.push(object);
+        self
+    }
+
+    /// Adds every object of `objects` to the scene, in order.
+    #[inline(always)]
+    pub fn add_objects(mut self, objects: impl IntoIterator<Item = Object>) -> Self {
+        self.objects.extend(objects);
+        self
+    }
+
+    /// Adds a light to the scene.
+    #[inline(always)]
+    pub fn add_light(mut self, light: Light) -> Self {
+        self.lights.push(light);
+        self
+    }
+
+    /// Adds a [delta light](DeltaLight) to the scene.
+    #[inline(always)]
+    pub fn add_delta_light(mut self, light: impl Into<DeltaLight>) -> Self {
+        self.delta_lights.push(light.into());
+        self
+    }
+
+    /// Sets the [environment](Scene::environment) of the scene.
+    #[inline(always)]
+    pub fn environment(mut self, environment: Background) -> Self {
+        self.environment = Some(environment);
+        self
+    }
+
+    /// Adds an object of the given shape and material to the scene.
+    #[inline(always)]
+    pub fn shape(self, shape: impl Into<Shape>, material: Arc<dyn Material>) -> Self {
+        self.add_object(Object::new(shape.into(), material))
+    }
+
+    /// Adds a [`Sphere`] to the scene.
+    #[inline(always)]
+    pub fn sphere(self, center: Vec3, radius: f32, material: Arc<dyn Material>) -> Self {
+        self.shape(Sphere { center, radius }, material)
+    }
+
+    /// Adds a [`Plane`] to the scene.
+    ///
+    /// # Panics
+    /// Panics if `normal` is not normalized.
+    #[inline(always)]
+    pub fn plane(self, point: Vec3, normal: Vec3, material: Arc<dyn Material>) -> Self {
+        self.shape(Plane::new(point, normal), material)
+    }
+
+    /// Builds the scene.
+    ///
+    /// # Panics
+    /// Panics if no camera was set.
+    pub fn build(self) -> Scene {
+        Scene {
+            camera: self.camera.expect("the scene should have a camera"),
+            objects: self.objects,
+            lights: self.lights,
+            delta_lights: self.delta_lights,
+            environment: self.environment,
+        }
+    }
+}
@@ -0,0 +1,731 @@
+//! Plain data descriptions of scenes, which can be (de)serialized.
+//!
+//! Scenes themselves can't be, since materials and textures are trait
+//! objects. Descriptions use enums of the known kinds instead, and are
+//! turned into scenes with [`SceneDescription::build`].
+//!
+//! Colors are `[r, g, b]` arrays, and directions don't have to be
+//! normalized. Otherwise, values must follow the same rules as the
+//! constructors of the types they describe. Descriptions with invalid
+//! values fail to [`try_build`](SceneDescription::try_build), and panic
+//! when [built](SceneDescription::build).
+
+use super::Scene;
+use crate::{
+    common::{check_range, BuildError, Color},
+    light::{DeltaLight, DirectionalLight, Falloff, Light, PointLight, SpotLight},
+    material::{Emissive, Isotropic, Material, Simple},
+    object::Object,
+    render::{Camera, Projection},
+    shape::{
+        Capsule, ConstantMedium, Csg, CsgOperation, Cuboid, Cylinder, Disk, Ellipsoid, Mesh,
+        MovingSphere, Plane, Shape, Sphere, Torus,
+    },
+    texture::{Checker, SolidColor, Texture},
+    Vec3,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{Read, Write},
+    sync::Arc,
+};
+
+#[inline(always)]
+fn color([r, g, b]: [f32; 3]) -> Result<Color, BuildError> {
+    Color::try_new(r, g, b)
+}
+
+/// Checks that `value` is a valid intensity for a light or an emissive
+/// material.
+#[inline(always)]
+fn intensity(value: f32) -> Result<f32, BuildError> {
+    check_range(
+        "intensity",
+        value,
+        "finite and non-negative",
+        value.is_finite() && value >= 0.0,
+    )?;
+    Ok(value)
+}
+
+/// Normalizes `value`, a direction named `name`.
+#[inline(always)]
+fn normalized(name: &'static str, value: Vec3) -> Result<Vec3, BuildError> {
+    value
+        .try_normalize()
+        .ok_or(BuildError::InvalidDirection { name, value })
+}
+
+#[inline(always)]
+fn one() -> Vec3 {
+    Vec3::ONE
+}
+
+#[inline(always)]
+fn yes() -> bool {
+    true
+}
+
+/// A description of a [`Scene`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SceneDescription {
+    pub camera: CameraDescription,
+    #[serde(default)]
+    pub objects: Vec<ObjectDescription>,
+    #[serde(default)]
+    pub lights: Vec<LightDescription>,
+    #[serde(default)]
+    pub delta_lights: Vec<DeltaLightDescription>,
+}
+
+impl SceneDescription {
+    /// Reads a description from JSON.
+    pub fn from_reader(reader: impl Read) -> serde_json::Result<Self> {
+        serde_json::from_reader(reader)
+    }
+
+    /// Writes this description as pretty printed JSON.
+    pub fn to_writer(&self, writer: impl Write) -> serde_json::Result<()> {
+        serde_json::to_writer_pretty(writer, self)
+    }
+
+    /// Builds the scene this describes.
+    ///
+    /// # Panics
+    /// Panics if any value is invalid for the type it describes.
+    pub fn build(&self) -> Scene {
+        self.try_build().unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Same as [`SceneDescription::build`], but fails instead of panicking.
+    pub fn try_build(&self) -> Result<Scene, BuildError> {
+        Ok(Scene {
+            camera: self.camera.try_build()?,
+            objects: self
+                .objects
+                .iter()
+                .map(ObjectDescription::try_build)
+                .collect::<Result<_, _>>()?,
+            lights: self
+                .lights
+                .iter()
+                .map(LightDescription::try_build)
+                .collect::<Result<_, _>>()?,
+            delta_lights: self
+                .delta_lights
+                .iter()
+                .map(DeltaLightDescription::try_build)
+                .collect::<Result<_, _>>()?,
+            environment: None,
+        })
+    }
+}
+
+/// The lens of a [`CameraDescription`]. See [`Camera::with_depth_of_field`].
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DepthOfField {
+    pub aperture: f32,
+    pub focus_distance: f32,
+}
+
+/// A description of a [`Camera`].
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CameraDescription {
+    pub position: Vec3,
+    pub direction: Vec3,
+    /// The direction considered to be up. Defaults to the Y axis.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub up: Option<Vec3>,
+    pub projection: Projection,
+    pub aspect_ratio: f32,
+    /// The lens of the camera. Defaults to a pinhole camera.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub depth_of_field: Option<DepthOfField>,
+}
+
+impl CameraDescription {
+    /// Builds the camera this describes.
+    ///
+    /// # Panics
+    /// Panics if any value is invalid for a [`Camera`].
+    pub fn build(&self) -> Camera {
+        self.try_build().unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Same as [`CameraDescription::build`], but fails instead of
+    /// panicking.
+    pub fn try_build(&self) -> Result<Camera, BuildError> {
+        let direction = normalized("direction", self.direction)?;
+        let mut camera = match self.projection {
+            Projection::Perspective { fov } => {
+                Camera::try_new(self.position, direction, fov, self.aspect_ratio)?
+            }
+            Projection::Orthographic { scale } => {
+                Camera::try_orthographic(self.position, direction, scale, self.aspect_ratio)?
+            }
+        };
+
+        if let Some(up) = self.up {
+            camera.set_up(normalized("up", up)?);
+        }
+
+        if let Some(lens) = self.depth_of_field {
+            let DepthOfField {
+                aperture,
+                focus_distance,
+            } = lens;
+            check_range("aperture", aperture, "non-negative", aperture >= 0.0)?;
+            check_range(
+                "focus_distance",
+                focus_distance,
+                "positive",
+                focus_distance > 0.0,
+            )?;
+            camera = camera.with_depth_of_field(aperture, focus_distance);
+        }
+
+        Ok(camera)
+    }
+}
+
+/// A description of a [`Shape`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ShapeDescription {
+    Sphere {
+        center: Vec3,
+        radius: f32,
+    },
+    MovingSphere {
+        center0: Vec3,
+        center1: Vec3,
+        radius: f32,
+    },
+    Ellipsoid {
+        center: Vec3,
+        radii: Vec3,
+    },
+    Plane {
+        point: Vec3,
+        normal: Vec3,
+    },
+    Disk {
+        center: Vec3,
+        normal: Vec3,
+        radius: f32,
+    },
+    Cuboid {
+        min: Vec3,
+        max: Vec3,
+    },
+    Torus {
+        center: Vec3,
+        axis: Vec3,
+        major_radius: f32,
+        minor_radius: f32,
+    },
+    Capsule {
+        a: Vec3,
+        b: Vec3,
+        radius: f32,
+    },
+    Cylinder {
+        base: Vec3,
+        axis: Vec3,
+        radius: f32,
+        height: f32,
+        #[serde(default)]
+        capped: bool,
+    },
+    /// A triangle mesh. When `smooth` is set, its normals are interpolated
+    /// between vertices.
+    Mesh {
+        vertices: Vec<Vec3>,
+        indices: Vec<[u32; 3]>,
+        #[serde(default)]
+        smooth: bool,
+    },
+    /// Another shape, scaled, then rotated around the Y axis by `rotation_y`
+    /// radians and then translated.
+    Transformed {
+        shape: Box<ShapeDescription>,
+        #[serde(default = "one")]
+        scale: Vec3,
+        #[serde(default)]
+        rotation_y: f32,
+        #[serde(default)]
+        translation: Vec3,
+    },
+    ConstantMedium {
+        boundary: Box<ShapeDescription>,
+        density: f32,
+    },
+    Csg {
+        left: Box<ShapeDescription>,
+        right: Box<ShapeDescription>,
+        operation: CsgOperation,
+    },
+}
+
+impl ShapeDescription {
+    /// Builds the shape this describes.
+    ///
+    /// # Panics
+    /// Panics if any value is invalid for the shape.
+    pub fn build(&self) -> Shape {
+        self.try_build().unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Same as [`ShapeDescription::build`], but fails instead of panicking.
+    pub fn try_build(&self) -> Result<Shape, BuildError> {
+        Ok(match self {
+            &ShapeDescription::Sphere { center, radius } => Shape::from(Sphere { center, radius }),
+            &ShapeDescription::MovingSphere {
+                center0,
+                center1,
+                radius,
+            } => Shape::from(MovingSphere {
+                center0,
+                center1,
+                radius,
+            }),
+            &ShapeDescription::Ellipsoid { center, radii } => {
+                Shape::from(Ellipsoid { center, radii })
+            }
+            &ShapeDescription::Plane { point, normal } => {
+                Shape::from(Plane::try_new(point, normalized("normal", normal)?)?)
+            }
+            &ShapeDescription::Disk {
+                center,
+                normal,
+                radius,
+            } => Shape::from(Disk::new(center, normalized("normal", normal)?, radius)),
+            &ShapeDescription::Cuboid { min, max } => Shape::from(Cuboid { min, max }),
+            &ShapeDescription::Torus {
+                center,
+                axis,
+                major_radius,
+                minor_radius,
+            } => Shape::from(Torus::new(
+                center,
+                normalized("axis", axis)?,
+                major_radius,
+                minor_radius,
+            )),
+            &ShapeDescription::Capsule { a, b, radius } => Shape::from(Capsule { a, b, radius }),
+            &ShapeDescription::Cylinder {
+                base,
+                axis,
+                radius,
+                height,
+                capped,
+            } => Shape::from(Cylinder::new(
+                base,
+                normalized("axis", axis)?,
+                radius,
+                height,
+                capped,
+            )),
+            ShapeDescription::Mesh {
+                vertices,
+                indices,
+                smooth,
+            } => {
+                if let Some(&index) = indices
+                    .iter()
+                    .flatten()
+                    .find(|&&index| index as usize >= vertices.len())
+                {
+                    return Err(BuildError::IndexOutOfBounds {
+                        index,
+                        vertex_count: vertices.len(),
+                    });
+                }
+
+                let mesh = Mesh::new(vertices.clone(), indices.clone());
+                Shape::from(if *smooth {
+                    mesh.with_smooth_normals()
+                } else {
+                    mesh
+                })
+            }
+            ShapeDescription::Transformed {
+                shape,
+                scale,
+                rotation_y,
+                translation,
+            } => {
+                // a zero scale would make the transform impossible to invert
+                let product = scale.x * scale.y * scale.z;
+                check_range("scale", product, "non-zero on every axis", product != 0.0)?;
+
+                shape
+                    .try_build()?
+                    .scaled(*scale)
+                    .rotated_y(*rotation_y)
+                    .translated(*translation)
+            }
+            ShapeDescription::ConstantMedium { boundary, density } => {
+                check_range("density", *density, "positive", *density > 0.0)?;
+                Shape::from(ConstantMedium::new(boundary.try_build()?, *density))
+            }
+            ShapeDescription::Csg {
+                left,
+                right,
+                operation,
+            } => Shape::from(Csg::new(left.try_build()?, right.try_build()?, *operation)),
+        })
+    }
+}
+
+/// A description of a [`Texture`].
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TextureDescription {
+    Solid {
+        color: [f32; 3],
+    },
+    Checker {
+        a: [f32; 3],
+        b: [f32; 3],
+        scale: f32,
+    },
+}
+
+impl TextureDescription {
+    /// Builds the texture this describes.
+    ///
+    /// # Panics
+    /// Panics if any color is invalid.
+    pub fn build(&self) -> Box<dyn Texture> {
+        self.try_build().unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Same as [`TextureDescription::build`], but fails instead of
+    /// panicking.
+    pub fn try_build(&self) -> Result<Box<dyn Texture>, BuildError> {
+        Ok(match *self {
+            TextureDescription::Solid { color: c } => Box::new(SolidColor(color(c)?)),
+            TextureDescription::Checker { a, b, scale } => Box::new(Checker {
+                a: color(a)?,
+                b: color(b)?,
+                scale,
+            }),
+        })
+    }
+}
+
+/// A description of a [`Material`].
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MaterialDescription {
+    Simple {
+        albedo: TextureDescription,
+        diffuse: f32,
+        fuzzyness: f32,
+    },
+    Emissive {
+        color: [f32; 3],
+        intensity: f32,
+    },
+    Isotropic {
+        color: [f32; 3],
+    },
+}
+
+impl MaterialDescription {
+    /// Builds the material this describes.
+    ///
+    /// # Panics
+    /// Panics if any color is invalid.
+    pub fn build(&self) -> Arc<dyn Material> {
+        self.try_build().unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Same as [`MaterialDescription::build`], but fails instead of
+    /// panicking.
+    pub fn try_build(&self) -> Result<Arc<dyn Material>, BuildError> {
+        Ok(match *self {
+            MaterialDescription::Simple {
+                albedo,
+                diffuse,
+                fuzzyness,
+            } => Arc::new(Simple {
+                albedo: albedo.try_build()?,
+                diffuse,
+                fuzzyness,
+            }),
+            MaterialDescription::Emissive {
+                color: c,
+                intensity: value,
+            } => Arc::new(Emissive {
+                color: color(c)?,
+                intensity: intensity(value)?,
+            }),
+            MaterialDescription::Isotropic { color: c } => Arc::new(Isotropic { color: color(c)? }),
+        })
+    }
+}
+
+/// A description of an [`Object`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ObjectDescription {
+    pub shape: ShapeDescription,
+    pub material: MaterialDescription,
+    /// The indices of the lights that light the object, with the delta
+    /// lights coming after the other lights. Defaults to every light. See
+    /// [`Object::light_mask`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lights: Option<Vec<usize>>,
+}
+
+impl ObjectDescription {
+    /// Builds the object this describes.
+    ///
+    /// # Panics
+    /// Panics if any value is invalid for its shape or material.
+    pub fn build(&self) -> Object {
+        self.try_build().unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Same as [`ObjectDescription::build`], but fails instead of
+    /// panicking.
+    pub fn try_build(&self) -> Result<Object, BuildError> {
+        let object = Object::new(self.shape.try_build()?, self.material.try_build()?);
+        let Some(lights) = &self.lights else {
+            return Ok(object);
+        };
+
+        let mut mask = 0;
+        for &index in lights {
+            check_range("lights", index as f32, "less than 64", index < 64)?;
+            mask |= 1 << index;
+        }
+
+        Ok(object.with_light_mask(mask))
+    }
+}
+
+/// A description of a [`Light`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LightDescription {
+    pub shape: ShapeDescription,
+    pub color: [f32; 3],
+    pub intensity: f32,
+    /// Defaults to [`Falloff::None`].
+    #[serde(default)]
+    pub falloff: Falloff,
+    /// Defaults to `true`.
+    #[serde(default = "yes")]
+    pub enabled: bool,
+}
+
+impl LightDescription {
+    /// Builds the light this describes.
+    ///
+    /// # Panics
+    /// Panics if any value is invalid for its shape or its color, or if
+    /// the intensity is negative or not finite.
+    pub fn build(&self) -> Light {
+        self.try_build().unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Same as [`LightDescription::build`], but fails instead of panicking.
+    pub fn try_build(&self) -> Result<Light, BuildError> {
+        let light = Light::new(
+            self.shape.try_build()?,
+            color(self.color)?,
+            intensity(self.intensity)?,
+        );
+        Ok(light.with_falloff(self.falloff).with_enabled(self.enabled))
+    }
+}
+
+/// A description of a [`DeltaLight`].
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DeltaLightDescription {
+    Point {
+        position: Vec3,
+        color: [f32; 3],
+        intensity: f32,
+        /// Defaults to `true`.
+        #[serde(default = "yes")]
+        enabled: bool,
+    },
+    Directional {
+        direction: Vec3,
+        color: [f32; 3],
+        intensity: f32,
+        /// Defaults to `true`.
+        #[serde(default = "yes")]
+        enabled: bool,
+    },
+    /// A spot light. The angles are half-angles of its cone, in radians.
+    Spot {
+        position: Vec3,
+        direction: Vec3,
+        color: [f32; 3],
+        intensity: f32,
+        inner_angle: f32,
+        outer_angle: f32,
+        /// Defaults to `true`.
+        #[serde(default = "yes")]
+        enabled: bool,
+    },
+}
+
+impl DeltaLightDescription {
+    /// Builds the light this describes.
+    ///
+    /// # Panics
+    /// Panics if any value is invalid for the light.
+    pub fn build(&self) -> DeltaLight {
+        self.try_build().unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Same as [`DeltaLightDescription::build`], but fails instead of
+    /// panicking.
+    pub fn try_build(&self) -> Result<DeltaLight, BuildError> {
+        Ok(match *self {
+            DeltaLightDescription::Point {
+                position,
+                color: c,
+                intensity: value,
+                enabled,
+            } => DeltaLight::from(PointLight {
+                position,
+                color: color(c)?,
+                intensity: intensity(value)?,
+                enabled,
+            }),
+            DeltaLightDescription::Directional {
+                direction,
+                color: c,
+                intensity: value,
+                enabled,
+            } => DeltaLight::from(DirectionalLight::new(
+                normalized("direction", direction)?,
+                color(c)?,
+                intensity(value)?,
+            ))
+            .with_enabled(enabled),
+            DeltaLightDescription::Spot {
+                position,
+                direction,
+                color: c,
+                intensity: value,
+                inner_angle,
+                outer_angle,
+                enabled,
+            } => {
+                let angle = |name, angle: f32| {
+                    check_range(
+                        name,
+                        angle,
+                        "in the [0, pi) range",
+                        (0.0..std::f32::consts::PI).contains(&angle),
+                    )
+                };
+                angle("inner_angle", inner_angle)?;
+                angle("outer_angle", outer_angle)?;
+                check_range(
+                    "inner_angle",
+                    inner_angle,
+                    "at most `outer_angle`",
+                    inner_angle <= outer_angle,
+                )?;
+
+                DeltaLight::from(SpotLight::new(
+                    position,
+                    normalized("direction", direction)?,
+                    color(c)?,
+                    intensity(value)?,
+                    inner_angle,
+                    outer_angle,
+                ))
+                .with_enabled(enabled)
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn light(intensity: f32) -> LightDescription {
+        LightDescription {
+            shape: ShapeDescription::Sphere {
+                center: Vec3::new(0.0, 4.0, 0.0),
+                radius: 1.0,
+            },
+            color: [1.0, 1.0, 1.0],
+            intensity,
+            falloff: Falloff::None,
+            enabled: true,
+        }
+    }
+
+    #[test]
+    fn invalid_intensities_are_errors() {
+        for value in [-1.0, f32::NAN, f32::INFINITY] {
+            assert!(light(value).try_build().is_err(), "{value}");
+
+            let emissive = MaterialDescription::Emissive {
+                color: [1.0, 1.0, 1.0],
+                intensity: value,
+            };
+            assert!(emissive.try_build().is_err(), "{value}");
+
+            let point = DeltaLightDescription::Point {
+                position: Vec3::ZERO,
+                color: [1.0, 1.0, 1.0],
+                intensity: value,
+                enabled: true,
+            };
+            assert!(point.try_build().is_err(), "{value}");
+        }
+
+        assert!(light(0.0).try_build().is_ok());
+    }
+
+    #[test]
+    fn scenes_with_invalid_intensities_fail_to_load() {
+        let description = SceneDescription {
+            camera: CameraDescription {
+                position: Vec3::ZERO,
+                direction: Vec3::Z,
+                up: None,
+                projection: Projection::Perspective { fov: 1.0 },
+                aspect_ratio: 1.0,
+                depth_of_field: None,
+            },
+            objects: Vec::new(),
+            lights: vec![light(12345.0)],
+            delta_lights: Vec::new(),
+        };
+        let mut json = Vec::new();
+        description.to_writer(&mut json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert!(Scene::from_reader(json.as_bytes()).is_ok());
+
+        // too big for an f32, so it's read as infinity
+        for intensity in ["-1.0", "1e40"] {
+            let json = json.replace("12345.0", intensity);
+            assert!(Scene::from_reader(json.as_bytes()).is_err(), "{intensity}");
+        }
+    }
+
+    #[test]
+    fn delta_lights_are_enabled_by_default() {
+        let json =
+            r#"{ "type": "point", "position": [0, 1, 0], "color": [1, 1, 1], "intensity": 2 }"#;
+        let point: DeltaLightDescription = serde_json::from_str(json).unwrap();
+        assert!(point.build().enabled());
+
+        let json = json.replace(r#""intensity""#, r#""enabled": false, "intensity""#);
+        let point: DeltaLightDescription = serde_json::from_str(&json).unwrap();
+        assert!(!point.build().enabled());
+    }
+}
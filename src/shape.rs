@@ -1,18 +1,167 @@
-use crate::common::Ray;
+use crate::accel::{Aabb, Bounded};
+use crate::common::{check_normalized, mix64, BuildError, Ray};
+use crate::solver;
 use crate::{Vec3, EPSILON};
 use enum_dispatch::enum_dispatch;
+use glam::{Affine3A, Mat3A, Vec2};
+use rand::Rng;
+use std::ops::Range;
+use std::sync::Arc;
+
+pub mod csg;
+pub mod mesh;
+
+pub use csg::{Csg, CsgOperation, IntersectAll, Span};
+pub use mesh::Mesh;
 
 /// An intersection of a [`Ray`] with some sort of [`Shape`].
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Intersection {
     pub point: Vec3,
+    /// The normal used for shading the surface at the intersection. Always
+    /// faces against the ray, see [`Intersection::front_face`].
     pub normal: Vec3,
+    /// The actual normal of the surface at the intersection, which is
+    /// usually the same as `normal`, except for surfaces with interpolated
+    /// normals. Faces the same side as `normal`.
+    pub geometric_normal: Vec3,
     pub t: f32,
+    /// Whether the ray hit the outside of the surface. If it didn't, the
+    /// normal was flipped to face the ray.
+    pub front_face: bool,
+    /// The texture coordinates of the surface at the intersection. Zero for
+    /// shapes that have no parametrization.
+    pub uv: Vec2,
+}
+
+impl Intersection {
+    /// Creates a new [`Intersection`] of `ray` at `t` with a surface whose
+    /// outward normal is `outward_normal`, flipping the normal if needed so
+    /// it faces the ray.
+    #[inline(always)]
+    pub fn new(ray: Ray, t: f32, outward_normal: Vec3) -> Self {
+        let intersection = Self::outward(ray, t, outward_normal);
+        if intersection.front_face {
+            intersection
+        } else {
+            Self {
+                normal: -outward_normal,
+                geometric_normal: -outward_normal,
+                ..intersection
+            }
+        }
+    }
+
+    /// Like [`Intersection::new`], but keeps the outward normal as is.
+    #[inline(always)]
+    pub(crate) fn outward(ray: Ray, t: f32, outward_normal: Vec3) -> Self {
+        Self {
+            point: ray.point_at_t(t),
+            normal: outward_normal,
+            geometric_normal: outward_normal,
+            t,
+            front_face: ray.direction().dot(outward_normal) <= 0.0,
+            uv: Vec2::ZERO,
+        }
+    }
+
+    /// Returns this intersection with a shading normal different from the
+    /// geometric one, like an interpolated vertex normal. `outward_normal`
+    /// gets flipped if the geometric normal was.
+    #[inline(always)]
+    pub fn with_shading_normal(self, outward_normal: Vec3) -> Self {
+        let normal = if self.front_face {
+            outward_normal
+        } else {
+            -outward_normal
+        };
+
+        Self { normal, ..self }
+    }
+
+    /// Returns this intersection with the given texture coordinates.
+    #[inline(always)]
+    pub fn with_uv(self, uv: Vec2) -> Self {
+        Self { uv, ..self }
+    }
+}
+
+/// The spherical texture coordinates of a direction from the center of a
+/// sphere. `u` goes around the Y axis, and `v` goes from zero at the bottom
+/// pole to one at the top pole.
+#[inline(always)]
+pub(crate) fn spherical_uv(direction: Vec3) -> Vec2 {
+    let theta = (-direction.y).clamp(-1.0, 1.0).acos();
+    let phi = (-direction.z).atan2(direction.x) + std::f32::consts::PI;
+
+    Vec2::new(
+        phi / (2.0 * std::f32::consts::PI),
+        theta / std::f32::consts::PI,
+    )
 }
 
 /// Trait for things in space that can intersect with a ray.
 #[enum_dispatch]
 pub trait Intersect {
     fn intersection(&self, ray: Ray) -> Option<Intersection>;
+
+    /// Like [`Intersect::intersection`], but only considers intersections
+    /// whose t is in `t_range`.
+    ///
+    /// The default implementation filters the closest intersection, so it
+    /// misses intersections in the range that come after one before it.
+    /// Shapes should override it when they can do better.
+    #[inline(always)]
+    fn intersection_in(&self, ray: Ray, t_range: Range<f32>) -> Option<Intersection> {
+        self.intersection(ray)
+            .filter(|intersection| t_range.contains(&intersection.t))
+    }
+
+    /// Whether `ray` hits this shape before `max_t`, i.e. whether its
+    /// [closest intersection](Intersect::intersection) has a t smaller than
+    /// `max_t`.
+    ///
+    /// The default implementation computes the closest intersection. Shapes
+    /// should override it when they can skip computing a full intersection
+    /// or stop at the first hit they find.
+    #[inline(always)]
+    fn occluded(&self, ray: Ray, max_t: f32) -> bool {
+        self.intersection(ray)
+            .is_some_and(|intersection| intersection.t < max_t)
+    }
+}
+
+/// Solves `a * t² + 2 * b_halved * t + c = 0`, returning both roots in
+/// ascending order.
+#[inline(always)]
+fn quadratic_roots(a: f32, b_halved: f32, c: f32) -> Option<(f32, f32)> {
+    // this is delta / 4
+    let delta_reduced = b_halved * b_halved - a * c;
+    if delta_reduced < 0.0 {
+        return None;
+    }
+
+    let delta_sqrt_halved = delta_reduced.sqrt();
+
+    let t1 = (-b_halved + delta_sqrt_halved) / a;
+    let t2 = (-b_halved - delta_sqrt_halved) / a;
+
+    Some(if t1 < t2 { (t1, t2) } else { (t2, t1) })
+}
+
+/// Solves `a * t² + 2 * b_halved * t + c = 0` and returns the smallest
+/// root that is greater than or equal to [`EPSILON`], if any.
+#[inline(always)]
+fn closest_quadratic_root(a: f32, b_halved: f32, c: f32) -> Option<f32> {
+    let (min, max) = quadratic_roots(a, b_halved, c)?;
+
+    if min >= EPSILON {
+        Some(min)
+    } else if max >= EPSILON {
+        Some(max)
+    } else {
+        None
+    }
 }
 
 /// A sphere shape.
@@ -26,6 +175,34 @@ impl Sphere {
     pub fn normal(&self, point: Vec3) -> Vec3 {
         (point - self.center).try_normalize().unwrap_or(Vec3::Z)
     }
+
+    /// The intersection of `ray` with this sphere at `t`.
+    #[inline(always)]
+    fn intersection_at(&self, ray: Ray, t: f32) -> Intersection {
+        let normal = self.normal(ray.point_at_t(t));
+        Intersection::new(ray, t, normal).with_uv(spherical_uv(normal))
+    }
+
+    #[inline(always)]
+    pub fn area(&self) -> f32 {
+        4.0 * std::f32::consts::PI * self.radius * self.radius
+    }
+
+    /// Samples an uniformly distributed point on the surface of this
+    /// sphere. Returns the point, the normal at it and the probability
+    /// density of having sampled it, per unit area.
+    pub fn sample_surface(&self, rng: &mut impl Rng) -> (Vec3, Vec3, f32) {
+        let z: f32 = rng.gen_range(-1.0..1.0);
+        let phi = rng.gen_range(0.0..2.0 * std::f32::consts::PI);
+        let r = (1.0 - z * z).max(0.0).sqrt();
+
+        let normal = Vec3::new(r * phi.cos(), r * phi.sin(), z);
+        (
+            self.center + normal * self.radius,
+            normal,
+            self.area().recip(),
+        )
+    }
 }
 
 impl Intersect for Sphere {
@@ -48,38 +225,155 @@ impl Intersect for Sphere {
         let b_halved = p_minus_c.dot(ray.direction());
         let c = p_minus_c.length_squared() - self.radius * self.radius;
 
-        // this is delta / 4
-        let delta_reduced = b_halved * b_halved - c;
-        if delta_reduced < 0.0 {
-            return None;
+        let valid_t = closest_quadratic_root(1.0, b_halved, c);
+
+        valid_t.map(|t| self.intersection_at(ray, t))
+    }
+
+    fn intersection_in(&self, ray: Ray, t_range: Range<f32>) -> Option<Intersection> {
+        let p_minus_c = ray.point() - self.center;
+        let b_halved = p_minus_c.dot(ray.direction());
+        let c = p_minus_c.length_squared() - self.radius * self.radius;
+
+        let (t1, t2) = quadratic_roots(1.0, b_halved, c)?;
+        let t = [t1, t2].into_iter().find(|t| t_range.contains(t))?;
+
+        Some(self.intersection_at(ray, t))
+    }
+
+    fn occluded(&self, ray: Ray, max_t: f32) -> bool {
+        let p_minus_c = ray.point() - self.center;
+        let b_halved = p_minus_c.dot(ray.direction());
+        let c = p_minus_c.length_squared() - self.radius * self.radius;
+
+        closest_quadratic_root(1.0, b_halved, c).is_some_and(|t| t < max_t)
+    }
+}
+
+impl Bounded for Sphere {
+    #[inline(always)]
+    fn aabb(&self) -> Option<Aabb> {
+        let radius = Vec3::splat(self.radius.abs());
+        Some(Aabb::new(self.center - radius, self.center + radius))
+    }
+}
+
+/// A sphere moving in a straight line during the shutter: it's centered at
+/// `center0` at time zero and at `center1` at time one. Rays see it where it
+/// is at [their time](Ray::time), so it gets blurred along its motion.
+pub struct MovingSphere {
+    pub center0: Vec3,
+    pub center1: Vec3,
+    pub radius: f32,
+}
+
+impl MovingSphere {
+    /// The center of this sphere at `time`.
+    #[inline(always)]
+    pub fn center(&self, time: f32) -> Vec3 {
+        self.center0.lerp(self.center1, time)
+    }
+
+    /// This sphere, stopped where it is at `time`.
+    #[inline(always)]
+    pub fn at(&self, time: f32) -> Sphere {
+        Sphere {
+            center: self.center(time),
+            radius: self.radius,
         }
+    }
+}
 
-        let delta_sqrt_halved = delta_reduced.sqrt();
+impl Intersect for MovingSphere {
+    #[inline(always)]
+    fn intersection(&self, ray: Ray) -> Option<Intersection> {
+        self.at(ray.time()).intersection(ray)
+    }
 
-        let t1 = -b_halved + delta_sqrt_halved;
-        let t2 = -b_halved - delta_sqrt_halved;
+    #[inline(always)]
+    fn intersection_in(&self, ray: Ray, t_range: Range<f32>) -> Option<Intersection> {
+        self.at(ray.time()).intersection_in(ray, t_range)
+    }
 
-        let (min, max) = if t1 < t2 { (t1, t2) } else { (t2, t1) };
+    #[inline(always)]
+    fn occluded(&self, ray: Ray, max_t: f32) -> bool {
+        self.at(ray.time()).occluded(ray, max_t)
+    }
+}
 
-        let valid_t = if min >= EPSILON {
-            Some(min)
-        } else if max >= EPSILON {
-            Some(max)
-        } else {
-            None
-        };
+impl Bounded for MovingSphere {
+    /// Bounds the sphere during the whole shutter.
+    #[inline(always)]
+    fn aabb(&self) -> Option<Aabb> {
+        let start = self.at(0.0).aabb()?;
+        let end = self.at(1.0).aabb()?;
+        Some(start.union(end))
+    }
+}
+
+/// An axis-aligned ellipsoid shape. It's a sphere with a different
+/// radius along each axis.
+pub struct Ellipsoid {
+    pub center: Vec3,
+    pub radii: Vec3,
+}
+
+impl Ellipsoid {
+    #[inline(always)]
+    pub fn normal(&self, point: Vec3) -> Vec3 {
+        // the ellipsoid is the unit sphere scaled by `radii`, so normals
+        // have to be transformed by the inverse transpose of that scale,
+        // which means dividing by the radii twice: once to get back to the
+        // unit sphere and once more for the normal itself.
+        ((point - self.center) / (self.radii * self.radii))
+            .try_normalize()
+            .unwrap_or(Vec3::Z)
+    }
+}
+
+impl Intersect for Ellipsoid {
+    fn intersection(&self, ray: Ray) -> Option<Intersection> {
+        // scaling space by 1 / radii turns the ellipsoid into an unit
+        // sphere. since the transformation is linear, t stays the same
+        // as long as the direction isn't renormalized, so we just need
+        // to solve the sphere equation with a != 1:
+        // (d')² * t² + 2(P' - C').d' * t + (P' - C')² - 1 = 0
+
+        let p_minus_c = (ray.point() - self.center) / self.radii;
+        let direction = ray.direction() / self.radii;
 
-        valid_t.map(|t| {
+        let a = direction.length_squared();
+        let b_halved = p_minus_c.dot(direction);
+        let c = p_minus_c.length_squared() - 1.0;
+
+        closest_quadratic_root(a, b_halved, c).map(|t| {
             let point = ray.point_at_t(t);
-            Intersection {
-                point,
-                normal: self.normal(point),
-                t,
-            }
+            let uv = spherical_uv(((point - self.center) / self.radii).normalize());
+            Intersection::new(ray, t, self.normal(point)).with_uv(uv)
         })
     }
 }
 
+impl Bounded for Ellipsoid {
+    #[inline(always)]
+    fn aabb(&self) -> Option<Aabb> {
+        let radii = self.radii.abs();
+        Some(Aabb::new(self.center - radii, self.center + radii))
+    }
+}
+
+/// Two axes perpendicular to `normal` and to each other.
+#[inline(always)]
+fn tangent_axes(normal: Vec3) -> (Vec3, Vec3) {
+    let reference = if normal.x.abs() < 0.9 {
+        Vec3::X
+    } else {
+        Vec3::Y
+    };
+    let tangent = reference.cross(normal).normalize();
+    (tangent, normal.cross(tangent))
+}
+
 /// A plane shape.
 pub struct Plane {
     point: Vec3,
@@ -87,9 +381,33 @@ pub struct Plane {
 }
 
 impl Plane {
+    /// Creates a new [`Plane`] going through `point`, facing `normal`.
+    ///
+    /// # Panics
+    /// Panics if `normal` is not normalized.
     pub fn new(point: Vec3, normal: Vec3) -> Self {
-        assert!(normal.is_normalized());
-        Self { point, normal }
+        Self::try_new(point, normal).unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Same as [`Plane::new`], but fails instead of panicking.
+    pub fn try_new(point: Vec3, normal: Vec3) -> Result<Self, BuildError> {
+        check_normalized("normal", normal)?;
+        Ok(Self { point, normal })
+    }
+
+    /// The texture coordinates of a point in this plane: its coordinates
+    /// relative to `point`, along two axes perpendicular to the normal.
+    #[inline(always)]
+    fn uv(&self, point: Vec3) -> Vec2 {
+        let (tangent, bitangent) = tangent_axes(self.normal);
+        let offset = point - self.point;
+        Vec2::new(offset.dot(tangent), offset.dot(bitangent))
+    }
+
+    /// The intersection of `ray` with this plane at `t`.
+    #[inline(always)]
+    fn intersection_at(&self, ray: Ray, t: f32) -> Intersection {
+        Intersection::new(ray, t, self.normal).with_uv(self.uv(ray.point_at_t(t)))
     }
 }
 
@@ -102,22 +420,1249 @@ impl Intersect for Plane {
             let t = plane_point_minus_ray_point.dot(self.normal) / dir_dot_normal;
 
             if t > EPSILON {
-                return Some(Intersection {
-                    point: ray.point_at_t(t),
-                    normal: self.normal,
-                    t,
-                });
+                return Some(self.intersection_at(ray, t));
             }
         }
 
         None
     }
+
+    fn intersection_in(&self, ray: Ray, t_range: Range<f32>) -> Option<Intersection> {
+        let dir_dot_normal = ray.direction().dot(self.normal);
+        if dir_dot_normal == 0.0 {
+            return None;
+        }
+
+        let t = (self.point - ray.point()).dot(self.normal) / dir_dot_normal;
+        t_range.contains(&t).then(|| self.intersection_at(ray, t))
+    }
+
+    fn occluded(&self, ray: Ray, max_t: f32) -> bool {
+        let dir_dot_normal = ray.direction().dot(self.normal);
+        if dir_dot_normal == 0.0 {
+            return false;
+        }
+
+        let t = (self.point - ray.point()).dot(self.normal) / dir_dot_normal;
+        t > EPSILON && t < max_t
+    }
+}
+
+/// Planes are infinite, so they have no bounding box.
+impl Bounded for Plane {
+    #[inline(always)]
+    fn aabb(&self) -> Option<Aabb> {
+        None
+    }
+}
+
+/// A flat, round shape: the points of a plane within `radius` of `center`.
+///
+/// As an area light, it only emits light towards the side its normal
+/// points to.
+pub struct Disk {
+    center: Vec3,
+    normal: Vec3,
+    radius: f32,
+}
+
+impl Disk {
+    /// Creates a new [`Disk`].
+    ///
+    /// # Panics
+    /// Panics if `normal` is not normalized.
+    pub fn new(center: Vec3, normal: Vec3, radius: f32) -> Self {
+        assert!(normal.is_normalized());
+        Self {
+            center,
+            normal,
+            radius,
+        }
+    }
+
+    #[inline(always)]
+    pub fn center(&self) -> Vec3 {
+        self.center
+    }
+
+    #[inline(always)]
+    pub fn normal(&self) -> Vec3 {
+        self.normal
+    }
+
+    #[inline(always)]
+    pub fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    /// The t at which `ray` crosses the plane of this disk, if it does so
+    /// inside of it.
+    #[inline(always)]
+    fn t(&self, ray: Ray) -> Option<f32> {
+        let dir_dot_normal = ray.direction().dot(self.normal);
+        if dir_dot_normal == 0.0 {
+            return None;
+        }
+
+        let t = (self.center - ray.point()).dot(self.normal) / dir_dot_normal;
+        let offset = ray.point_at_t(t) - self.center;
+        (offset.length_squared() <= self.radius * self.radius).then_some(t)
+    }
+
+    /// The intersection of `ray` with this disk at `t`. The texture
+    /// coordinates are polar: the angle around the center, as a fraction of
+    /// a turn, and the distance to it, as a fraction of the radius.
+    #[inline(always)]
+    fn intersection_at(&self, ray: Ray, t: f32) -> Intersection {
+        let (tangent, bitangent) = tangent_axes(self.normal);
+        let offset = ray.point_at_t(t) - self.center;
+        let angle = offset.dot(bitangent).atan2(offset.dot(tangent));
+        let uv = Vec2::new(
+            angle / (2.0 * std::f32::consts::PI) + 0.5,
+            offset.length() / self.radius,
+        );
+
+        Intersection::new(ray, t, self.normal).with_uv(uv)
+    }
+
+    #[inline(always)]
+    pub fn area(&self) -> f32 {
+        std::f32::consts::PI * self.radius * self.radius
+    }
+
+    /// Samples an uniformly distributed point on this disk. Returns the
+    /// point, the normal at it and the probability density of having
+    /// sampled it, per unit area.
+    pub fn sample_surface(&self, rng: &mut impl Rng) -> (Vec3, Vec3, f32) {
+        let (tangent, bitangent) = tangent_axes(self.normal);
+
+        // the square root compensates for outer rings being larger
+        let r = self.radius * rng.gen_range(0.0f32..1.0).sqrt();
+        let phi = rng.gen_range(0.0..2.0 * std::f32::consts::PI);
+
+        let point = self.center + r * (phi.cos() * tangent + phi.sin() * bitangent);
+        (point, self.normal, self.area().recip())
+    }
+}
+
+impl Intersect for Disk {
+    fn intersection(&self, ray: Ray) -> Option<Intersection> {
+        self.t(ray)
+            .filter(|&t| t > EPSILON)
+            .map(|t| self.intersection_at(ray, t))
+    }
+
+    fn intersection_in(&self, ray: Ray, t_range: Range<f32>) -> Option<Intersection> {
+        self.t(ray)
+            .filter(|t| t_range.contains(t))
+            .map(|t| self.intersection_at(ray, t))
+    }
+
+    fn occluded(&self, ray: Ray, max_t: f32) -> bool {
+        self.t(ray).is_some_and(|t| t > EPSILON && t < max_t)
+    }
+}
+
+impl Bounded for Disk {
+    #[inline(always)]
+    fn aabb(&self) -> Option<Aabb> {
+        // along each axis, the disk extends by its radius times the sine of
+        // the angle between the axis and the normal
+        let sin_sq = (Vec3::ONE - self.normal * self.normal).max(Vec3::ZERO);
+        let extent = self.radius * Vec3::from(sin_sq.to_array().map(f32::sqrt));
+        Some(Aabb::new(self.center - extent, self.center + extent))
+    }
+}
+
+/// An axis-aligned box shape, going from `min` to `max`.
+pub struct Cuboid {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Cuboid {
+    /// Intersects the line of `ray` with the box, returning where it enters
+    /// and leaves it, with outward normals. Both can be behind the start of
+    /// the ray.
+    fn slabs(&self, ray: Ray) -> Option<(Intersection, Intersection)> {
+        let inv_direction = ray.direction().recip();
+        let t1 = (self.min - ray.point()) * inv_direction;
+        let t2 = (self.max - ray.point()) * inv_direction;
+        let t_near = t1.min(t2);
+        let t_far = t1.max(t2);
+
+        let t_enter = t_near.max_element();
+        let t_exit = t_far.min_element();
+        if t_enter > t_exit {
+            return None;
+        }
+
+        // the normal is along the axis of the slab that was crossed last
+        // when entering, or first when leaving.
+        let axis_normal = |t: Vec3, value: f32, sign: f32| {
+            let axis = Vec3::select(t.cmpeq(Vec3::splat(value)), Vec3::ONE, Vec3::ZERO);
+            let normal = axis * ray.direction().signum() * sign;
+            normal.try_normalize().unwrap_or(Vec3::Z)
+        };
+
+        Some((
+            Intersection::outward(ray, t_enter, axis_normal(t_near, t_enter, -1.0)),
+            Intersection::outward(ray, t_exit, axis_normal(t_far, t_exit, 1.0)),
+        ))
+    }
+}
+
+impl Intersect for Cuboid {
+    fn intersection(&self, ray: Ray) -> Option<Intersection> {
+        let (enter, exit) = self.slabs(ray)?;
+        [enter, exit]
+            .into_iter()
+            .find(|crossing| crossing.t >= EPSILON)
+            .map(|crossing| Intersection::new(ray, crossing.t, crossing.normal))
+    }
+}
+
+impl Bounded for Cuboid {
+    #[inline(always)]
+    fn aabb(&self) -> Option<Aabb> {
+        Some(Aabb::new(self.min, self.max))
+    }
+}
+
+/// A torus shape.
+pub struct Torus {
+    center: Vec3,
+    axis: Vec3,
+    major_radius: f32,
+    minor_radius: f32,
+}
+
+impl Torus {
+    /// Creates a new [`Torus`] around `center`, with its ring lying on the
+    /// plane perpendicular to `axis`. `major_radius` is the distance from
+    /// the center to the middle of the tube, and `minor_radius` is the
+    /// radius of the tube itself.
+    ///
+    /// # Panics
+    /// Panics if `axis` is not normalized.
+    pub fn new(center: Vec3, axis: Vec3, major_radius: f32, minor_radius: f32) -> Self {
+        assert!(axis.is_normalized());
+        Self {
+            center,
+            axis,
+            major_radius,
+            minor_radius,
+        }
+    }
+
+    #[inline(always)]
+    pub fn normal(&self, point: Vec3) -> Vec3 {
+        // the normal points away from the closest point in the
+        // circle that runs through the middle of the tube
+        let local = point - self.center;
+        let in_plane = local - local.dot(self.axis) * self.axis;
+        let ring_point = in_plane.try_normalize().unwrap_or(Vec3::ZERO) * self.major_radius;
+
+        (local - ring_point).try_normalize().unwrap_or(self.axis)
+    }
+}
+
+impl Intersect for Torus {
+    fn intersection(&self, ray: Ray) -> Option<Intersection> {
+        // a point X (relative to the center) is on the torus if
+        // (X² + R² - r²)² = 4R²(X² - (X.A)²)
+        // where A is the axis, R the major radius and r the minor radius.
+        //
+        // replacing X by O + d * t (O being the ray point relative to the
+        // center) gives a quartic in t. to keep it well conditioned, the
+        // ray is first moved up to the torus' bounding sphere.
+        let bounding_radius = self.major_radius + self.minor_radius;
+
+        let p_minus_c = ray.point() - self.center;
+        let b_halved = p_minus_c.dot(ray.direction());
+        let c = p_minus_c.length_squared() - bounding_radius * bounding_radius;
+        let delta_reduced = b_halved * b_halved - c;
+        if delta_reduced < 0.0 {
+            return None;
+        }
+
+        let t_offset = (-b_halved - delta_reduced.sqrt()).max(0.0);
+
+        let origin = (p_minus_c + ray.direction() * t_offset).as_dvec3();
+        let direction = ray.direction().as_dvec3();
+        let axis = self.axis.as_dvec3();
+        let major_sq = (self.major_radius as f64).powi(2);
+        let minor_sq = (self.minor_radius as f64).powi(2);
+
+        let o_dot_d = origin.dot(direction);
+        let o_dot_a = origin.dot(axis);
+        let d_dot_a = direction.dot(axis);
+        let o_sq = origin.length_squared();
+        let alpha = o_sq + major_sq - minor_sq;
+
+        let a = 4.0 * o_dot_d;
+        let b = 4.0 * o_dot_d * o_dot_d + 2.0 * alpha - 4.0 * major_sq * (1.0 - d_dot_a * d_dot_a);
+        let c = 4.0 * alpha * o_dot_d - 8.0 * major_sq * (o_dot_d - o_dot_a * d_dot_a);
+        let d = alpha * alpha - 4.0 * major_sq * (o_sq - o_dot_a * o_dot_a);
+
+        let t = solver::solve_quartic(a, b, c, d)
+            .iter()
+            .map(|t| *t as f32 + t_offset)
+            .filter(|t| *t >= EPSILON)
+            .min_by(|a, b| a.total_cmp(b))?;
+
+        Some(Intersection::new(ray, t, self.normal(ray.point_at_t(t))))
+    }
+}
+
+impl Bounded for Torus {
+    fn aabb(&self) -> Option<Aabb> {
+        // along each world axis, the ring extends by the major radius times
+        // how perpendicular that axis is to the torus' axis.
+        let ring_extent = (Vec3::ONE - self.axis * self.axis).max(Vec3::ZERO);
+        let extent = ring_extent.powf(0.5) * self.major_radius + Vec3::splat(self.minor_radius);
+        Some(Aabb::new(self.center - extent, self.center + extent))
+    }
+}
+
+/// A capsule shape: a cylinder going from `a` to `b` with hemispherical
+/// caps on both ends.
+pub struct Capsule {
+    pub a: Vec3,
+    pub b: Vec3,
+    pub radius: f32,
+}
+
+impl Capsule {
+    #[inline(always)]
+    pub fn normal(&self, point: Vec3) -> Vec3 {
+        // the normal points away from the closest point in the segment
+        let axis = self.b - self.a;
+        let h = ((point - self.a).dot(axis) / axis.length_squared()).clamp(0.0, 1.0);
+        let h = if h.is_nan() { 0.0 } else { h };
+
+        (point - (self.a + axis * h))
+            .try_normalize()
+            .unwrap_or(Vec3::Z)
+    }
+}
+
+impl Intersect for Capsule {
+    fn intersection(&self, ray: Ray) -> Option<Intersection> {
+        // a capsule is the union of an infinite cylinder clipped to the
+        // segment and two spheres clipped to the outside of it, so we
+        // test all of them and keep the closest valid t.
+        let axis = self.b - self.a;
+        let axis_sq = axis.length_squared();
+        let p_minus_a = ray.point() - self.a;
+
+        let axis_dot_d = axis.dot(ray.direction());
+        let axis_dot_pa = axis.dot(p_minus_a);
+
+        // position of the point at t along the axis, scaled by axis_sq
+        let height_at = |t: f32| axis_dot_pa + t * axis_dot_d;
+
+        let mut closest = f32::INFINITY;
+        let mut consider = |t: f32| {
+            if t >= EPSILON && t < closest {
+                closest = t;
+            }
+        };
+
+        // lateral surface: distance from the axis equals the radius.
+        // a is zero when the ray is parallel to the axis, in which case
+        // only the caps can be hit.
+        let a = axis_sq - axis_dot_d * axis_dot_d;
+        if a > EPSILON * axis_sq {
+            let b_halved = axis_sq * p_minus_a.dot(ray.direction()) - axis_dot_pa * axis_dot_d;
+            let c = axis_sq * p_minus_a.length_squared()
+                - axis_dot_pa * axis_dot_pa
+                - self.radius * self.radius * axis_sq;
+
+            if let Some((t1, t2)) = quadratic_roots(a, b_halved, c) {
+                for t in [t1, t2] {
+                    let height = height_at(t);
+                    if height > 0.0 && height < axis_sq {
+                        consider(t);
+                    }
+                }
+            }
+        }
+
+        // caps
+        for (center, outside) in [(self.a, true), (self.b, false)] {
+            let p_minus_c = ray.point() - center;
+            let b_halved = p_minus_c.dot(ray.direction());
+            let c = p_minus_c.length_squared() - self.radius * self.radius;
+
+            if let Some((t1, t2)) = quadratic_roots(1.0, b_halved, c) {
+                for t in [t1, t2] {
+                    let height = height_at(t);
+                    let in_cap = if outside {
+                        height <= 0.0
+                    } else {
+                        height >= axis_sq
+                    };
+
+                    if in_cap {
+                        consider(t);
+                    }
+                }
+            }
+        }
+
+        closest
+            .is_finite()
+            .then(|| Intersection::new(ray, closest, self.normal(ray.point_at_t(closest))))
+    }
+}
+
+impl Bounded for Capsule {
+    #[inline(always)]
+    fn aabb(&self) -> Option<Aabb> {
+        let radius = Vec3::splat(self.radius.abs());
+        Some(Aabb::new(
+            self.a.min(self.b) - radius,
+            self.a.max(self.b) + radius,
+        ))
+    }
+}
+
+/// A finite cylinder shape, going from `base` along `axis` for `height`,
+/// optionally closed by flat caps on both ends.
+pub struct Cylinder {
+    base: Vec3,
+    axis: Vec3,
+    radius: f32,
+    height: f32,
+    capped: bool,
+}
+
+impl Cylinder {
+    /// Creates a new [`Cylinder`]. If `capped` is false, only its lateral
+    /// surface exists, like a tube.
+    ///
+    /// # Panics
+    /// Panics if `axis` is not normalized.
+    pub fn new(base: Vec3, axis: Vec3, radius: f32, height: f32, capped: bool) -> Self {
+        assert!(axis.is_normalized());
+        Self {
+            base,
+            axis,
+            radius,
+            height,
+            capped,
+        }
+    }
+
+    #[inline(always)]
+    pub fn base(&self) -> Vec3 {
+        self.base
+    }
+
+    #[inline(always)]
+    pub fn axis(&self) -> Vec3 {
+        self.axis
+    }
+
+    #[inline(always)]
+    pub fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    #[inline(always)]
+    pub fn height(&self) -> f32 {
+        self.height
+    }
+
+    #[inline(always)]
+    pub fn capped(&self) -> bool {
+        self.capped
+    }
+}
+
+impl Intersect for Cylinder {
+    fn intersection(&self, ray: Ray) -> Option<Intersection> {
+        let p_minus_b = ray.point() - self.base;
+        let d_dot_axis = ray.direction().dot(self.axis);
+        let pb_dot_axis = p_minus_b.dot(self.axis);
+
+        // the parts of the ray perpendicular to the axis
+        let d_perp = ray.direction() - self.axis * d_dot_axis;
+        let p_perp = p_minus_b - self.axis * pb_dot_axis;
+
+        let mut closest: Option<(f32, Vec3)> = None;
+        let mut consider = |t: f32, normal: Vec3| {
+            if t >= EPSILON && closest.is_none_or(|(closest, _)| t < closest) {
+                closest = Some((t, normal));
+            }
+        };
+
+        // lateral surface: the distance to the axis equals the radius. a is
+        // zero when the ray is parallel to the axis, in which case only the
+        // caps can be hit. both roots are the same when the ray grazes the
+        // surface, but only the closest valid one is kept.
+        let a = d_perp.length_squared();
+        if a > 0.0 {
+            let b_halved = p_perp.dot(d_perp);
+            let c = p_perp.length_squared() - self.radius * self.radius;
+
+            if let Some((t1, t2)) = quadratic_roots(a, b_halved, c) {
+                for t in [t1, t2] {
+                    let height = pb_dot_axis + t * d_dot_axis;
+                    if (0.0..=self.height).contains(&height) {
+                        let normal = (p_perp + d_perp * t).try_normalize().unwrap_or(Vec3::Z);
+                        consider(t, normal);
+                    }
+                }
+            }
+        }
+
+        if self.capped && d_dot_axis != 0.0 {
+            for (height, normal) in [(0.0, -self.axis), (self.height, self.axis)] {
+                let t = (height - pb_dot_axis) / d_dot_axis;
+                if (p_perp + d_perp * t).length_squared() <= self.radius * self.radius {
+                    consider(t, normal);
+                }
+            }
+        }
+
+        closest.map(|(t, normal)| Intersection::new(ray, t, normal))
+    }
+}
+
+impl Bounded for Cylinder {
+    #[inline(always)]
+    fn aabb(&self) -> Option<Aabb> {
+        // the bounding box of both ends, which are disks
+        let sin_sq = (Vec3::ONE - self.axis * self.axis).max(Vec3::ZERO);
+        let extent = self.radius.abs() * Vec3::from(sin_sq.to_array().map(f32::sqrt));
+        let top = self.base + self.axis * self.height;
+
+        Some(Aabb::new(
+            self.base.min(top) - extent,
+            self.base.max(top) + extent,
+        ))
+    }
+}
+
+/// An affine transform taking points from the local space of a shape to
+/// world space, together with what's needed to go the other way around.
+struct Frame {
+    transform: Affine3A,
+    inverse: Affine3A,
+    /// The inverse-transpose of the linear part of `transform`, which is
+    /// what transforms normals correctly under non-uniform scaling.
+    normal_matrix: Mat3A,
+}
+
+impl Frame {
+    /// # Panics
+    /// Panics if `transform` is not invertible.
+    fn new(transform: Affine3A) -> Self {
+        assert!(transform.matrix3.determinant() != 0.0);
+
+        let inverse = transform.inverse();
+        Self {
+            transform,
+            inverse,
+            normal_matrix: inverse.matrix3.transpose(),
+        }
+    }
+
+    /// Transforms a ray in world space to the local space of this frame.
+    /// Also returns the length of the local direction before it was
+    /// normalized: a world t is that many times smaller than the local one.
+    #[inline(always)]
+    fn local_ray(&self, ray: Ray) -> (Ray, f32) {
+        let local_point = self.inverse.transform_point3a(ray.point());
+        let local_direction = self.inverse.transform_vector3a(ray.direction());
+
+        let scale = local_direction.length();
+        let local_ray = Ray::new(local_point, local_direction / scale)
+            .with_time(ray.time())
+            .with_seed(ray.seed());
+        (local_ray, scale)
+    }
+
+    /// Transforms an intersection with a ray returned by
+    /// [`Frame::local_ray`] back to world space.
+    #[inline(always)]
+    fn to_world(&self, local: Intersection, scale: f32) -> Intersection {
+        let to_world_normal = |normal: Vec3| {
+            (self.normal_matrix * normal)
+                .try_normalize()
+                .unwrap_or(normal)
+        };
+
+        Intersection {
+            point: self.transform.transform_point3a(local.point),
+            normal: to_world_normal(local.normal),
+            geometric_normal: to_world_normal(local.geometric_normal),
+            t: local.t / scale,
+            front_face: local.front_face,
+            uv: local.uv,
+        }
+    }
+
+    /// Intersects a ray in world space with `shape`, which is in the local
+    /// space of this frame.
+    fn intersection(&self, shape: &Shape, ray: Ray) -> Option<Intersection> {
+        let (local_ray, scale) = self.local_ray(ray);
+        let local = shape.intersection(local_ray)?;
+
+        Some(self.to_world(local, scale))
+    }
+
+    /// Whether a ray in world space hits `shape`, which is in the local
+    /// space of this frame, before `max_t`.
+    #[inline(always)]
+    fn occluded(&self, shape: &Shape, ray: Ray, max_t: f32) -> bool {
+        let (local_ray, scale) = self.local_ray(ray);
+        shape.occluded(local_ray, max_t * scale)
+    }
+}
+
+/// A shape placed in space by an affine transform. The inner shape is
+/// described in its own local space, and the transform takes points from
+/// local space to world space.
+pub struct Transformed {
+    shape: Box<Shape>,
+    frame: Frame,
+}
+
+impl Transformed {
+    /// Creates a new [`Transformed`] shape.
+    ///
+    /// # Panics
+    /// Panics if `transform` is not invertible.
+    pub fn new(shape: Shape, transform: Affine3A) -> Self {
+        Self {
+            shape: Box::new(shape),
+            frame: Frame::new(transform),
+        }
+    }
+
+    #[inline(always)]
+    pub fn shape(&self) -> &Shape {
+        &self.shape
+    }
+
+    #[inline(always)]
+    pub fn transform(&self) -> Affine3A {
+        self.frame.transform
+    }
+}
+
+impl Intersect for Transformed {
+    #[inline(always)]
+    fn intersection(&self, ray: Ray) -> Option<Intersection> {
+        self.frame.intersection(&self.shape, ray)
+    }
+
+    #[inline(always)]
+    fn occluded(&self, ray: Ray, max_t: f32) -> bool {
+        self.frame.occluded(&self.shape, ray, max_t)
+    }
+}
+
+impl Bounded for Transformed {
+    #[inline(always)]
+    fn aabb(&self) -> Option<Aabb> {
+        Some(self.shape.aabb()?.transformed(self.frame.transform))
+    }
+}
+
+/// Like [`Transformed`], but the inner shape is shared with other instances
+/// instead of owned. Placing the same heavy shape (like a [`Mesh`]) many
+/// times this way only stores it once.
+pub struct Instance {
+    shape: Arc<Shape>,
+    frame: Frame,
+}
+
+impl Instance {
+    /// Creates a new [`Instance`] of `shape`.
+    ///
+    /// # Panics
+    /// Panics if `transform` is not invertible.
+    pub fn new(shape: Arc<Shape>, transform: Affine3A) -> Self {
+        Self {
+            shape,
+            frame: Frame::new(transform),
+        }
+    }
+
+    #[inline(always)]
+    pub fn shape(&self) -> &Arc<Shape> {
+        &self.shape
+    }
+
+    #[inline(always)]
+    pub fn transform(&self) -> Affine3A {
+        self.frame.transform
+    }
+}
+
+impl Intersect for Instance {
+    #[inline(always)]
+    fn intersection(&self, ray: Ray) -> Option<Intersection> {
+        self.frame.intersection(&self.shape, ray)
+    }
+
+    #[inline(always)]
+    fn occluded(&self, ray: Ray, max_t: f32) -> bool {
+        self.frame.occluded(&self.shape, ray, max_t)
+    }
+}
+
+impl Bounded for Instance {
+    #[inline(always)]
+    fn aabb(&self) -> Option<Aabb> {
+        Some(self.shape.aabb()?.transformed(self.frame.transform))
+    }
+}
+
+/// A volume of constant density, like fog or smoke, filling a closed
+/// boundary shape.
+///
+/// Rays going through it can randomly scatter anywhere inside of it, with a
+/// probability that grows with the distance travelled and the density.
+/// Intersections with a medium are these scattering events, and since they
+/// happen inside the volume and not on a surface, their normal has no
+/// meaning: it's always set to face the incoming ray. Materials meant for
+/// media, like [`Isotropic`](crate::material::Isotropic), ignore it.
+pub struct ConstantMedium {
+    boundary: Box<Shape>,
+    density: f32,
+}
+
+impl ConstantMedium {
+    /// Creates a new [`ConstantMedium`] filling `boundary`, which must be a
+    /// closed shape with outward facing normals.
+    ///
+    /// # Panics
+    /// Panics if `density` is not positive.
+    pub fn new(boundary: Shape, density: f32) -> Self {
+        assert!(density > 0.0);
+        Self {
+            boundary: Box::new(boundary),
+            density,
+        }
+    }
+
+    #[inline(always)]
+    pub fn boundary(&self) -> &Shape {
+        &self.boundary
+    }
+
+    #[inline(always)]
+    pub fn density(&self) -> f32 {
+        self.density
+    }
+}
+
+impl Intersect for ConstantMedium {
+    fn intersection(&self, ray: Ray) -> Option<Intersection> {
+        let first = self.boundary.intersection(ray)?;
+
+        // if the boundary is hit from the inside, the ray starts in the
+        // volume and the first hit is where it leaves it.
+        let (t_enter, t_exit) = if !first.front_face {
+            (0.0, first.t)
+        } else {
+            let inside = Ray::new(first.point, ray.direction()).with_time(ray.time());
+            let exit = self.boundary.intersection(inside)?;
+            (first.t, first.t + exit.t)
+        };
+
+        // the scattering distance is random, but derived from the ray and
+        // its seed so that renders stay reproducible: the same ray in the
+        // same sample always scatters at the same place.
+        let seed = [ray.point(), ray.direction()]
+            .iter()
+            .flat_map(|v| v.to_array())
+            .fold(mix64(ray.seed()), |seed, x| {
+                mix64(seed ^ x.to_bits() as u64)
+            });
+        let random = ((seed >> 40) as f32 + 1.0) / (1u64 << 24) as f32;
+        let distance = -random.ln() / self.density;
+        let t = t_enter + distance;
+        if t >= t_exit || t < EPSILON {
+            return None;
+        }
+
+        Some(Intersection::new(ray, t, -ray.direction()))
+    }
+}
+
+impl Bounded for ConstantMedium {
+    #[inline(always)]
+    fn aabb(&self) -> Option<Aabb> {
+        self.boundary.aabb()
+    }
 }
 
 /// A shape in space - just something that can be tested for intersection
 /// with a ray.
-#[enum_dispatch(Intersect)]
+#[enum_dispatch(Intersect, IntersectAll, Bounded)]
 pub enum Shape {
     Sphere(Sphere),
+    MovingSphere(MovingSphere),
+    Ellipsoid(Ellipsoid),
     Plane(Plane),
+    Disk(Disk),
+    Cuboid(Cuboid),
+    Torus(Torus),
+    Capsule(Capsule),
+    Cylinder(Cylinder),
+    Mesh(Mesh),
+    Transformed(Transformed),
+    Instance(Instance),
+    ConstantMedium(ConstantMedium),
+    Csg(Csg),
+}
+
+impl Shape {
+    /// The surface area of this shape, if it supports
+    /// [surface sampling](Shape::sample_surface). Points are sampled
+    /// uniformly, so the density of every one of them is the inverse of the
+    /// area.
+    pub fn area(&self) -> Option<f32> {
+        match self {
+            Shape::Sphere(sphere) => Some(sphere.area()),
+            Shape::Disk(disk) => Some(disk.area()),
+            _ => None,
+        }
+    }
+
+    /// Samples an uniformly distributed point on the surface of this shape.
+    /// Returns the point, the normal at it and the probability density of
+    /// having sampled it, per unit area, or [`None`] if this kind of shape
+    /// can't be sampled.
+    pub fn sample_surface(&self, rng: &mut impl Rng) -> Option<(Vec3, Vec3, f32)> {
+        match self {
+            Shape::Sphere(sphere) => Some(sphere.sample_surface(rng)),
+            Shape::Disk(disk) => Some(disk.sample_surface(rng)),
+            _ => None,
+        }
+    }
+
+    /// Applies `transform` to this shape, after any transform it already
+    /// has.
+    pub fn transformed(self, transform: Affine3A) -> Shape {
+        match self {
+            Shape::Transformed(transformed) => Shape::Transformed(Transformed::new(
+                *transformed.shape,
+                transform * transformed.frame.transform,
+            )),
+            shape => Shape::Transformed(Transformed::new(shape, transform)),
+        }
+    }
+
+    /// Moves this shape by `offset`.
+    #[inline(always)]
+    pub fn translated(self, offset: Vec3) -> Shape {
+        self.transformed(Affine3A::from_translation(offset.into()))
+    }
+
+    /// Rotates this shape around the Y axis going through the origin by
+    /// `angle` radians.
+    #[inline(always)]
+    pub fn rotated_y(self, angle: f32) -> Shape {
+        self.transformed(Affine3A::from_rotation_y(angle))
+    }
+
+    /// Scales this shape by `factor` on each axis, relative to the origin.
+    #[inline(always)]
+    pub fn scaled(self, factor: Vec3) -> Shape {
+        self.transformed(Affine3A::from_scale(factor.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        common::Color,
+        material::Simple,
+        render::{Camera, Scene},
+        texture::SolidColor,
+    };
+    use std::sync::Arc;
+
+    /// A torus around the origin, lying on the XZ plane, whose tube goes
+    /// from 1.5 to 2.5 units away from the Y axis.
+    fn torus() -> Torus {
+        Torus::new(Vec3::ZERO, Vec3::Y, 2.0, 0.5)
+    }
+
+    #[test]
+    fn torus_straight_hits() {
+        let torus = torus();
+
+        // through the tube, from the side
+        let hit = torus
+            .intersection(Ray::new(Vec3::new(-5.0, 0.0, 0.0), Vec3::X))
+            .unwrap();
+        assert!((hit.t - 2.5).abs() < 1e-4, "{}", hit.t);
+        assert!((hit.normal + Vec3::X).length() < 1e-4);
+
+        // down onto the top of the tube
+        let hit = torus
+            .intersection(Ray::new(Vec3::new(0.0, 5.0, 2.0), -Vec3::Y))
+            .unwrap();
+        assert!((hit.t - 4.5).abs() < 1e-4, "{}", hit.t);
+        assert!((hit.normal - Vec3::Y).length() < 1e-4);
+    }
+
+    #[test]
+    fn torus_grazing_rays_hit() {
+        let torus = torus();
+        for offset in [0.0, 1e-3, 1e-2] {
+            // along the top of the tube
+            let ray = Ray::new(Vec3::new(-5.0, 0.5 - offset, 0.0), Vec3::X);
+            let hit = torus.intersection(ray);
+            assert!(hit.is_some(), "missed the top at {offset}");
+            assert!((hit.unwrap().point.x + 2.0).abs() < 0.2);
+
+            // down the inner and outer sides of the tube
+            for x in [1.5 + offset, 2.5 - offset] {
+                let ray = Ray::new(Vec3::new(x, 5.0, 0.0), -Vec3::Y);
+                let hit = torus.intersection(ray);
+                assert!(hit.is_some(), "missed the side at {x}");
+                assert!(hit.unwrap().point.y.abs() < 0.2);
+            }
+        }
+    }
+
+    #[test]
+    fn torus_misses_through_the_hole() {
+        let torus = torus();
+        for x in [0.0, 1.0, 1.49] {
+            let ray = Ray::new(Vec3::new(x, 5.0, 0.0), -Vec3::Y);
+            assert_eq!(torus.intersection(ray), None, "hit the hole at {x}");
+        }
+
+        // tilted, but still through the hole
+        let direction = Vec3::new(0.2, -1.0, 0.1).normalize();
+        let ray = Ray::new(-5.0 * direction, direction);
+        assert_eq!(torus.intersection(ray), None);
+
+        // just above the top of the tube
+        let ray = Ray::new(Vec3::new(-5.0, 0.51, 0.0), Vec3::X);
+        assert_eq!(torus.intersection(ray), None);
+    }
+
+    #[test]
+    fn ellipsoid_normal_is_the_gradient() {
+        let ellipsoid = Ellipsoid {
+            center: Vec3::new(1.0, -2.0, 0.5),
+            radii: Vec3::new(3.0, 0.5, 1.5),
+        };
+
+        // the implicit function of the surface, in f64 so that central
+        // differences are precise
+        let implicit = |point: glam::DVec3| {
+            ((point - ellipsoid.center.as_dvec3()) / ellipsoid.radii.as_dvec3()).length_squared()
+                - 1.0
+        };
+
+        for index in 0..64 {
+            // rays from all around, towards points near the center
+            let theta = index as f32 * 0.7;
+            let phi = index as f32 * 0.3;
+            let from = ellipsoid.center
+                + 10.0 * Vec3::new(theta.cos() * phi.sin(), phi.cos(), theta.sin() * phi.sin());
+            let target = ellipsoid.center + 0.1 * Vec3::new(phi.cos(), theta.sin(), 0.0);
+            let ray = Ray::new(from, (target - from).normalize());
+
+            let hit = ellipsoid.intersection(ray).unwrap();
+            let point = hit.point.as_dvec3();
+            assert!(
+                implicit(point).abs() < 1e-3,
+                "{} is off the surface",
+                hit.point
+            );
+
+            let h = 1e-5;
+            let gradient = glam::DVec3::new(
+                implicit(point + glam::DVec3::X * h) - implicit(point - glam::DVec3::X * h),
+                implicit(point + glam::DVec3::Y * h) - implicit(point - glam::DVec3::Y * h),
+                implicit(point + glam::DVec3::Z * h) - implicit(point - glam::DVec3::Z * h),
+            )
+            .normalize();
+            let gradient = Vec3::new(gradient.x as f32, gradient.y as f32, gradient.z as f32);
+
+            let normal = ellipsoid.normal(hit.point);
+            assert!(
+                (normal - gradient).length() < 1e-3,
+                "{normal} != {gradient} at {}",
+                hit.point
+            );
+            // the ray came from outside, so the normal faces it unchanged
+            assert!((hit.normal - normal).length() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn media_scatter_by_the_seed_of_rays() {
+        let fog = ConstantMedium::new(
+            Cuboid {
+                min: Vec3::splat(-100.0),
+                max: Vec3::splat(100.0),
+            }
+            .into(),
+            0.1,
+        );
+        let ray = Ray::new(Vec3::new(0.0, 0.0, -200.0), Vec3::Z);
+        let distance = |seed| fog.intersection(ray.with_seed(seed)).unwrap().t;
+
+        // reproducible for the same seed, but not the same for every one
+        assert_eq!(distance(3), distance(3));
+        let mut distances: Vec<f32> = (0..32).map(distance).collect();
+        distances.sort_by(f32::total_cmp);
+        distances.dedup();
+        assert!(distances.len() > 16, "{distances:?}");
+
+        // and the mean free path is the inverse of the density
+        let mean = (0..4096).map(|seed| distance(seed) - 100.0).sum::<f32>() / 4096.0;
+        assert!((mean - 10.0).abs() < 0.5, "mean free path of {mean}");
+    }
+
+    #[test]
+    fn occlusion_stops_right_before_max_t() {
+        let ray = Ray::new(Vec3::ZERO, Vec3::Z);
+        let sphere = |distance: f32| Sphere {
+            center: Vec3::new(0.0, 0.0, distance + 1.0),
+            radius: 1.0,
+        };
+
+        // exactly at max_t isn't before it
+        let at = sphere(4.0);
+        assert_eq!(at.intersection(ray).unwrap().t, 4.0);
+        assert!(!at.occluded(ray, 4.0));
+        assert!(at.occluded(ray, 4.001));
+
+        let beyond = sphere(4.001);
+        assert!(!beyond.occluded(ray, 4.0));
+        assert!(beyond.occluded(ray, 4.01));
+
+        // planes behind the origin never occlude, no matter how far the ray
+        // goes
+        let behind = Plane::new(Vec3::new(0.0, 0.0, -1.0), Vec3::Z);
+        assert!(!behind.occluded(ray, 4.0));
+        assert!(!behind.occluded(ray, f32::INFINITY));
+
+        let facing_away = Plane::new(Vec3::new(0.0, 0.0, -1.0), -Vec3::Z);
+        assert!(!facing_away.occluded(ray, f32::INFINITY));
+
+        // and scenes agree with their objects
+        let material = Arc::new(Simple {
+            albedo: Box::new(SolidColor(Color::new(0.5, 0.5, 0.5))),
+            diffuse: 1.0,
+            fuzzyness: 0.0,
+        });
+        let scene = |shape: Shape| {
+            Scene::builder()
+                .camera(Camera::new(Vec3::ZERO, Vec3::Z, 1.0, 1.0))
+                .shape(shape, material.clone())
+                .build()
+        };
+
+        for (shape, max_t, occluded) in [
+            (Shape::from(sphere(4.0)), 4.0, false),
+            (sphere(4.0).into(), 4.001, true),
+            (sphere(4.001).into(), 4.0, false),
+            (behind.into(), f32::INFINITY, false),
+        ] {
+            let scene = scene(shape);
+            assert_eq!(scene.occluded(ray, max_t), occluded);
+            assert_eq!(scene.build().occluded(ray, max_t), occluded);
+        }
+    }
+
+    #[test]
+    fn intersections_beyond_t_max_are_ignored() {
+        let ray = Ray::new(Vec3::ZERO, Vec3::Z);
+        let sphere = Sphere {
+            center: Vec3::new(0.0, 0.0, 5.0),
+            radius: 1.0,
+        };
+
+        // the sphere goes from 4 to 6
+        assert!(sphere.intersection_in(ray, 0.0..3.0).is_none());
+        assert!(sphere.intersection_in(ray, 0.0..4.0).is_none());
+        assert_eq!(sphere.intersection_in(ray, 0.0..4.5).unwrap().t, 4.0);
+
+        // past the near side, the far one is found instead
+        let far = sphere.intersection_in(ray, 4.5..10.0).unwrap();
+        assert_eq!(far.t, 6.0);
+        assert!(!far.front_face);
+        assert!(sphere.intersection_in(ray, 6.5..10.0).is_none());
+
+        let plane = Plane::new(Vec3::new(0.0, 0.0, 5.0), -Vec3::Z);
+        assert!(plane.intersection_in(ray, 0.0..5.0).is_none());
+        assert_eq!(plane.intersection_in(ray, 0.0..5.5).unwrap().t, 5.0);
+
+        // shapes without their own implementation filter the closest hit
+        let cuboid = Cuboid {
+            min: Vec3::new(-1.0, -1.0, 4.0),
+            max: Vec3::new(1.0, 1.0, 6.0),
+        };
+        assert!(cuboid.intersection_in(ray, 0.0..3.0).is_none());
+        assert_eq!(cuboid.intersection_in(ray, 0.0..4.5).unwrap().t, 4.0);
+    }
+
+    #[test]
+    fn capsule_hits_caps_and_sides() {
+        // from y = -0.5 to y = 2.5, with the caps centered at y = 0 and 2
+        let capsule = Capsule {
+            a: Vec3::ZERO,
+            b: Vec3::new(0.0, 2.0, 0.0),
+            radius: 0.5,
+        };
+        let assert_hit = |origin: Vec3, direction: Vec3, t: f32, normal: Vec3| {
+            let ray = Ray::new(origin, direction);
+            let hit = capsule.intersection(ray).unwrap();
+            assert!((hit.t - t).abs() < 1e-4, "t of {} for {ray:?}", hit.t);
+            assert!(
+                (hit.normal - normal).length() < 1e-4,
+                "normal of {} for {ray:?}",
+                hit.normal
+            );
+        };
+
+        // along the axis, from both ends
+        assert_hit(Vec3::new(0.0, -5.0, 0.0), Vec3::Y, 4.5, -Vec3::Y);
+        assert_hit(Vec3::new(0.0, 5.0, 0.0), -Vec3::Y, 2.5, Vec3::Y);
+
+        // parallel to the axis, but off of it, only the cap is hit
+        let off_axis = Vec3::new(0.3, -0.4, 0.0);
+        assert_hit(Vec3::new(0.3, -5.0, 0.0), Vec3::Y, 4.6, off_axis / 0.5);
+
+        // the side, and the bottom cap right below where it ends
+        assert_hit(Vec3::new(-5.0, 1.0, 0.0), Vec3::X, 4.5, -Vec3::X);
+        let x = -(0.25f32 - 0.25 * 0.25).sqrt();
+        let below = Vec3::new(x, -0.25, 0.0);
+        assert_hit(Vec3::new(-5.0, -0.25, 0.0), Vec3::X, 5.0 + x, below / 0.5);
+
+        // misses, both beside it and past its ends
+        for (origin, direction) in [
+            (Vec3::new(0.6, -5.0, 0.0), Vec3::Y),
+            (Vec3::new(-5.0, 2.6, 0.0), Vec3::X),
+            (Vec3::new(-5.0, -0.6, 0.0), Vec3::X),
+        ] {
+            assert!(capsule.intersection(Ray::new(origin, direction)).is_none());
+        }
+    }
+
+    #[test]
+    fn capsule_rays_from_inside_hit_forwards() {
+        let capsule = Capsule {
+            a: Vec3::ZERO,
+            b: Vec3::new(0.0, 2.0, 0.0),
+            radius: 0.5,
+        };
+
+        // through the side, through a cap and along the axis
+        for (direction, t) in [
+            (Vec3::X, 0.5),
+            (Vec3::Y, 1.5),
+            (-Vec3::Y, 1.5),
+            (Vec3::new(1.0, 1.0, 0.0).normalize(), 0.5 * 2f32.sqrt()),
+        ] {
+            let ray = Ray::new(Vec3::new(0.0, 1.0, 0.0), direction);
+            let hit = capsule.intersection(ray).unwrap();
+            assert!((hit.t - t).abs() < 1e-4, "t of {} for {ray:?}", hit.t);
+            assert!(!hit.front_face);
+            assert!(hit.normal.dot(direction) < 0.0);
+        }
+    }
+
+    #[test]
+    fn rotated_boxes_match_oriented_boxes() {
+        // a cube with sides of 4, turned 45 degrees around the Y axis, so
+        // its faces point along the diagonals of the XZ plane
+        let center = Vec3::new(1.0, 2.0, 3.0);
+        let transform = Affine3A::from_translation(center.into())
+            * Affine3A::from_rotation_y(std::f32::consts::FRAC_PI_4)
+            * Affine3A::from_scale(glam::Vec3::splat(2.0));
+        let cube = Shape::from(Cuboid {
+            min: Vec3::splat(-1.0),
+            max: Vec3::splat(1.0),
+        })
+        .transformed(transform);
+
+        // the corners stick out by the half diagonal of the faces
+        let diagonal = 2.0 * 2f32.sqrt();
+        let half = Vec3::new(diagonal, 2.0, diagonal);
+        let aabb = cube.aabb().unwrap();
+        assert!((aabb.min - (center - half)).abs().max_element() < 1e-5);
+        assert!((aabb.max - (center + half)).abs().max_element() < 1e-5);
+
+        // rays along X, a bit to the side of the corner facing them, hit
+        // the face whose points p satisfy n.(p - center) = 2
+        let normal = Vec3::new(-1.0, 0.0, 1.0).normalize();
+        for offset in [0.3, 1.0, 2.5] {
+            let ray = Ray::new(center + Vec3::new(-10.0, 0.5, offset), Vec3::X);
+            let hit = cube.intersection(ray).unwrap();
+
+            let t = 10.0 + offset - diagonal;
+            assert!((hit.t - t).abs() < 1e-4, "t of {} at {offset}", hit.t);
+            assert!((hit.normal - normal).length() < 1e-4, "{}", hit.normal);
+            let distance = normal.dot(ray.point_at_t(hit.t) - center);
+            assert!((distance - 2.0).abs() < 1e-4);
+        }
+
+        // past the corner at the side, which is inside of the bounding box
+        let ray = Ray::new(center + Vec3::new(-10.0, 0.0, diagonal - 0.1), Vec3::X);
+        let hit = cube.intersection(ray).unwrap();
+        assert!((hit.point.x - center.x).abs() < 0.1 + 1e-4);
+        let ray = Ray::new(center + Vec3::new(-10.0, 0.0, diagonal + 0.1), Vec3::X);
+        assert!(cube.intersection(ray).is_none());
+    }
+
+    #[test]
+    fn instances_share_their_shape() {
+        let shape = Arc::new(Shape::from(Sphere {
+            center: Vec3::ZERO,
+            radius: 1.0,
+        }));
+
+        let instances: Vec<_> = (0..100)
+            .map(|index| {
+                let offset = Vec3::new(3.0 * index as f32, 0.0, 0.0);
+                Instance::new(
+                    shape.clone(),
+                    Affine3A::from_translation(offset.into())
+                        * Affine3A::from_scale(glam::Vec3::splat(1.0 + index as f32 / 100.0)),
+                )
+            })
+            .collect();
+
+        // every instance points to the same shape instead of copying it
+        assert_eq!(Arc::strong_count(&shape), 101);
+        assert!(instances
+            .iter()
+            .all(|instance| Arc::ptr_eq(instance.shape(), &shape)));
+
+        // but each one is hit where it was placed
+        for (index, instance) in instances.iter().enumerate() {
+            let x = 3.0 * index as f32;
+            let radius = 1.0 + index as f32 / 100.0;
+            let ray = Ray::new(Vec3::new(x, 10.0, 0.0), -Vec3::Y);
+            let hit = instance.intersection(ray).unwrap();
+            assert!((hit.t - (10.0 - radius)).abs() < 1e-4, "t of {}", hit.t);
+            assert!((hit.point - Vec3::new(x, radius, 0.0)).length() < 1e-4);
+            assert!((hit.normal - Vec3::Y).length() < 1e-4);
+
+            // and missed right beside it
+            let ray = Ray::new(Vec3::new(x + radius + 0.1, 10.0, 0.0), -Vec3::Y);
+            assert!(instance.intersection(ray).is_none());
+        }
+
+        drop(instances);
+        assert_eq!(Arc::strong_count(&shape), 1);
+    }
 }
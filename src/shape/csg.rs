@@ -0,0 +1,381 @@
+//! Constructive solid geometry: shapes made by combining closed shapes with
+//! boolean operations.
+//!
+//! Combining shapes requires knowing every interval in which a ray is inside
+//! of them, not only the closest intersection, which is what
+//! [`IntersectAll`] provides.
+
+use super::{
+    Capsule, ConstantMedium, Cuboid, Cylinder, Disk, Ellipsoid, Instance, Intersect, Intersection,
+    Mesh, MovingSphere, Plane, Shape, Sphere, Torus, Transformed,
+};
+use crate::accel::{Aabb, Bounded};
+use crate::common::Ray;
+use crate::EPSILON;
+use enum_dispatch::enum_dispatch;
+
+/// An interval of the line of a ray in which it's inside of a shape.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Span {
+    /// Where the ray enters the shape. Its normal points out of the shape.
+    pub enter: Intersection,
+    /// Where the ray leaves the shape. Its normal points out of the shape.
+    pub exit: Intersection,
+}
+
+/// Trait for closed shapes that can report every interval in which a ray is
+/// inside of them.
+#[enum_dispatch]
+pub trait IntersectAll {
+    /// Returns the ordered, non-overlapping intervals of the whole line of
+    /// `ray` (including what's behind its start) that are inside this shape.
+    /// The t of unbounded intervals is infinite.
+    ///
+    /// Returns [`None`] if this shape isn't closed or doesn't support this,
+    /// which is the default.
+    #[inline(always)]
+    fn spans(&self, _ray: Ray) -> Option<Vec<Span>> {
+        None
+    }
+}
+
+impl IntersectAll for Sphere {
+    fn spans(&self, ray: Ray) -> Option<Vec<Span>> {
+        let p_minus_c = ray.point() - self.center;
+        let b_halved = p_minus_c.dot(ray.direction());
+        let c = p_minus_c.length_squared() - self.radius * self.radius;
+
+        let span = super::quadratic_roots(1.0, b_halved, c).map(|(t1, t2)| {
+            let [enter, exit] =
+                [t1, t2].map(|t| Intersection::outward(ray, t, self.normal(ray.point_at_t(t))));
+
+            Span { enter, exit }
+        });
+
+        Some(span.into_iter().collect())
+    }
+}
+
+impl IntersectAll for MovingSphere {
+    #[inline(always)]
+    fn spans(&self, ray: Ray) -> Option<Vec<Span>> {
+        self.at(ray.time()).spans(ray)
+    }
+}
+
+impl IntersectAll for Ellipsoid {
+    fn spans(&self, ray: Ray) -> Option<Vec<Span>> {
+        let p_minus_c = (ray.point() - self.center) / self.radii;
+        let direction = ray.direction() / self.radii;
+
+        let a = direction.length_squared();
+        let b_halved = p_minus_c.dot(direction);
+        let c = p_minus_c.length_squared() - 1.0;
+
+        let span = super::quadratic_roots(a, b_halved, c).map(|(t1, t2)| {
+            let [enter, exit] =
+                [t1, t2].map(|t| Intersection::outward(ray, t, self.normal(ray.point_at_t(t))));
+
+            Span { enter, exit }
+        });
+
+        Some(span.into_iter().collect())
+    }
+}
+
+/// A [`Plane`] is treated as the half-space behind it, i.e. on the opposite
+/// side of its normal.
+impl IntersectAll for Plane {
+    fn spans(&self, ray: Ray) -> Option<Vec<Span>> {
+        let infinite = |t: f32| Intersection::outward(ray, t, self.normal);
+
+        let dir_dot_normal = ray.direction().dot(self.normal);
+        let distance = (ray.point() - self.point).dot(self.normal);
+        if dir_dot_normal == 0.0 {
+            // parallel: the ray is either always or never inside
+            return Some(if distance < 0.0 {
+                vec![Span {
+                    enter: infinite(f32::NEG_INFINITY),
+                    exit: infinite(f32::INFINITY),
+                }]
+            } else {
+                Vec::new()
+            });
+        }
+
+        let t = -distance / dir_dot_normal;
+        let hit = Intersection::outward(ray, t, self.normal);
+
+        Some(vec![if dir_dot_normal > 0.0 {
+            Span {
+                enter: infinite(f32::NEG_INFINITY),
+                exit: hit,
+            }
+        } else {
+            Span {
+                enter: hit,
+                exit: infinite(f32::INFINITY),
+            }
+        }])
+    }
+}
+
+impl IntersectAll for Cuboid {
+    fn spans(&self, ray: Ray) -> Option<Vec<Span>> {
+        let span = self.slabs(ray).map(|(enter, exit)| Span { enter, exit });
+        Some(span.into_iter().collect())
+    }
+}
+
+impl IntersectAll for Transformed {
+    fn spans(&self, ray: Ray) -> Option<Vec<Span>> {
+        let (local_ray, scale) = self.frame.local_ray(ray);
+        let spans = self.shape.spans(local_ray)?;
+
+        Some(
+            spans
+                .into_iter()
+                .map(|span| Span {
+                    enter: self.frame.to_world(span.enter, scale),
+                    exit: self.frame.to_world(span.exit, scale),
+                })
+                .collect(),
+        )
+    }
+}
+
+impl IntersectAll for Instance {
+    fn spans(&self, ray: Ray) -> Option<Vec<Span>> {
+        let (local_ray, scale) = self.frame.local_ray(ray);
+        let spans = self.shape.spans(local_ray)?;
+
+        Some(
+            spans
+                .into_iter()
+                .map(|span| Span {
+                    enter: self.frame.to_world(span.enter, scale),
+                    exit: self.frame.to_world(span.exit, scale),
+                })
+                .collect(),
+        )
+    }
+}
+
+impl IntersectAll for Disk {}
+impl IntersectAll for Torus {}
+impl IntersectAll for Capsule {}
+impl IntersectAll for Cylinder {}
+impl IntersectAll for Mesh {}
+impl IntersectAll for ConstantMedium {}
+
+/// A boolean operation combining two shapes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum CsgOperation {
+    /// Everything inside either shape.
+    Union,
+    /// Everything inside both shapes.
+    Intersection,
+    /// Everything inside the left shape but not inside the right one.
+    Difference,
+}
+
+impl CsgOperation {
+    #[inline(always)]
+    fn contains(self, in_left: bool, in_right: bool) -> bool {
+        match self {
+            CsgOperation::Union => in_left || in_right,
+            CsgOperation::Intersection => in_left && in_right,
+            CsgOperation::Difference => in_left && !in_right,
+        }
+    }
+}
+
+/// A shape made by combining two closed shapes with a [`CsgOperation`].
+///
+/// Both shapes must support [`IntersectAll`]. If either of them doesn't,
+/// the combination is never hit.
+pub struct Csg {
+    pub left: Box<Shape>,
+    pub right: Box<Shape>,
+    pub operation: CsgOperation,
+}
+
+impl Csg {
+    pub fn new(left: Shape, right: Shape, operation: CsgOperation) -> Self {
+        Self {
+            left: Box::new(left),
+            right: Box::new(right),
+            operation,
+        }
+    }
+}
+
+impl IntersectAll for Csg {
+    fn spans(&self, ray: Ray) -> Option<Vec<Span>> {
+        let left = self.left.spans(ray)?;
+        let right = self.right.spans(ray)?;
+
+        // every boundary crossing, tagged with whether it belongs to the right
+        // shape and whether it enters it.
+        let mut crossings: Vec<_> = left
+            .iter()
+            .map(|span| (false, span))
+            .chain(right.iter().map(|span| (true, span)))
+            .flat_map(|(is_right, span)| {
+                [(is_right, true, span.enter), (is_right, false, span.exit)]
+            })
+            .collect();
+        crossings.sort_by(|a, b| a.2.t.total_cmp(&b.2.t));
+
+        // sweep along the ray, keeping track of which shapes we're in
+        let mut spans = Vec::new();
+        let (mut in_left, mut in_right) = (false, false);
+        let mut enter = None;
+        for (is_right, entering, mut crossing) in crossings {
+            let was_inside = self.operation.contains(in_left, in_right);
+            if is_right {
+                in_right = entering;
+            } else {
+                in_left = entering;
+            }
+
+            let inside = self.operation.contains(in_left, in_right);
+            if inside == was_inside {
+                continue;
+            }
+
+            // the surface of a subtracted shape faces into it
+            if is_right && self.operation == CsgOperation::Difference {
+                crossing = Intersection::outward(ray, crossing.t, -crossing.normal);
+            }
+
+            if inside {
+                enter = Some(crossing);
+            } else if let Some(enter) = enter.take() {
+                spans.push(Span {
+                    enter,
+                    exit: crossing,
+                });
+            }
+        }
+
+        Some(spans)
+    }
+}
+
+impl Intersect for Csg {
+    fn intersection(&self, ray: Ray) -> Option<Intersection> {
+        self.spans(ray)?
+            .into_iter()
+            .flat_map(|span| [span.enter, span.exit])
+            // half-spaces reach infinitely far, but there's nothing to hit
+            // there
+            .find(|crossing| crossing.t >= EPSILON && crossing.t.is_finite())
+            .map(|crossing| Intersection::new(ray, crossing.t, crossing.normal))
+    }
+}
+
+impl Bounded for Csg {
+    fn aabb(&self) -> Option<Aabb> {
+        let left = self.left.aabb();
+        match self.operation {
+            CsgOperation::Union => Some(left?.union(self.right.aabb()?)),
+            CsgOperation::Intersection => match (left, self.right.aabb()) {
+                (Some(left), Some(right)) => Some(left.intersection_box(right)),
+                (left, right) => left.or(right),
+            },
+            CsgOperation::Difference => left,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Vec3;
+
+    fn sphere(center: Vec3, radius: f32) -> Shape {
+        Sphere { center, radius }.into()
+    }
+
+    #[test]
+    fn difference_bites_a_lens_out() {
+        let csg = Csg::new(
+            sphere(Vec3::ZERO, 1.0),
+            sphere(Vec3::X, 1.0),
+            CsgOperation::Difference,
+        );
+
+        // through both, leaving through the bitten surface
+        let ray = Ray::new(Vec3::new(-5.0, 0.0, 0.0), Vec3::X);
+        let spans = csg.spans(ray).unwrap();
+        assert_eq!(spans.len(), 1);
+        assert!((spans[0].enter.t - 4.0).abs() < 1e-5);
+        assert!((spans[0].exit.t - 5.0).abs() < 1e-5);
+        assert!((spans[0].exit.normal - Vec3::X).length() < 1e-5);
+
+        // the lens shared by both spheres is gone
+        let ray = Ray::new(Vec3::new(0.6, 5.0, 0.0), -Vec3::Y);
+        assert_eq!(csg.intersection(ray), None);
+
+        // while the rest of the left sphere is still there
+        let ray = Ray::new(Vec3::new(-0.5, 5.0, 0.0), -Vec3::Y);
+        let hit = csg.intersection(ray).unwrap();
+        assert!((hit.t - (5.0 - 0.75f32.sqrt())).abs() < 1e-5);
+    }
+
+    #[test]
+    fn intersection_of_box_and_sphere() {
+        let csg = Csg::new(
+            Cuboid {
+                min: Vec3::splat(-1.0),
+                max: Vec3::splat(1.0),
+            }
+            .into(),
+            sphere(Vec3::ZERO, 1.2),
+            CsgOperation::Intersection,
+        );
+
+        // through a face of the box, which is inside the sphere
+        let hit = csg
+            .intersection(Ray::new(Vec3::new(-5.0, 0.0, 0.0), Vec3::X))
+            .unwrap();
+        assert!((hit.t - 4.0).abs() < 1e-5);
+        assert!((hit.normal + Vec3::X).length() < 1e-5);
+
+        // towards a corner of the box, which is outside the sphere
+        let diagonal = Vec3::ONE.normalize();
+        let hit = csg
+            .intersection(Ray::new(-5.0 * diagonal, diagonal))
+            .unwrap();
+        assert!((hit.t - 3.8).abs() < 1e-5);
+        assert!((hit.normal + diagonal).length() < 1e-5);
+
+        // past the sphere, where the box alone would still be hit
+        let ray = Ray::new(Vec3::new(-5.0, 1.0, 0.9), Vec3::X);
+        assert_eq!(csg.intersection(ray), None);
+    }
+
+    #[test]
+    fn half_spaces_are_never_hit_at_infinity() {
+        let csg = Csg::new(
+            Plane::new(Vec3::new(0.0, -1.0, 0.0), Vec3::Y).into(),
+            sphere(Vec3::new(0.0, 2.0, 0.0), 1.0),
+            CsgOperation::Union,
+        );
+
+        // inside the half-space, moving away from the plane
+        let ray = Ray::new(Vec3::new(0.0, -2.0, 0.0), -Vec3::Y);
+        assert_eq!(csg.intersection(ray), None);
+
+        // and towards it
+        let ray = Ray::new(Vec3::new(0.0, -2.0, 0.0), Vec3::Y);
+        let hit = csg.intersection(ray).unwrap();
+        assert!((hit.t - 1.0).abs() < 1e-5);
+    }
+}
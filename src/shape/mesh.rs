@@ -0,0 +1,356 @@
+use super::{Intersect, Intersection};
+use crate::accel::{Aabb, Bounded, Bvh, BvhBuild};
+use crate::common::Ray;
+use crate::{Vec3, EPSILON};
+use glam::Vec2;
+
+mod obj;
+mod stl;
+
+pub use obj::{ObjError, ObjMesh};
+pub use stl::StlError;
+
+/// A triangle mesh, described by a vertex buffer and an index buffer.
+///
+/// A [`Bvh`] over the triangles is built once, on construction, so
+/// intersecting a mesh doesn't have to test every single triangle.
+pub struct Mesh {
+    vertices: Vec<Vec3>,
+    indices: Vec<[u32; 3]>,
+    /// The texture coordinates of every vertex, if any.
+    uvs: Option<Vec<Vec2>>,
+    /// The normal of every vertex, if any. Normalized.
+    normals: Option<Vec<Vec3>>,
+    bvh: Bvh,
+}
+
+impl Mesh {
+    /// Creates a new [`Mesh`] with the given vertices, where every entry
+    /// in `indices` is a triangle made of the vertices at those indices.
+    ///
+    /// # Panics
+    /// Panics if any index is out of bounds.
+    #[inline(always)]
+    pub fn new(vertices: Vec<Vec3>, indices: Vec<[u32; 3]>) -> Self {
+        Self::new_with(vertices, indices, BvhBuild::Median)
+    }
+
+    /// Same as [`Mesh::new`], but builds the [`Bvh`] over the triangles
+    /// with the given strategy.
+    ///
+    /// # Panics
+    /// Panics if any index is out of bounds.
+    pub fn new_with(vertices: Vec<Vec3>, indices: Vec<[u32; 3]>, build: BvhBuild) -> Self {
+        assert!(indices
+            .iter()
+            .flatten()
+            .all(|&index| (index as usize) < vertices.len()));
+
+        let bounds: Vec<Aabb> = indices
+            .iter()
+            .map(|triangle| Aabb::from_points(triangle.map(|index| vertices[index as usize])))
+            .collect();
+        let bvh = Bvh::with_build(&bounds, build);
+
+        Self {
+            vertices,
+            indices,
+            uvs: None,
+            normals: None,
+            bvh,
+        }
+    }
+
+    /// Gives every vertex of this mesh texture coordinates, which are
+    /// interpolated across triangles. Without them, the texture coordinates
+    /// of a point are its barycentric coordinates in its triangle.
+    ///
+    /// # Panics
+    /// Panics if there isn't exactly one uv per vertex.
+    pub fn with_uvs(mut self, uvs: Vec<Vec2>) -> Self {
+        assert_eq!(uvs.len(), self.vertices.len());
+        self.uvs = Some(uvs);
+        self
+    }
+
+    #[inline(always)]
+    pub fn uvs(&self) -> Option<&[Vec2]> {
+        self.uvs.as_deref()
+    }
+
+    /// Gives every vertex of this mesh a normal, which is interpolated
+    /// across triangles to shade them smoothly. Without them, triangles are
+    /// shaded flat.
+    ///
+    /// # Panics
+    /// Panics if there isn't exactly one normal per vertex, or if any of
+    /// them is not normalized.
+    pub fn with_normals(mut self, normals: Vec<Vec3>) -> Self {
+        assert_eq!(normals.len(), self.vertices.len());
+        assert!(normals.iter().all(|normal| normal.is_normalized()));
+        self.normals = Some(normals);
+        self
+    }
+
+    /// Gives every vertex of this mesh the average of the normals of the
+    /// triangles around it, weighted by their areas. See
+    /// [`Mesh::with_normals`].
+    ///
+    /// Only vertices shared between triangles are smoothed, so meshes with
+    /// duplicated vertices along their edges (like most STL files, unless
+    /// welded) still look faceted.
+    pub fn with_smooth_normals(self) -> Self {
+        let mut normals = vec![Vec3::ZERO; self.vertices.len()];
+        for (index, triangle) in self.indices.iter().enumerate() {
+            let [a, b, c] = self.triangle(index);
+            // its length is twice the area of the triangle
+            let normal = (b - a).cross(c - a);
+            for &vertex in triangle {
+                normals[vertex as usize] += normal;
+            }
+        }
+
+        let normals = normals
+            .into_iter()
+            .map(|normal| normal.try_normalize().unwrap_or(Vec3::Z))
+            .collect();
+        self.with_normals(normals)
+    }
+
+    #[inline(always)]
+    pub fn normals(&self) -> Option<&[Vec3]> {
+        self.normals.as_deref()
+    }
+
+    #[inline(always)]
+    pub fn vertices(&self) -> &[Vec3] {
+        &self.vertices
+    }
+
+    #[inline(always)]
+    pub fn indices(&self) -> &[[u32; 3]] {
+        &self.indices
+    }
+
+    /// Returns the vertices of the triangle at `index`.
+    #[inline(always)]
+    pub fn triangle(&self, index: usize) -> [Vec3; 3] {
+        self.indices[index].map(|index| self.vertices[index as usize])
+    }
+
+    /// The bounding box of this mesh.
+    #[inline(always)]
+    pub fn bounds(&self) -> Aabb {
+        self.bvh.bounds()
+    }
+
+    /// Intersects a ray with the triangle at `index`, using the
+    /// Möller-Trumbore algorithm.
+    fn triangle_intersection(&self, index: usize, ray: Ray) -> Option<Intersection> {
+        let [a, b, c] = self.triangle(index);
+        let edge_ab = b - a;
+        let edge_ac = c - a;
+
+        let p = ray.direction().cross(edge_ac);
+        let det = edge_ab.dot(p);
+        if det.abs() < f32::EPSILON {
+            // the ray is parallel to the triangle
+            return None;
+        }
+
+        let inv_det = 1.0 / det;
+        let a_to_point = ray.point() - a;
+
+        let u = a_to_point.dot(p) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+
+        let q = a_to_point.cross(edge_ab);
+        let v = ray.direction().dot(q) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let t = edge_ac.dot(q) * inv_det;
+        if t < EPSILON {
+            return None;
+        }
+
+        let normal = edge_ab.cross(edge_ac).try_normalize()?;
+        let uv = match &self.uvs {
+            Some(uvs) => {
+                let [uv_a, uv_b, uv_c] = self.indices[index].map(|index| uvs[index as usize]);
+                uv_a * (1.0 - u - v) + uv_b * u + uv_c * v
+            }
+            None => Vec2::new(u, v),
+        };
+
+        let intersection = Intersection::new(ray, t, normal).with_uv(uv);
+        Some(match &self.normals {
+            Some(normals) => {
+                let [n_a, n_b, n_c] = self.indices[index].map(|index| normals[index as usize]);
+                let shading_normal = (n_a * (1.0 - u - v) + n_b * u + n_c * v)
+                    .try_normalize()
+                    .unwrap_or(normal);
+                intersection.with_shading_normal(shading_normal)
+            }
+            None => intersection,
+        })
+    }
+}
+
+impl Intersect for Mesh {
+    fn intersection(&self, ray: Ray) -> Option<Intersection> {
+        self.bvh
+            .closest_hit(ray, |index, ray| self.triangle_intersection(index, ray))
+            .map(|(_, intersection)| intersection)
+    }
+
+    fn occluded(&self, ray: Ray, max_t: f32) -> bool {
+        self.bvh.any_hit(ray, max_t, |index, ray| {
+            self.triangle_intersection(index, ray)
+                .is_some_and(|intersection| intersection.t < max_t)
+        })
+    }
+}
+
+impl Bounded for Mesh {
+    #[inline(always)]
+    fn aabb(&self) -> Option<Aabb> {
+        Some(self.bounds())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats;
+
+    /// A cube from -1 to 1, with every triangle wound to face outwards.
+    fn cube() -> Mesh {
+        // vertex `i` has its x, y and z coordinates in bits 0, 1 and 2
+        let vertices = (0..8)
+            .map(|i| {
+                let bit = |shift: u32| if i >> shift & 1 == 1 { 1.0 } else { -1.0 };
+                Vec3::new(bit(0), bit(1), bit(2))
+            })
+            .collect();
+        let indices = vec![
+            [0, 4, 6],
+            [0, 6, 2],
+            [1, 7, 5],
+            [1, 3, 7],
+            [0, 1, 5],
+            [0, 5, 4],
+            [2, 7, 3],
+            [2, 6, 7],
+            [0, 2, 3],
+            [0, 3, 1],
+            [4, 7, 6],
+            [4, 5, 7],
+        ];
+
+        Mesh::new(vertices, indices)
+    }
+
+    /// A bumpy `size` by `size` grid on the XZ plane, from 0 to `size`, made
+    /// of two triangles per cell.
+    fn terrain(size: u32) -> Mesh {
+        let vertices = (0..=size)
+            .flat_map(|z| (0..=size).map(move |x| (x, z)))
+            .map(|(x, z)| {
+                let (x, z) = (x as f32, z as f32);
+                Vec3::new(x, (x * 0.7).sin() * (z * 0.3).cos(), z)
+            })
+            .collect();
+        let vertex = |x: u32, z: u32| z * (size + 1) + x;
+        let indices = (0..size)
+            .flat_map(|z| (0..size).map(move |x| (x, z)))
+            .flat_map(|(x, z)| {
+                [
+                    [vertex(x, z), vertex(x, z + 1), vertex(x + 1, z)],
+                    [vertex(x + 1, z), vertex(x, z + 1), vertex(x + 1, z + 1)],
+                ]
+            })
+            .collect();
+
+        Mesh::new(vertices, indices)
+    }
+
+    #[test]
+    fn cube_is_hit_from_every_side() {
+        let cube = cube();
+        assert_eq!(cube.indices().len(), 12);
+
+        let axes = [Vec3::X, Vec3::Y, Vec3::Z];
+        for axis in axes.into_iter().flat_map(|axis| [axis, -axis]) {
+            // a point on the face, away from the diagonal of its triangles
+            let offset = axis.any_orthonormal_vector();
+            let offset = 0.3 * offset + 0.2 * axis.cross(offset);
+
+            let outside = Ray::new(5.0 * axis + offset, -axis);
+            let hit = cube.intersection(outside).unwrap();
+            assert!((hit.t - 4.0).abs() < 1e-5);
+            assert!((hit.point - (axis + offset)).length() < 1e-5);
+            assert!((hit.normal - axis).length() < 1e-5);
+            assert!(hit.front_face);
+
+            assert!(!cube.occluded(outside, 3.9));
+            assert!(cube.occluded(outside, 4.1));
+
+            let inside = Ray::new(offset, axis);
+            let hit = cube.intersection(inside).unwrap();
+            assert!((hit.t - 1.0).abs() < 1e-5);
+            assert!((hit.normal + axis).length() < 1e-5);
+            assert!(!hit.front_face);
+
+            let beside = Ray::new(5.0 * axis + 1.5 * offset.normalize(), -axis);
+            assert!(cube.intersection(beside).is_none());
+            assert!(!cube.occluded(beside, f32::INFINITY));
+        }
+    }
+
+    #[test]
+    fn large_meshes_are_not_traversed_linearly() {
+        let size = 48;
+        let terrain = terrain(size);
+        let triangles = terrain.indices().len();
+        assert_eq!(triangles, 2 * 48 * 48);
+
+        for index in 0..64 {
+            let x = 0.5 + (index as f32 * 7.31) % (size as f32 - 4.0);
+            let z = 0.5 + (index as f32 * 3.77) % (size as f32 - 4.0);
+            let ray = Ray::new(
+                Vec3::new(x, 10.0, z),
+                Vec3::new(0.1, -1.0, 0.05).normalize(),
+            );
+
+            let mut tested = 0;
+            let (hit, counts) = stats::counted(|| {
+                terrain
+                    .bvh
+                    .closest_hit(ray, |index, ray| {
+                        tested += 1;
+                        terrain.triangle_intersection(index, ray)
+                    })
+                    .map(|(_, intersection)| intersection)
+            });
+
+            // the same hit as testing every single triangle
+            let expected = (0..triangles)
+                .filter_map(|index| terrain.triangle_intersection(index, ray))
+                .min_by(|a, b| a.t.total_cmp(&b.t))
+                .unwrap();
+            let hit = hit.unwrap();
+            assert_eq!(hit.t, expected.t);
+
+            assert!(
+                counts.nodes_visited < 128,
+                "visited {} nodes",
+                counts.nodes_visited
+            );
+            assert!(tested < 64, "tested {tested} triangles");
+        }
+    }
+}
@@ -0,0 +1,346 @@
+use super::Mesh;
+use crate::Vec3;
+use glam::Vec2;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::io::BufRead;
+
+/// An error that happened while loading a Wavefront OBJ file.
+#[derive(Debug)]
+pub enum ObjError {
+    /// Reading from the source failed.
+    Io(std::io::Error),
+    /// A line of the file is malformed. Lines start at 1.
+    Parse { line: usize, message: String },
+}
+
+impl Display for ObjError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ObjError::Io(err) => write!(f, "failed to read obj: {err}"),
+            ObjError::Parse { line, message } => write!(f, "line {line}: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ObjError::Io(err) => Some(err),
+            ObjError::Parse { .. } => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ObjError {
+    fn from(value: std::io::Error) -> Self {
+        ObjError::Io(value)
+    }
+}
+
+/// A mesh loaded from a Wavefront OBJ file, together with its per-vertex
+/// attributes.
+pub struct ObjMesh {
+    pub mesh: Mesh,
+    /// The normal of each vertex in the mesh, if the file had any. Vertices
+    /// of faces that didn't reference a normal get the face's normal. These
+    /// are also set on the mesh itself.
+    pub normals: Option<Vec<Vec3>>,
+    /// The texture coordinates of each vertex in the mesh, if the file had
+    /// any. Vertices that didn't reference one get `(0, 0)`. These are also
+    /// set on the mesh itself.
+    pub uvs: Option<Vec<Vec2>>,
+}
+
+/// A vertex of a face, as the indices of its position, texture coordinates
+/// and normal.
+type FaceVertex = (usize, Option<usize>, Option<usize>);
+
+/// Identifies the normal of an output vertex: either one from the file or
+/// the computed normal of a face.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum NormalKey {
+    Index(usize),
+    Face(usize),
+}
+
+fn parse_error(line: usize, message: impl Into<String>) -> ObjError {
+    ObjError::Parse {
+        line,
+        message: message.into(),
+    }
+}
+
+fn parse_floats<const N: usize>(
+    line: usize,
+    tokens: &mut std::str::SplitWhitespace,
+    required: usize,
+) -> Result<[f32; N], ObjError> {
+    let mut values = [0.0; N];
+    for (i, value) in values.iter_mut().enumerate() {
+        match tokens.next() {
+            Some(token) => {
+                *value = token
+                    .parse()
+                    .map_err(|_| parse_error(line, format!("invalid number `{token}`")))?;
+            }
+            None if i < required => {
+                return Err(parse_error(line, format!("expected {required} numbers")))
+            }
+            None => break,
+        }
+    }
+
+    Ok(values)
+}
+
+/// Resolves an OBJ index, which is either 1-based or, when negative,
+/// relative to the end of the list read so far.
+fn resolve_index(line: usize, token: &str, len: usize) -> Result<usize, ObjError> {
+    let index: isize = token
+        .parse()
+        .map_err(|_| parse_error(line, format!("invalid index `{token}`")))?;
+
+    let resolved = match index {
+        0 => None,
+        1.. => Some(index as usize - 1),
+        _ => len.checked_sub(index.unsigned_abs()),
+    };
+
+    resolved
+        .filter(|&index| index < len)
+        .ok_or_else(|| parse_error(line, format!("index `{token}` out of bounds")))
+}
+
+impl Mesh {
+    /// Loads a mesh from a Wavefront OBJ file.
+    ///
+    /// Only `v`, `vt`, `vn` and `f` records are taken into account, other
+    /// records are ignored. Faces with more than three vertices are
+    /// triangulated as fans.
+    pub fn from_obj(reader: impl BufRead) -> Result<ObjMesh, ObjError> {
+        let mut positions = Vec::new();
+        let mut uvs = Vec::new();
+        let mut normals = Vec::new();
+        let mut faces: Vec<Vec<FaceVertex>> = Vec::new();
+
+        for (line_index, line) in reader.lines().enumerate() {
+            let line_number = line_index + 1;
+            let line = line?;
+            let line = line.split('#').next().unwrap_or_default();
+
+            let mut tokens = line.split_whitespace();
+            match tokens.next() {
+                Some("v") => {
+                    let [x, y, z] = parse_floats(line_number, &mut tokens, 3)?;
+                    positions.push(Vec3::new(x, y, z));
+                }
+                Some("vt") => {
+                    let [u, v] = parse_floats(line_number, &mut tokens, 1)?;
+                    uvs.push(Vec2::new(u, v));
+                }
+                Some("vn") => {
+                    let [x, y, z] = parse_floats(line_number, &mut tokens, 3)?;
+                    normals.push(Vec3::new(x, y, z).try_normalize().unwrap_or(Vec3::Z));
+                }
+                Some("f") => {
+                    let mut face = Vec::new();
+                    for token in tokens {
+                        let mut parts = token.split('/');
+                        let position = resolve_index(
+                            line_number,
+                            parts.next().unwrap_or_default(),
+                            positions.len(),
+                        )?;
+                        let uv = match parts.next() {
+                            Some("") | None => None,
+                            Some(part) => Some(resolve_index(line_number, part, uvs.len())?),
+                        };
+                        let normal = match parts.next() {
+                            Some("") | None => None,
+                            Some(part) => Some(resolve_index(line_number, part, normals.len())?),
+                        };
+
+                        face.push((position, uv, normal));
+                    }
+
+                    if face.len() < 3 {
+                        return Err(parse_error(line_number, "face with less than 3 vertices"));
+                    }
+
+                    faces.push(face);
+                }
+                _ => (),
+            }
+        }
+
+        let has_uvs = faces.iter().flatten().any(|(_, uv, _)| uv.is_some());
+        let has_normals = faces
+            .iter()
+            .flatten()
+            .any(|(_, _, normal)| normal.is_some());
+
+        // OBJ indexes positions, uvs and normals separately, so every unique
+        // combination of them becomes a vertex in the mesh.
+        let mut vertex_map: HashMap<(usize, Option<usize>, Option<NormalKey>), u32> =
+            HashMap::new();
+        let mut out_positions = Vec::new();
+        let mut out_uvs = Vec::new();
+        let mut out_normals = Vec::new();
+        let mut indices = Vec::new();
+
+        for (face_index, face) in faces.iter().enumerate() {
+            // newell's method, which works for any planar-ish polygon
+            let face_normal = face
+                .iter()
+                .zip(face.iter().cycle().skip(1))
+                .fold(Vec3::ZERO, |normal, (a, b)| {
+                    let a = positions[a.0];
+                    let b = positions[b.0];
+                    normal
+                        + Vec3::new(
+                            (a.y - b.y) * (a.z + b.z),
+                            (a.z - b.z) * (a.x + b.x),
+                            (a.x - b.x) * (a.y + b.y),
+                        )
+                })
+                .try_normalize()
+                .unwrap_or(Vec3::Z);
+
+            let face_indices: Vec<u32> = face
+                .iter()
+                .map(|&(position, uv, normal)| {
+                    let normal_key = has_normals.then_some(match normal {
+                        Some(normal) => NormalKey::Index(normal),
+                        None => NormalKey::Face(face_index),
+                    });
+
+                    *vertex_map
+                        .entry((position, uv, normal_key))
+                        .or_insert_with(|| {
+                            out_positions.push(positions[position]);
+                            out_uvs.push(uv.map(|uv| uvs[uv]).unwrap_or(Vec2::ZERO));
+                            out_normals.push(match normal_key {
+                                Some(NormalKey::Index(normal)) => normals[normal],
+                                _ => face_normal,
+                            });
+
+                            out_positions.len() as u32 - 1
+                        })
+                })
+                .collect();
+
+            for i in 1..face_indices.len() - 1 {
+                indices.push([face_indices[0], face_indices[i], face_indices[i + 1]]);
+            }
+        }
+
+        let uvs = has_uvs.then_some(out_uvs);
+        let normals = has_normals.then_some(out_normals);
+        let mut mesh = Mesh::new(out_positions, indices);
+        if let Some(uvs) = &uvs {
+            mesh = mesh.with_uvs(uvs.clone());
+        }
+        if let Some(normals) = &normals {
+            mesh = mesh.with_normals(normals.clone());
+        }
+
+        Ok(ObjMesh { mesh, normals, uvs })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{common::Ray, shape::Intersect};
+
+    /// A unit quad facing +Z, followed by two triangles one unit above it
+    /// which mix every form of face vertex and negative indices.
+    const FIXTURE: &str = "\
+# a quad
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+vn 0 0 1
+f 1/1/1 2/2/1 3/3/1 4/4/1
+
+o triangles
+v 0 0 1
+v 1 0 1
+v 0 1 1 # trailing comment
+f -3 -2//1 -1/4
+f 5/1 6 7
+";
+
+    fn parse_error_line(source: &str) -> usize {
+        match Mesh::from_obj(source.as_bytes()) {
+            Err(ObjError::Parse { line, .. }) => line,
+            Err(err) => panic!("unexpected error: {err}"),
+            Ok(_) => panic!("parsed {source:?}"),
+        }
+    }
+
+    #[test]
+    fn parses_quads_and_mixed_face_vertices() {
+        let obj = Mesh::from_obj(FIXTURE.as_bytes()).unwrap();
+        let mesh = &obj.mesh;
+
+        // the quad is split in two, and every unique combination of
+        // indices is its own vertex
+        assert_eq!(mesh.indices().len(), 4);
+        assert_eq!(mesh.vertices().len(), 10);
+        assert_eq!(obj.uvs.as_ref().unwrap().len(), 10);
+        assert_eq!(obj.normals.as_ref().unwrap().len(), 10);
+
+        // negative indices are relative to the vertices read so far
+        let expected = [
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(1.0, 0.0, 1.0),
+            Vec3::new(0.0, 1.0, 1.0),
+        ];
+        assert_eq!(mesh.triangle(2), expected);
+        assert_eq!(mesh.triangle(3), expected);
+
+        // vertices without a normal get the face's, the rest the file's
+        assert!(obj
+            .normals
+            .unwrap()
+            .iter()
+            .all(|&normal| (normal - Vec3::Z).length() < 1e-6));
+
+        let [a, b, c] = mesh.indices()[2].map(|index| obj.uvs.as_ref().unwrap()[index as usize]);
+        assert_eq!([a, b, c], [Vec2::ZERO, Vec2::ZERO, Vec2::new(0.0, 1.0)]);
+
+        // uvs are interpolated across the quad
+        let hit = mesh
+            .intersection(Ray::new(Vec3::new(0.25, 0.75, -1.0), Vec3::Z))
+            .unwrap();
+        assert!((hit.t - 1.0).abs() < 1e-6);
+        assert!((hit.uv - Vec2::new(0.25, 0.75)).length() < 1e-6);
+    }
+
+    #[test]
+    fn errors_have_line_numbers() {
+        assert_eq!(parse_error_line("v 0 0 0\nv 1 0\n"), 2);
+        assert_eq!(parse_error_line("# comment\n\nvn 0 x 1\n"), 3);
+        assert_eq!(parse_error_line("v 0 0 0\nv 1 0 0\nf 1 2 3\n"), 3);
+        assert_eq!(parse_error_line("v 0 0 0\nv 1 0 0\nf 1 2 0\n"), 3);
+        assert_eq!(parse_error_line("v 0 0 0\nv 1 0 0\nf 1 2 -3\n"), 3);
+        assert_eq!(parse_error_line("v 0 0 0\nv 1 0 0\n\nf 1 2\n"), 4);
+        assert_eq!(
+            parse_error_line("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1/1 2 3\n"),
+            4
+        );
+        assert_eq!(parse_error_line("v 0 0 0\nf 1/a 1 1\n"), 2);
+
+        let err = Mesh::from_obj("v 0 0 0\nf 1 2 3\n".as_bytes())
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "line 2: index `2` out of bounds");
+    }
+}
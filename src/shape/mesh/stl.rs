@@ -0,0 +1,281 @@
+use super::Mesh;
+use crate::Vec3;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::io::Read;
+
+/// The size of the header of a binary STL file, including the triangle count.
+const BINARY_HEADER_SIZE: usize = 84;
+/// The size of a triangle record in a binary STL file.
+const BINARY_TRIANGLE_SIZE: usize = 50;
+
+/// An error that happened while loading a STL file.
+#[derive(Debug)]
+pub enum StlError {
+    /// Reading from the source failed.
+    Io(std::io::Error),
+    /// A line of an ASCII file is malformed. Lines start at 1.
+    Parse { line: usize, message: String },
+    /// The file is neither valid ASCII STL nor a binary STL of the size its
+    /// header announces.
+    Truncated,
+}
+
+impl Display for StlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StlError::Io(err) => write!(f, "failed to read stl: {err}"),
+            StlError::Parse { line, message } => write!(f, "line {line}: {message}"),
+            StlError::Truncated => write!(f, "binary stl is truncated"),
+        }
+    }
+}
+
+impl std::error::Error for StlError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StlError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for StlError {
+    fn from(value: std::io::Error) -> Self {
+        StlError::Io(value)
+    }
+}
+
+fn parse_error(line: usize, message: impl Into<String>) -> StlError {
+    StlError::Parse {
+        line,
+        message: message.into(),
+    }
+}
+
+fn read_binary(data: &[u8]) -> Vec<[Vec3; 3]> {
+    let read_vec3 = |bytes: &[u8]| {
+        let [x, y, z] = [0, 4, 8]
+            .map(|offset| f32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()));
+        Vec3::new(x, y, z)
+    };
+
+    // every record is a normal, three vertices and an attribute byte count.
+    // the normal is ignored, it's recomputed from the vertices anyway.
+    data[BINARY_HEADER_SIZE..]
+        .chunks_exact(BINARY_TRIANGLE_SIZE)
+        .map(|record| [12, 24, 36].map(|offset| read_vec3(&record[offset..])))
+        .collect()
+}
+
+fn read_ascii(text: &str) -> Result<Vec<[Vec3; 3]>, StlError> {
+    let mut triangles = Vec::new();
+    let mut triangle = Vec::with_capacity(3);
+
+    for (line_index, line) in text.lines().enumerate() {
+        let line_number = line_index + 1;
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("vertex") => {
+                let mut coords = [0.0; 3];
+                for coord in &mut coords {
+                    let token = tokens
+                        .next()
+                        .ok_or_else(|| parse_error(line_number, "expected 3 numbers"))?;
+                    *coord = token.parse().map_err(|_| {
+                        parse_error(line_number, format!("invalid number `{token}`"))
+                    })?;
+                }
+
+                triangle.push(Vec3::from_array(coords));
+            }
+            Some("endloop") => {
+                let Ok(vertices) = <[Vec3; 3]>::try_from(triangle.as_slice()) else {
+                    return Err(parse_error(line_number, "facet without exactly 3 vertices"));
+                };
+
+                triangles.push(vertices);
+                triangle.clear();
+            }
+            _ => (),
+        }
+    }
+
+    Ok(triangles)
+}
+
+/// Merges vertices closer than `epsilon` to each other, returning the
+/// unique vertices and the index of every input vertex into them.
+/// `epsilon` must be positive and finite.
+fn weld(points: impl Iterator<Item = Vec3>, epsilon: f32) -> (Vec<Vec3>, Vec<u32>) {
+    // cells are clamped far enough from the limits of i32 that their
+    // neighbours don't overflow. points past them share the outermost cells,
+    // which only makes searching them slower
+    const MAX_CELL: f32 = (1 << 30) as f32;
+    let cell_of = |point: Vec3| {
+        (point / epsilon)
+            .floor()
+            .clamp(Vec3::splat(-MAX_CELL), Vec3::splat(MAX_CELL))
+            .as_ivec3()
+    };
+
+    let mut vertices: Vec<Vec3> = Vec::new();
+    let mut cells: HashMap<glam::IVec3, Vec<u32>> = HashMap::new();
+    let mut indices = Vec::new();
+    for point in points {
+        let cell = cell_of(point);
+
+        // a vertex within epsilon is at most one cell away
+        let mut existing = None;
+        'search: for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    let neighbour = cell + glam::IVec3::new(x, y, z);
+                    for &index in cells.get(&neighbour).into_iter().flatten() {
+                        if vertices[index as usize].distance(point) <= epsilon {
+                            existing = Some(index);
+                            break 'search;
+                        }
+                    }
+                }
+            }
+        }
+
+        let index = existing.unwrap_or_else(|| {
+            vertices.push(point);
+            let index = vertices.len() as u32 - 1;
+            cells.entry(cell).or_default().push(index);
+            index
+        });
+        indices.push(index);
+    }
+
+    (vertices, indices)
+}
+
+impl Mesh {
+    /// Loads a mesh from a STL file, either ASCII or binary.
+    ///
+    /// If `weld_epsilon` is given, vertices closer than it to each other are
+    /// merged into a single one. Otherwise, or if it isn't positive and
+    /// finite, triangles don't share vertices. Triangles with zero area are
+    /// dropped.
+    pub fn from_stl(mut reader: impl Read, weld_epsilon: Option<f32>) -> Result<Mesh, StlError> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+
+        // binary files may also start with "solid", so the size announced by
+        // the header is the most reliable way to tell them apart.
+        let binary_size = data.get(80..BINARY_HEADER_SIZE).map(|count| {
+            let count = u32::from_le_bytes(count.try_into().unwrap()) as usize;
+            BINARY_HEADER_SIZE + count * BINARY_TRIANGLE_SIZE
+        });
+
+        let triangles = if binary_size == Some(data.len()) {
+            read_binary(&data)
+        } else if data.trim_ascii_start().starts_with(b"solid") {
+            let text = String::from_utf8_lossy(&data);
+            read_ascii(&text)?
+        } else {
+            return Err(StlError::Truncated);
+        };
+
+        let points = triangles.iter().flatten().copied();
+        let (vertices, vertex_indices) = match weld_epsilon {
+            Some(epsilon) if epsilon > 0.0 && epsilon.is_finite() => weld(points, epsilon),
+            _ => (points.collect(), (0..triangles.len() as u32 * 3).collect()),
+        };
+
+        let indices = vertex_indices
+            .chunks_exact(3)
+            .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+            .filter(|triangle| {
+                let [a, b, c] = triangle.map(|index| vertices[index as usize]);
+                (b - a).cross(c - a).length_squared() > 0.0
+            })
+            .collect();
+
+        Ok(Mesh::new(vertices, indices))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A binary STL file with the given triangles, with zero normals.
+    fn binary(triangles: &[[Vec3; 3]]) -> Vec<u8> {
+        let mut data = vec![0; 80];
+        data.extend((triangles.len() as u32).to_le_bytes());
+        for triangle in triangles {
+            data.extend([0u8; 12]);
+            for vertex in triangle {
+                data.extend(vertex.to_array().into_iter().flat_map(f32::to_le_bytes));
+            }
+            data.extend([0u8; 2]);
+        }
+
+        data
+    }
+
+    /// A unit square, split into two triangles.
+    fn square(offset: Vec3) -> [[Vec3; 3]; 2] {
+        let [a, b, c, d] =
+            [Vec3::ZERO, Vec3::X, Vec3::new(1.0, 1.0, 0.0), Vec3::Y].map(|vertex| vertex + offset);
+        [[a, b, c], [a, c, d]]
+    }
+
+    #[test]
+    fn binary_is_welded() {
+        let data = binary(&square(Vec3::ZERO));
+        assert_eq!(data.len(), BINARY_HEADER_SIZE + 2 * BINARY_TRIANGLE_SIZE);
+
+        let mesh = Mesh::from_stl(data.as_slice(), None).unwrap();
+        assert_eq!(mesh.vertices().len(), 6);
+        assert_eq!(mesh.indices().len(), 2);
+
+        let mesh = Mesh::from_stl(data.as_slice(), Some(1e-4)).unwrap();
+        assert_eq!(mesh.vertices().len(), 4);
+        assert_eq!(mesh.indices(), &[[0, 1, 2], [0, 2, 3]]);
+    }
+
+    #[test]
+    fn binary_starting_with_solid() {
+        let mut data = binary(&square(Vec3::ZERO));
+        data[..5].copy_from_slice(b"solid");
+
+        let mesh = Mesh::from_stl(data.as_slice(), Some(1e-4)).unwrap();
+        assert_eq!(mesh.indices().len(), 2);
+    }
+
+    #[test]
+    fn truncated_binary() {
+        let data = binary(&square(Vec3::ZERO));
+        let result = Mesh::from_stl(&data[..data.len() - 1], None);
+        assert!(matches!(result, Err(StlError::Truncated)));
+    }
+
+    #[test]
+    fn degenerate_weld_epsilons() {
+        let data = binary(&square(Vec3::ZERO));
+        for epsilon in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+            let mesh = Mesh::from_stl(data.as_slice(), Some(epsilon)).unwrap();
+            assert_eq!(mesh.vertices().len(), 6);
+        }
+
+        // far enough that the cells of the vertices don't fit in an i32
+        let data = binary(&square(Vec3::splat(1e30)));
+        Mesh::from_stl(data.as_slice(), Some(1e-6)).unwrap();
+        let data = binary(&square(Vec3::splat(-1e30)));
+        Mesh::from_stl(data.as_slice(), Some(1e-6)).unwrap();
+    }
+
+    #[test]
+    fn ascii_parse_errors() {
+        let text = "solid square\n  facet normal 0 0 1\n    outer loop\n      vertex 0 0 0\n      vertex 1 x 0\n";
+        match Mesh::from_stl(text.as_bytes(), None) {
+            Err(StlError::Parse { line, .. }) => assert_eq!(line, 5),
+            other => panic!("expected a parse error, got {:?}", other.err()),
+        }
+    }
+}
@@ -0,0 +1,276 @@
+//! Small polynomial root solvers used by shapes whose intersection can't be
+//! expressed with a quadratic, like the [torus](crate::shape::Torus).
+//!
+//! Everything here works in `f64`: the coefficients of higher degree
+//! polynomials lose precision fast, and `f32` is not enough to avoid holes
+//! in surfaces hit by grazing rays.
+
+use std::ops::Deref;
+
+/// Relative tolerance used to decide that a slightly negative discriminant
+/// is actually zero (i.e. a double root lost to rounding).
+const DISCRIMINANT_TOLERANCE: f64 = 1e-9;
+
+/// The real roots of a polynomial of degree at most 4.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Roots {
+    roots: [f64; 4],
+    len: usize,
+}
+
+impl Roots {
+    #[inline(always)]
+    fn push(&mut self, root: f64) {
+        self.roots[self.len] = root;
+        self.len += 1;
+    }
+}
+
+impl Deref for Roots {
+    type Target = [f64];
+
+    #[inline(always)]
+    fn deref(&self) -> &Self::Target {
+        &self.roots[..self.len]
+    }
+}
+
+/// Solves `a * x² + b * x + c = 0` for real `x`. `a` must not be zero.
+///
+/// Discriminants that are negative only because of rounding are treated as
+/// zero, so tangent configurations still report their double root.
+pub fn solve_quadratic(a: f64, b: f64, c: f64) -> Roots {
+    let mut roots = Roots::default();
+
+    let b_halved = b / (2.0 * a);
+    let c = c / a;
+
+    let delta = b_halved * b_halved - c;
+    let scale = (b_halved * b_halved).max(c.abs()).max(f64::MIN_POSITIVE);
+    if delta < -DISCRIMINANT_TOLERANCE * scale {
+        return roots;
+    }
+
+    let delta_sqrt = delta.max(0.0).sqrt();
+    if delta_sqrt == 0.0 {
+        roots.push(-b_halved);
+    } else {
+        // avoid cancellation by computing the larger root first
+        let q = -b_halved - b_halved.signum() * delta_sqrt;
+        roots.push(q);
+        if q != 0.0 {
+            roots.push(c / q);
+        } else {
+            roots.push(-q);
+        }
+    }
+
+    roots
+}
+
+/// Solves `x³ + a * x² + b * x + c = 0` for real `x`.
+pub fn solve_cubic(a: f64, b: f64, c: f64) -> Roots {
+    let mut roots = Roots::default();
+
+    // depress the cubic with x = y - a/3, leaving y³ + p * y + q = 0
+    let a_third = a / 3.0;
+    let p = b - a * a_third;
+    let q = 2.0 * a_third * a_third * a_third - a_third * b + c;
+
+    let p_third = p / 3.0;
+    let q_halved = q / 2.0;
+    let delta = q_halved * q_halved + p_third * p_third * p_third;
+
+    if delta > 0.0 {
+        // one real root (cardano)
+        let delta_sqrt = delta.sqrt();
+        let u = (-q_halved + delta_sqrt).cbrt();
+        let v = (-q_halved - delta_sqrt).cbrt();
+        roots.push(u + v - a_third);
+    } else if p_third == 0.0 {
+        // triple root
+        roots.push(-a_third);
+    } else {
+        // three real roots (trigonometric method)
+        let m = 2.0 * (-p_third).sqrt();
+        let cos_arg = (3.0 * q / (p * m)).clamp(-1.0, 1.0);
+        let theta = cos_arg.acos() / 3.0;
+        for k in 0..3 {
+            let angle = theta - 2.0 * std::f64::consts::PI * k as f64 / 3.0;
+            roots.push(m * angle.cos() - a_third);
+        }
+    }
+
+    for root in &mut roots.roots[..roots.len] {
+        *root = polish(*root, |x| {
+            let f = ((x + a) * x + b) * x + c;
+            let df = (3.0 * x + 2.0 * a) * x + b;
+            (f, df)
+        });
+    }
+
+    roots
+}
+
+/// Solves `x⁴ + a * x³ + b * x² + c * x + d = 0` for real `x` using
+/// Ferrari's method, polishing every root found with a few Newton steps
+/// on the original polynomial.
+pub fn solve_quartic(a: f64, b: f64, c: f64, d: f64) -> Roots {
+    let mut depressed_roots = Roots::default();
+
+    // depress the quartic with x = y - a/4, leaving y⁴ + p * y² + q * y + r = 0
+    let a_quarter = a / 4.0;
+    let a_quarter_sq = a_quarter * a_quarter;
+    let p = b - 6.0 * a_quarter_sq;
+    let q = c - 2.0 * b * a_quarter + 8.0 * a_quarter_sq * a_quarter;
+    let r = d - c * a_quarter + b * a_quarter_sq - 3.0 * a_quarter_sq * a_quarter_sq;
+
+    let scale = p.abs().max(r.abs().sqrt()).max(f64::MIN_POSITIVE);
+    if q.abs() <= DISCRIMINANT_TOLERANCE * scale * scale.sqrt() {
+        // biquadratic: z² + p * z + r = 0 where z = y²
+        for z in solve_quadratic(1.0, p, r).iter() {
+            if *z >= -DISCRIMINANT_TOLERANCE * scale {
+                let y = z.max(0.0).sqrt();
+                depressed_roots.push(y);
+                if y != 0.0 {
+                    depressed_roots.push(-y);
+                }
+            }
+        }
+    } else {
+        // the largest root of the resolvent cubic is always positive when
+        // q != 0, since the cubic is negative at zero.
+        let resolvent = solve_cubic(p, p * p / 4.0 - r, -q * q / 8.0);
+        let m = resolvent
+            .iter()
+            .copied()
+            .fold(f64::NEG_INFINITY, f64::max)
+            .max(f64::MIN_POSITIVE);
+
+        let s = (2.0 * m).sqrt();
+        let base = p / 2.0 + m;
+        let offset = q / (2.0 * s);
+
+        for y in solve_quadratic(1.0, -s, base + offset).iter() {
+            depressed_roots.push(*y);
+        }
+        for y in solve_quadratic(1.0, s, base - offset).iter() {
+            depressed_roots.push(*y);
+        }
+    }
+
+    let mut roots = Roots::default();
+    for y in depressed_roots.iter() {
+        roots.push(polish(y - a_quarter, |x| {
+            let f = (((x + a) * x + b) * x + c) * x + d;
+            let df = ((4.0 * x + 3.0 * a) * x + 2.0 * b) * x + c;
+            (f, df)
+        }));
+    }
+
+    roots
+}
+
+/// Refines a root with a few Newton-Raphson iterations. `f` returns the
+/// value of the polynomial and of its derivative at the given point.
+#[inline(always)]
+fn polish(mut x: f64, f: impl Fn(f64) -> (f64, f64)) -> f64 {
+    let (mut value, mut derivative) = f(x);
+    for _ in 0..4 {
+        if derivative == 0.0 {
+            break;
+        }
+
+        // stop as soon as a step doesn't improve things, which happens
+        // around (near) double roots where the derivative vanishes.
+        let next = x - value / derivative;
+        let (next_value, next_derivative) = f(next);
+        if !next.is_finite() || next_value.abs() >= value.abs() {
+            break;
+        }
+
+        x = next;
+        value = next_value;
+        derivative = next_derivative;
+    }
+
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The coefficients `[a, b, c, d]` of the monic quartic with the given
+    /// roots.
+    fn quartic_with_roots([r1, r2, r3, r4]: [f64; 4]) -> [f64; 4] {
+        [
+            -(r1 + r2 + r3 + r4),
+            r1 * r2 + r1 * r3 + r1 * r4 + r2 * r3 + r2 * r4 + r3 * r4,
+            -(r1 * r2 * r3 + r1 * r2 * r4 + r1 * r3 * r4 + r2 * r3 * r4),
+            r1 * r2 * r3 * r4,
+        ]
+    }
+
+    /// Checks that every root in `expected` was found, and nothing else,
+    /// ignoring repeated roots.
+    fn assert_roots(roots: &[f64], expected: &[f64]) {
+        for root in roots {
+            assert!(
+                expected.iter().any(|r| (r - root).abs() < 1e-6),
+                "unexpected root {root}, expected {expected:?}"
+            );
+        }
+
+        for r in expected {
+            assert!(
+                roots.iter().any(|root| (r - root).abs() < 1e-6),
+                "missing root {r}, found {roots:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn quadratic() {
+        assert_roots(&solve_quadratic(1.0, -3.0, 2.0), &[1.0, 2.0]);
+        assert_roots(&solve_quadratic(2.0, 4.0, 2.0), &[-1.0]);
+        assert_roots(&solve_quadratic(1.0, 0.0, 1.0), &[]);
+        // catastrophic cancellation, if done naively
+        assert_roots(&solve_quadratic(1.0, -1e8, 1.0), &[1e8, 1e-8]);
+    }
+
+    #[test]
+    fn cubic() {
+        // (x - 1)(x - 2)(x - 3)
+        assert_roots(&solve_cubic(-6.0, 11.0, -6.0), &[1.0, 2.0, 3.0]);
+        // (x - 2)(x² + 1)
+        assert_roots(&solve_cubic(-2.0, 1.0, -2.0), &[2.0]);
+        // (x + 1)³
+        assert_roots(&solve_cubic(3.0, 3.0, 1.0), &[-1.0]);
+    }
+
+    #[test]
+    fn quartic_known_roots() {
+        for roots in [
+            [1.0, 2.0, 3.0, 4.0],
+            [-3.5, -0.25, 0.5, 10.0],
+            [-1.0, 1.0, -2.0, 2.0],
+            [1.0, 1.0, 3.0, 5.0],
+            [2.0, 2.0, -2.0, -2.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ] {
+            let [a, b, c, d] = quartic_with_roots(roots);
+            assert_roots(&solve_quartic(a, b, c, d), &roots);
+        }
+    }
+
+    #[test]
+    fn quartic_complex_roots() {
+        // (x - 2)(x - 3)(x² + 1)
+        assert_roots(&solve_quartic(-5.0, 7.0, -5.0, 6.0), &[2.0, 3.0]);
+        // (x² + 1)(x² + 4)
+        assert_roots(&solve_quartic(0.0, 5.0, 0.0, 4.0), &[]);
+        // (x - 1)²(x² + 1)
+        assert_roots(&solve_quartic(-2.0, 2.0, -2.0, 1.0), &[1.0]);
+    }
+}
@@ -0,0 +1,157 @@
+//! Statistics about the work done while rendering.
+//!
+//! Counters are accumulated per thread, so counting is cheap and never
+//! races, and the counts of every piece of work are then added up.
+
+use std::{cell::Cell, fmt, ops::AddAssign, time::Duration};
+
+/// Statistics about a render, returned by
+/// [`Renderer::render`](crate::render::Renderer::render).
+///
+/// Counts only include the samples of the image: the rays of auxiliary
+/// outputs aren't counted, but the time spent on them is.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RenderStats {
+    /// The amount of samples added to the image.
+    pub samples: u64,
+    /// The amount of pixels of the image that were rendered.
+    pub pixels: u64,
+    /// The amount of tiles of the image that were rendered. Less than the
+    /// amount of tiles of the image if the render was cancelled.
+    pub tiles: u64,
+    /// The amount of camera rays traced, one for every sample. With
+    /// [filters](crate::filter::Filter) wider than a pixel, the samples
+    /// around each tile are taken by the neighbouring tiles too, so this is
+    /// a bit more than the amount of samples of the image.
+    pub primary_rays: u64,
+    /// The amount of rays traced after paths bounced off a surface.
+    pub secondary_rays: u64,
+    /// The amount of shadow rays traced towards lights.
+    pub shadow_rays: u64,
+    /// The amount of intersection tests of rays against the shapes of
+    /// objects and lights.
+    pub intersection_tests: u64,
+    /// The amount of nodes of the acceleration structure visited, which are
+    /// the cells for [grids](crate::accel::Grid).
+    pub nodes_visited: u64,
+    /// The time spent taking samples.
+    pub sampling_time: Duration,
+    /// The time spent computing [auxiliary outputs](crate::render::Aovs).
+    pub aov_time: Duration,
+    /// The time spent turning the samples into the final image.
+    pub resolve_time: Duration,
+}
+
+impl RenderStats {
+    /// The average amount of samples added to every rendered pixel. Zero if
+    /// no pixels were rendered.
+    #[inline(always)]
+    pub fn samples_per_pixel(&self) -> f64 {
+        if self.pixels == 0 {
+            return 0.0;
+        }
+
+        self.samples as f64 / self.pixels as f64
+    }
+
+    /// The amount of rays traced, of every kind.
+    #[inline(always)]
+    pub fn rays(&self) -> u64 {
+        self.primary_rays + self.secondary_rays + self.shadow_rays
+    }
+
+    /// The average amount of rays traced along every path, not counting
+    /// shadow rays. Zero if no paths were traced.
+    #[inline(always)]
+    pub fn average_path_length(&self) -> f64 {
+        if self.primary_rays == 0 {
+            return 0.0;
+        }
+
+        (self.primary_rays + self.secondary_rays) as f64 / self.primary_rays as f64
+    }
+
+    /// The time spent on every phase of the render.
+    #[inline(always)]
+    pub fn total_time(&self) -> Duration {
+        self.sampling_time + self.aov_time + self.resolve_time
+    }
+}
+
+impl AddAssign for RenderStats {
+    fn add_assign(&mut self, other: Self) {
+        self.samples += other.samples;
+        self.pixels += other.pixels;
+        self.tiles += other.tiles;
+        self.primary_rays += other.primary_rays;
+        self.secondary_rays += other.secondary_rays;
+        self.shadow_rays += other.shadow_rays;
+        self.intersection_tests += other.intersection_tests;
+        self.nodes_visited += other.nodes_visited;
+        self.sampling_time += other.sampling_time;
+        self.aov_time += other.aov_time;
+        self.resolve_time += other.resolve_time;
+    }
+}
+
+impl fmt::Display for RenderStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "samples: {} ({:.2} per pixel)",
+            self.samples,
+            self.samples_per_pixel()
+        )?;
+        writeln!(f, "pixels: {} ({} tiles)", self.pixels, self.tiles)?;
+        writeln!(
+            f,
+            "rays: {} ({} primary, {} secondary, {} shadow)",
+            self.rays(),
+            self.primary_rays,
+            self.secondary_rays,
+            self.shadow_rays
+        )?;
+        writeln!(f, "average path length: {:.2}", self.average_path_length())?;
+        writeln!(f, "intersection tests: {}", self.intersection_tests)?;
+        writeln!(f, "nodes visited: {}", self.nodes_visited)?;
+        write!(
+            f,
+            "time: {:?} (sampling {:?}, aovs {:?}, resolve {:?})",
+            self.total_time(),
+            self.sampling_time,
+            self.aov_time,
+            self.resolve_time
+        )
+    }
+}
+
+thread_local! {
+    /// The counts of the work done by the current thread.
+    static COUNTERS: Cell<RenderStats> = Cell::new(RenderStats::default());
+}
+
+/// Adds to the counters of the current thread.
+#[inline(always)]
+pub(crate) fn count(f: impl FnOnce(&mut RenderStats)) {
+    COUNTERS.with(|counters| {
+        let mut stats = counters.get();
+        f(&mut stats);
+        counters.set(stats);
+    });
+}
+
+/// Runs `f` and returns what the current thread counted while it ran,
+/// which must be all of its work, so `f` can't hand any of it to other
+/// threads.
+pub(crate) fn counted<T>(f: impl FnOnce() -> T) -> (T, RenderStats) {
+    let outer = COUNTERS.with(|counters| counters.replace(RenderStats::default()));
+    let result = f();
+    let inner = COUNTERS.with(|counters| counters.get());
+
+    // the counts still belong to any outer call
+    let mut total = outer;
+    total += inner;
+    COUNTERS.with(|counters| counters.set(total));
+
+    (result, inner)
+}
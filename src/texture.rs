@@ -0,0 +1,146 @@
+use crate::{common::Color, tonemap::srgb_to_linear, Vec3, EPSILON};
+use glam::Vec2;
+use picture::prelude::{ImgBuf, ImgView, ImgViewMut, RGB, RGB8};
+
+/// A texture. Gives the color of a surface at each of its points.
+///
+/// Like [materials](crate::material::Material), textures are shared between
+/// the threads rendering a scene, so they must be [`Send`] and [`Sync`].
+pub trait Texture: Send + Sync {
+    /// The color of this texture at `point`, on a surface with the given
+    /// normal and texture coordinates.
+    fn sample(&self, point: Vec3, normal: Vec3, uv: Vec2) -> Color;
+}
+
+/// A texture with the same color everywhere.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SolidColor(pub Color);
+
+impl Texture for SolidColor {
+    #[inline(always)]
+    fn sample(&self, _: Vec3, _: Vec3, _: Vec2) -> Color {
+        self.0
+    }
+}
+
+/// A 3D checkerboard in world space, made of cubes of side `scale`
+/// alternating between colors `a` and `b`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Checker {
+    pub a: Color,
+    pub b: Color,
+    pub scale: f32,
+}
+
+impl Texture for Checker {
+    fn sample(&self, point: Vec3, normal: Vec3, _: Vec2) -> Color {
+        // sample the cube right below the surface, so surfaces lying on the
+        // boundary between cubes don't randomly pick either side
+        let cell = ((point - normal * EPSILON) / self.scale).floor();
+        let parity = (cell.x + cell.y + cell.z).rem_euclid(2.0);
+
+        if parity < 1.0 {
+            self.a
+        } else {
+            self.b
+        }
+    }
+}
+
+/// How an [`ImageTexture`] handles texture coordinates outside of the
+/// `[0, 1]` range.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Wrap {
+    /// The image repeats itself.
+    #[default]
+    Repeat,
+    /// The pixels at the border of the image extend forever.
+    Clamp,
+}
+
+/// A texture made from an image, mapped onto surfaces by their texture
+/// coordinates: `(0, 0)` is the bottom left corner of the image and
+/// `(1, 1)` is the top right one.
+pub struct ImageTexture {
+    image: ImgBuf<RGB<f32>, Vec<RGB<f32>>>,
+    wrap: Wrap,
+}
+
+impl ImageTexture {
+    /// Creates a new [`ImageTexture`] from an image with linear channels.
+    ///
+    /// # Panics
+    /// Panics if the image is empty or if any of its channels is not in the
+    /// \[0..1] range.
+    pub fn new(image: ImgBuf<RGB<f32>, Vec<RGB<f32>>>, wrap: Wrap) -> Self {
+        let (width, height) = image.dimensions();
+        assert!(width > 0 && height > 0);
+        assert!(image.pixels().all(|pixel| [pixel.r, pixel.g, pixel.b]
+            .iter()
+            .all(|c| (0.0..=1.0).contains(c))));
+
+        Self { image, wrap }
+    }
+
+    /// Creates a new [`ImageTexture`] from an image with 8-bit sRGB
+    /// channels, like most PNGs, converting them to linear.
+    ///
+    /// # Panics
+    /// Panics if the image is empty.
+    pub fn from_srgb8(image: &impl ImgView<Pixel = RGB8>, wrap: Wrap) -> Self {
+        let (width, height) = image.dimensions();
+        let mut linear = ImgBuf::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let pixel = image.pixel((x, y)).unwrap();
+                let [r, g, b] =
+                    [pixel.r, pixel.g, pixel.b].map(|c| srgb_to_linear(c as f32 / 255.0));
+                *linear.pixel_mut((x, y)).unwrap() = RGB::new(r, g, b);
+            }
+        }
+
+        Self::new(linear, wrap)
+    }
+
+    #[inline(always)]
+    pub fn image(&self) -> &ImgBuf<RGB<f32>, Vec<RGB<f32>>> {
+        &self.image
+    }
+
+    #[inline(always)]
+    pub fn wrap(&self) -> Wrap {
+        self.wrap
+    }
+
+    #[inline(always)]
+    fn pixel(&self, x: i64, y: i64) -> Vec3 {
+        let (width, height) = self.image.dimensions();
+        let (x, y) = match self.wrap {
+            Wrap::Repeat => (x.rem_euclid(width as i64), y.rem_euclid(height as i64)),
+            Wrap::Clamp => (x.clamp(0, width as i64 - 1), y.clamp(0, height as i64 - 1)),
+        };
+
+        let pixel = self.image.pixel((x as u32, y as u32)).unwrap();
+        Vec3::new(pixel.r, pixel.g, pixel.b)
+    }
+}
+
+impl Texture for ImageTexture {
+    /// Bilinearly interpolates between the pixels closest to `uv`.
+    fn sample(&self, _: Vec3, _: Vec3, uv: Vec2) -> Color {
+        let (width, height) = self.image.dimensions();
+
+        // position in pixels, relative to the pixel centers
+        let x = uv.x * width as f32 - 0.5;
+        let y = (1.0 - uv.y) * height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let top = self.pixel(x0, y0).lerp(self.pixel(x0 + 1, y0), tx);
+        let bottom = self.pixel(x0, y0 + 1).lerp(self.pixel(x0 + 1, y0 + 1), tx);
+        let color = top.lerp(bottom, ty).clamp(Vec3::ZERO, Vec3::ONE);
+
+        Color::new(color.x, color.y, color.z)
+    }
+}
@@ -0,0 +1,196 @@
+//! Operators mapping the unbounded light values of a render to the `[0, 1]`
+//! range of a displayable image.
+
+use crate::Vec3;
+
+/// How the unbounded light values of a render are scaled before being tone
+/// mapped.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Exposure {
+    /// Divides every value by `max_value`, so that values of `max_value`
+    /// become one.
+    Manual { max_value: f32 },
+    /// Scales the image so that its log-average luminance becomes `key`,
+    /// like Reinhard's key value: `0.18` is middle gray, higher keys give
+    /// brighter images.
+    ///
+    /// Completely black pixels are ignored, and so is the brightest `clip`
+    /// fraction of the pixels, so that a few fireflies don't darken the
+    /// whole image. A `clip` of zero keeps every pixel.
+    Auto { key: f32, clip: f32 },
+}
+
+impl Exposure {
+    /// The factor the values of an image are multiplied by, given the
+    /// luminance of each of its pixels (which only matter for
+    /// [`Exposure::Auto`]).
+    pub fn scale(&self, luminances: impl Iterator<Item = f32>) -> f32 {
+        match *self {
+            Exposure::Manual { max_value } => 1.0 / max_value,
+            Exposure::Auto { key, clip } => {
+                let mut luminances: Vec<_> = luminances.filter(|&l| l > 0.0).collect();
+                luminances.sort_unstable_by(f32::total_cmp);
+
+                let kept = ((1.0 - clip.clamp(0.0, 1.0)) * luminances.len() as f32).ceil();
+                let kept = &luminances[..kept as usize];
+                if kept.is_empty() {
+                    return 1.0;
+                }
+
+                // computed in f64, since the sum of the logarithms of a big
+                // image loses a lot of precision in f32
+                let log_sum: f64 = kept.iter().map(|&l| (l as f64).ln()).sum();
+                let log_average = (log_sum / kept.len() as f64).exp();
+
+                key / log_average as f32
+            }
+        }
+    }
+}
+
+/// A tone mapping operator. Applied per channel, after the value has been
+/// scaled by [`Renderer::exposure`](crate::render::Renderer::exposure).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneMap {
+    /// Clamps values above one, hard-clipping highlights.
+    Clamp,
+    /// `x / (1 + x)`. Never reaches white.
+    Reinhard,
+    /// Reinhard extended so that `white_point` (and anything brighter) maps
+    /// to white.
+    ReinhardExtended { white_point: f32 },
+    /// A fit of the ACES filmic curve, with a soft shoulder and a slightly
+    /// contrasty toe.
+    AcesFilmic,
+}
+
+impl ToneMap {
+    /// Maps a value to the `[0, 1]` range.
+    #[inline(always)]
+    pub fn apply(&self, value: Vec3) -> Vec3 {
+        let value = value.max(Vec3::ZERO);
+        let mapped = match *self {
+            ToneMap::Clamp => value,
+            ToneMap::Reinhard => value / (1.0 + value),
+            ToneMap::ReinhardExtended { white_point } => {
+                let white_sq = white_point * white_point;
+                value * (1.0 + value / white_sq) / (1.0 + value)
+            }
+            ToneMap::AcesFilmic => {
+                // krzysztof narkowicz's fit
+                let (a, b, c, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+                (value * (a * value + b)) / (value * (c * value + d) + e)
+            }
+        };
+
+        mapped.clamp(Vec3::ZERO, Vec3::ONE)
+    }
+}
+
+/// How the tone mapped values of a render are encoded in the buffer it's
+/// written to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Encoding {
+    /// Values are linear, proportional to the light of each pixel. Best for
+    /// processing the image further, e.g. blending or denoising it.
+    #[default]
+    Linear,
+    /// Values are encoded with the sRGB transfer function, see
+    /// [`linear_to_srgb`], ready to be displayed or quantized.
+    Srgb,
+}
+
+impl Encoding {
+    /// Encodes a linear value in the `[0, 1]` range.
+    #[inline(always)]
+    pub fn apply(&self, value: Vec3) -> Vec3 {
+        match self {
+            Encoding::Linear => value,
+            Encoding::Srgb => Vec3::from_array(value.to_array().map(linear_to_srgb)),
+        }
+    }
+}
+
+/// Applies the sRGB transfer function (the piecewise curve, not the `2.2`
+/// gamma approximation) to a linear value in the `[0, 1]` range.
+#[inline(always)]
+pub fn linear_to_srgb(value: f32) -> f32 {
+    let value = value.clamp(0.0, 1.0);
+    if value <= 0.0031308 {
+        12.92 * value
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Undoes the sRGB transfer function, turning a value in the `[0, 1]` range
+/// back to linear. The inverse of [`linear_to_srgb`].
+#[inline(always)]
+pub fn srgb_to_linear(value: f32) -> f32 {
+    let value = value.clamp(0.0, 1.0);
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Color;
+
+    #[test]
+    fn srgb_matches_known_pairs() {
+        // linear, 8-bit srgb
+        let pairs = [
+            (0.0, 0),
+            (0.001, 3),
+            (0.0031308, 10),
+            (0.1, 89),
+            (0.21586, 128),
+            (0.5, 188),
+            (1.0, 255),
+        ];
+
+        for (linear, srgb) in pairs {
+            let encoded = linear_to_srgb(linear) * 255.0;
+            assert_eq!(encoded.round() as u8, srgb, "{linear} encoded as {encoded}");
+
+            // within half of a step of 8-bit srgb, around 0.5
+            let decoded = srgb_to_linear(srgb as f32 / 255.0);
+            assert!(
+                (decoded - linear).abs() < 5e-3,
+                "{srgb} decoded as {decoded}"
+            );
+        }
+    }
+
+    #[test]
+    fn colors_encode_as_srgb8() {
+        let color = Color::new(0.0, 0.5, 1.0);
+        assert_eq!(color.to_srgb8(), [0, 188, 255]);
+    }
+
+    #[test]
+    fn srgb_round_trips() {
+        for step in 0..=1000 {
+            let value = step as f32 / 1000.0;
+            let round_trip = srgb_to_linear(linear_to_srgb(value));
+            assert!(
+                (round_trip - value).abs() < 1e-5,
+                "{value} became {round_trip}"
+            );
+        }
+    }
+
+    #[test]
+    fn encodings_apply_per_channel() {
+        let value = Vec3::new(0.0, 0.5, 1.0);
+        assert_eq!(Encoding::Linear.apply(value), value);
+
+        let srgb = Encoding::Srgb.apply(value);
+        let expected = Vec3::new(0.0, linear_to_srgb(0.5), linear_to_srgb(1.0));
+        assert_eq!(srgb, expected);
+    }
+}